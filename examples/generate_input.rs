@@ -0,0 +1,92 @@
+//! Generates a synthetic input file for benchmarking.
+//!
+//! USAGE: cargo run --release --example generate_input -- ROWS > input.csv
+
+use std::{
+    env,
+    io::{self, BufWriter, Write},
+};
+
+/// Number of clients the transactions are spread over
+const CLIENTS: u64 = 65_535;
+
+/// Number of rows after which a dispute is resolved
+const DISPUTE_ROWS: u64 = 10_000;
+
+/// Deterministic pseudo-random number derived from `x` (SplitMix64)
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn client_of(tx: u64) -> u64 {
+    (mix(tx) >> 8) % CLIENTS + 1
+}
+
+fn is_resolve(tx: u64) -> bool {
+    tx > DISPUTE_ROWS && is_dispute(tx - DISPUTE_ROWS)
+}
+
+fn is_deposit(tx: u64) -> bool {
+    mix(tx) % 100 >= 40 && !is_resolve(tx)
+}
+
+fn is_dispute(tx: u64) -> bool {
+    mix(tx) % 100 == 0 && !is_resolve(tx)
+}
+
+/// Older deposit, possibly long spilled to disk, disputed at row `tx`
+fn disputed(tx: u64) -> u64 {
+    let mut referenced = mix(mix(mix(tx))) % tx + 1;
+    while referenced > 1 && !is_deposit(referenced) {
+        referenced -= 1;
+    }
+    referenced
+}
+
+fn main() -> io::Result<()> {
+    let rows: u64 = env::args()
+        .nth(1)
+        .and_then(|a| a.parse().ok())
+        .expect("USAGE: generate_input ROWS");
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(out, "type,client,tx,amount")?;
+
+    for tx in 1..=rows {
+        let r = mix(tx);
+        let amount = mix(r);
+        if is_resolve(tx) {
+            // every dispute is resolved, so that the open ones do not pile up
+            let referenced = disputed(tx - DISPUTE_ROWS);
+            writeln!(out, "resolve,{},{},", client_of(referenced), referenced)?;
+            continue;
+        }
+        match r % 100 {
+            0 => {
+                let referenced = disputed(tx);
+                writeln!(out, "dispute,{},{},", client_of(referenced), referenced)?
+            }
+            1..=39 => writeln!(
+                out,
+                "withdrawal,{},{},{}.{:04}",
+                client_of(tx),
+                tx,
+                amount % 50 + 1,
+                (amount >> 16) % 10_000
+            )?,
+            _ => writeln!(
+                out,
+                "deposit,{},{},{}.{:04}",
+                client_of(tx),
+                tx,
+                amount % 100 + 1,
+                (amount >> 16) % 10_000
+            )?,
+        }
+    }
+
+    out.flush()
+}
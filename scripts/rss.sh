@@ -0,0 +1,40 @@
+#!/usr/bin/env bash
+# Measures peak resident memory of the engine on synthetic inputs of given sizes.
+#
+# USAGE: scripts/rss.sh [CONFIG_PATH] ROWS...
+#
+# Linux only - the peak is read from /proc/PID/status.
+
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+cargo build --release --quiet --bin toy-payments-engine --example generate_input
+
+args=()
+if [[ "${1:-}" == *.toml ]]; then
+    args=(--config "$1")
+    shift
+fi
+
+input=$(mktemp)
+trap 'rm -f "$input"' EXIT
+
+printf "%12s %12s %10s\n" rows "peak RSS kB" seconds
+for rows in "$@"; do
+    target/release/examples/generate_input "$rows" > "$input"
+
+    start=$(date +%s)
+    target/release/toy-payments-engine "${args[@]}" "$input" > /dev/null 2>&1 &
+    pid=$!
+    peak=0
+    while kill -0 "$pid" 2> /dev/null; do
+        hwm=$(awk '/VmHWM/ { print $2 }' "/proc/$pid/status" 2> /dev/null || true)
+        if [[ -n "$hwm" ]]; then
+            peak=$hwm
+        fi
+        sleep 0.2
+    done
+    wait "$pid"
+
+    printf "%12s %12s %10s\n" "$rows" "$peak" "$(( $(date +%s) - start ))"
+done
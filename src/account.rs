@@ -0,0 +1,84 @@
+//! Account names
+
+use crate::errors::DeserializationError;
+use std::{fmt, str::FromStr};
+
+/// Maximum length of the account name
+const MAX_LEN: usize = 16;
+
+/// Name of one of the client's accounts (e.g. `savings`). Transactions given without account go to `Account::MAIN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Account([u8; MAX_LEN]);
+
+impl Account {
+    /// Account of transactions given without account, named `main`
+    pub const MAIN: Account = Account([0; MAX_LEN]);
+
+    pub fn is_main(&self) -> bool {
+        *self == Account::MAIN
+    }
+
+    /// Returns the raw bytes of the name
+    pub fn to_bytes(self) -> [u8; MAX_LEN] {
+        self.0
+    }
+
+    /// Creates the account from raw bytes returned by `to_bytes`
+    pub fn from_bytes(bytes: [u8; MAX_LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl FromStr for Account {
+    type Err = DeserializationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        if name == "main" {
+            return Ok(Account::MAIN);
+        }
+
+        let valid = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-';
+        if name.is_empty() || name.len() > MAX_LEN || !name.bytes().all(valid) {
+            return Err(DeserializationError::InvalidAccount(s.to_owned()));
+        }
+
+        let mut bytes = [0; MAX_LEN];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_main() {
+            return f.write_str("main");
+        }
+
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(MAX_LEN);
+        // only ASCII characters can be parsed, so this is always valid UTF-8
+        f.write_str(std::str::from_utf8(&self.0[..len]).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Account;
+
+    #[test]
+    fn parses_and_normalizes_name() {
+        let a: Account = "Savings".parse().unwrap();
+
+        assert_eq!(a.to_string(), "savings");
+        assert!(!a.is_main());
+        assert_eq!("MAIN".parse::<Account>().unwrap(), Account::MAIN);
+        assert_eq!(Account::MAIN.to_string(), "main");
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert!("".parse::<Account>().is_err());
+        assert!("with space".parse::<Account>().is_err());
+        assert!("a_very_long_account_name".parse::<Account>().is_err());
+    }
+}
@@ -0,0 +1,425 @@
+//! Tamper-evident audit log - every entry holds a hash chaining it to the previous one.
+//! The head of the chain is kept apart from the log, so that removed trailing entries, or a rewritten chain, can be detected.
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    dto::{AuditRecord, InputRecord},
+    errors::AuditError,
+    events::Event,
+    history::Entry,
+    transaction::{ClientId, Timestamp, Transaction, TxId},
+};
+use color_eyre::{
+    eyre::{eyre, Context},
+    Result,
+};
+use getset::Getters;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    str::FromStr,
+};
+
+/// Previous hash of the first entry
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Accepted transaction or administrative action recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct AuditEntry {
+    /// Type of the transaction, or name of the action
+    #[get = "pub"]
+    action: String,
+
+    #[get = "pub"]
+    client: Option<ClientId>,
+
+    #[get = "pub"]
+    tx: Option<TxId>,
+
+    #[get = "pub"]
+    timestamp: Option<String>,
+
+    #[get = "pub"]
+    amount: Option<String>,
+
+    /// Other details, as space-separated `key=value` pairs
+    #[get = "pub"]
+    detail: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        action: String,
+        client: Option<ClientId>,
+        tx: Option<TxId>,
+        timestamp: Option<String>,
+        amount: Option<String>,
+        detail: Option<String>,
+    ) -> Self {
+        Self {
+            action,
+            client,
+            tx,
+            timestamp,
+            amount,
+            detail,
+        }
+    }
+
+    /// Returns the entry of the accepted `transaction` read from `record`
+    pub fn transaction(record: &InputRecord, transaction: &Transaction) -> Self {
+        let detail: Vec<_> = [
+            ("currency", record.currency()),
+            ("to_currency", record.to_currency()),
+            ("account", record.account()),
+            ("to_account", record.to_account()),
+        ]
+        .iter()
+        .filter_map(|(key, value)| value.as_ref().map(|v| format!("{}={}", key, v)))
+        .collect();
+
+        Self::new(
+            record.r#type().clone(),
+            Some(*record.client()),
+            Some(*record.tx()),
+            transaction.timestamp().map(|t| t.to_rfc3339()),
+            record.amount().map(|a| format!("{:.4}", a)),
+            (!detail.is_empty()).then(|| detail.join(" ")),
+        )
+    }
+
+    /// Returns the entry of an action taken by the engine itself (e.g. dispute expiry), described by the `event`
+    pub fn event(event: &Event) -> Self {
+        Self::new(
+            event.kind().name().to_owned(),
+            Some(*event.client()),
+            Some(*event.tx()),
+            event.timestamp().map(|t| t.to_rfc3339()),
+            None,
+            None,
+        )
+    }
+
+    /// Returns the entry of an administrative `action` of the operator (e.g. loading the configuration)
+    pub fn admin(action: &str, detail: String) -> Self {
+        Self::new(action.to_owned(), None, None, None, None, Some(detail))
+    }
+
+    /// Returns the entry of the opening balance of the client's `account` in `currency`, loaded from the file at `path`
+    pub fn opening_balance(
+        path: &str,
+        client: ClientId,
+        account: Account,
+        currency: Currency,
+        (available, held, locked): (f64, f64, bool),
+    ) -> Self {
+        Self::new(
+            "balance_opened".to_owned(),
+            Some(client),
+            None,
+            None,
+            Some(format!("{:.4}", available + held)),
+            Some(format!(
+                "file={} account={} currency={} available={:.4} held={:.4} locked={}",
+                path, account, currency, available, held, locked
+            )),
+        )
+    }
+
+    /// Returns the entry of the open dispute of the client's `deposit` `tx`, loaded from the file at `path`
+    pub fn opening_dispute(
+        path: &str,
+        client: ClientId,
+        tx: TxId,
+        deposit: &Entry,
+        disputed_at: Option<Timestamp>,
+    ) -> Self {
+        Self::new(
+            "dispute_opened".to_owned(),
+            Some(client),
+            Some(tx),
+            disputed_at.map(|t| t.to_rfc3339()),
+            Some(format!("{:.4}", deposit.amount)),
+            Some(format!(
+                "file={} account={} currency={}",
+                path, deposit.account, deposit.currency
+            )),
+        )
+    }
+}
+
+/// Latest entry of the audit log, written as `SEQ:HASH`
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Head {
+    /// Sequence number of the entry, 0 for an empty log
+    #[get = "pub"]
+    seq: u64,
+
+    #[get = "pub"]
+    hash: String,
+}
+
+impl Head {
+    /// Returns the path of the file the head of the audit log at `path` is kept in
+    pub fn path(path: &str) -> String {
+        format!("{}.head", path)
+    }
+
+    /// Reads the head of the audit log at `path`, if it was recorded
+    pub fn read(path: &str) -> Result<Option<Self>> {
+        let head_path = Self::path(path);
+        match fs::read_to_string(&head_path) {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .map(Some)
+                .wrap_err_with(|| format!("Invalid audit log head `{}`", head_path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                Err(e).wrap_err_with(|| format!("Can not read audit log head `{}`", head_path))
+            }
+        }
+    }
+}
+
+impl FromStr for Head {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (seq, hash) = s
+            .split_once(':')
+            .ok_or_else(|| eyre!("`{}` is not valid audit log head. Expected `SEQ:HASH`", s))?;
+        let seq = seq.parse().wrap_err_with(|| {
+            format!("`{}` is not valid audit log head. Expected `SEQ:HASH`", s)
+        })?;
+
+        Ok(Self {
+            seq,
+            hash: hash.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Head {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.seq, self.hash)
+    }
+}
+
+/// Appends entries to the audit log
+#[derive(Debug)]
+pub struct AuditLog<W: Write> {
+    wtr: csv::Writer<W>,
+
+    /// Sequence number of the latest entry
+    seq: u64,
+
+    /// Hash of the latest entry
+    last_hash: String,
+
+    /// File the head is written to when flushed, if any
+    head_path: Option<String>,
+}
+
+impl AuditLog<File> {
+    /// Opens the audit log at `path`, continuing the chain of the entries it already has.
+    /// Fails if the chain is broken, or does not reach the head recorded by the previous run.
+    pub fn open(path: &str) -> Result<Self> {
+        let heads: Vec<_> = Head::read(path)?.into_iter().collect();
+        let head = match File::open(path) {
+            Ok(file) => verify(file, &heads),
+            Err(e) if e.kind() == ErrorKind::NotFound => verify(io::empty(), &heads),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Can not open audit log `{}`", path)),
+        }
+        .wrap_err_with(|| format!("Audit log `{}` can not be continued", path))?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("Can not open audit log `{}`", path))?;
+        let wtr = csv::WriterBuilder::new()
+            .has_headers(file.metadata()?.len() == 0)
+            .from_writer(file);
+
+        Ok(Self {
+            wtr,
+            seq: head.seq,
+            last_hash: head.hash,
+            head_path: Some(Head::path(path)),
+        })
+    }
+}
+
+impl<W: Write> AuditLog<W> {
+    /// Returns new empty audit log written to `w`
+    #[cfg(test)]
+    pub fn new(w: W) -> Self {
+        Self {
+            wtr: csv::Writer::from_writer(w),
+            seq: 0,
+            last_hash: GENESIS.to_owned(),
+            head_path: None,
+        }
+    }
+
+    /// Appends the `entry`, chained to the latest one
+    pub fn append(&mut self, entry: &AuditEntry) -> Result<()> {
+        let seq = self.seq + 1;
+        let hash = digest(seq, entry, &self.last_hash);
+        self.wtr.serialize(AuditRecord::new(
+            seq,
+            entry,
+            self.last_hash.clone(),
+            hash.clone(),
+        ))?;
+        self.seq = seq;
+        self.last_hash = hash;
+
+        Ok(())
+    }
+
+    /// Flushes the entries, then records the head
+    pub fn flush(&mut self) -> Result<()> {
+        self.wtr.flush()?;
+        if let Some(path) = &self.head_path {
+            let head = Head {
+                seq: self.seq,
+                hash: self.last_hash.clone(),
+            };
+            fs::write(path, format!("{}\n", head))
+                .wrap_err_with(|| format!("Can not write audit log head `{}`", path))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.wtr.into_inner().unwrap()
+    }
+}
+
+/// Recomputes the chain of the audit log read from `rdr`, checking that it passes through all the `heads` recorded earlier.
+/// Returns the head of the log, or the error pointing at the first tampered entry.
+pub fn verify<R: Read>(rdr: R, heads: &[Head]) -> Result<Head, AuditError> {
+    let mut prev_hash = GENESIS.to_owned();
+    let mut seq = 0;
+    for result in csv::Reader::from_reader(rdr).deserialize() {
+        seq += 1;
+        let record: AuditRecord = result.map_err(|e| AuditError::Malformed(seq, e.to_string()))?;
+        if *record.seq() != seq || *record.prev_hash() != prev_hash {
+            return Err(AuditError::BrokenChain(seq));
+        }
+        if digest(seq, &record.entry(), &prev_hash) != *record.hash() {
+            return Err(AuditError::Tampered(seq));
+        }
+        if heads
+            .iter()
+            .any(|h| h.seq == seq && h.hash != *record.hash())
+        {
+            return Err(AuditError::HeadMismatch(seq));
+        }
+        prev_hash = record.hash().clone();
+    }
+    if let Some(head) = heads.iter().find(|h| h.seq > seq) {
+        return Err(AuditError::Truncated(head.seq, seq));
+    }
+
+    Ok(Head {
+        seq,
+        hash: prev_hash,
+    })
+}
+
+/// Returns hex-encoded SHA-256 of the `seq`-th `entry` following the entry with `prev_hash`
+fn digest(seq: u64, entry: &AuditEntry, prev_hash: &str) -> String {
+    let number = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_default();
+    let fields = [
+        seq.to_string(),
+        entry.action.clone(),
+        number(entry.client),
+        number(entry.tx),
+        entry.timestamp.clone().unwrap_or_default(),
+        entry.amount.clone().unwrap_or_default(),
+        entry.detail.clone().unwrap_or_default(),
+        prev_hash.to_owned(),
+    ];
+
+    let mut hasher = Sha256::new();
+    for field in &fields {
+        // length prefix keeps the field boundaries unambiguous
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, AuditEntry, AuditLog, Head};
+    use crate::errors::AuditError;
+
+    fn log(actions: &[&str]) -> String {
+        let mut log = AuditLog::new(Vec::new());
+        for action in actions {
+            log.append(&AuditEntry::admin(action, "x".to_owned()))
+                .unwrap();
+        }
+        String::from_utf8(log.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn intact_log_is_verified() {
+        let log = log(&["a", "b", "c"]);
+
+        assert_eq!(verify(log.as_bytes(), &[]).map(|h| h.seq), Ok(3));
+    }
+
+    #[test]
+    fn modified_entry_is_pinpointed() {
+        let log = log(&["a", "b", "c"]).replacen(",b,", ",B,", 1);
+
+        assert_eq!(verify(log.as_bytes(), &[]), Err(AuditError::Tampered(2)));
+    }
+
+    #[test]
+    fn removed_entry_is_pinpointed() {
+        let log = log(&["a", "b", "c"]);
+        let lines: Vec<_> = log.lines().collect();
+        let log = [lines[0], lines[1], lines[3]].join("\n");
+
+        assert_eq!(verify(log.as_bytes(), &[]), Err(AuditError::BrokenChain(2)));
+    }
+
+    #[test]
+    fn removed_trailing_entries_are_detected_by_the_head() {
+        let log = log(&["a", "b", "c"]);
+        let head: Head = verify(log.as_bytes(), &[])
+            .unwrap()
+            .to_string()
+            .parse()
+            .unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        let truncated = lines[..3].join("\n");
+
+        assert_eq!(
+            verify(truncated.as_bytes(), &[head]),
+            Err(AuditError::Truncated(3, 2))
+        );
+    }
+
+    #[test]
+    fn rewritten_chain_is_detected_by_the_head() {
+        let head = verify(log(&["a", "b", "c"]).as_bytes(), &[]).unwrap();
+        let rewritten = log(&["a", "b", "C", "d"]);
+
+        assert_eq!(
+            verify(rewritten.as_bytes(), &[head]),
+            Err(AuditError::HeadMismatch(3))
+        );
+    }
+}
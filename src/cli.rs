@@ -0,0 +1,720 @@
+//! Command line arguments
+
+use crate::{
+    audit::Head,
+    query::AsOf,
+    snapshot::Tolerance,
+    statement::StatementFilter,
+    table::Format,
+    transaction::{parse_timestamp, ClientId},
+};
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Result,
+};
+use getset::Getters;
+use std::str::FromStr;
+use tracing::Level;
+
+/// Parsed command line arguments
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Args {
+    /// What to do with the input
+    #[get = "pub"]
+    command: Command,
+
+    /// Path to the input CSV file
+    #[get = "pub"]
+    input: String,
+
+    /// Path to the file emitted domain events should be written to
+    #[get = "pub"]
+    events: Option<String>,
+
+    /// Path to the TOML configuration file
+    #[get = "pub"]
+    config: Option<String>,
+
+    /// Path to the file the FX P&L report should be written to
+    #[get = "pub"]
+    fx_report: Option<String>,
+
+    /// Path to the file the ledger postings should be written to
+    #[get = "pub"]
+    ledger: Option<String>,
+
+    /// Path to the audit log accepted transactions should be appended to
+    #[get = "pub"]
+    audit: Option<String>,
+
+    /// Whether the summary of the run should be written to the standard error
+    #[get = "pub"]
+    summary: bool,
+
+    /// Path to the file the metrics of the run should be written to, in the Prometheus text format
+    #[get = "pub"]
+    metrics: Option<String>,
+
+    /// Path to the balances the clients start with
+    #[get = "pub"]
+    opening_balances: Option<String>,
+
+    /// Path to the disputes open before this run
+    #[get = "pub"]
+    open_disputes: Option<String>,
+
+    /// Whether the progress of reading the input should be shown on the standard error
+    #[get = "pub"]
+    progress: bool,
+
+    /// How the errors are reported on the standard error
+    #[get = "pub"]
+    error_format: ErrorFormat,
+
+    /// Most verbose level of the logs written to the standard error, none if not logging
+    #[get = "pub"]
+    log_level: Option<Level>,
+
+    /// How the logs are written
+    #[get = "pub"]
+    log_format: LogFormat,
+}
+
+/// How the errors are reported on the standard error
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Line of text per error, with its code and name
+    #[default]
+    Text,
+    /// JSON envelope per line
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => bail!(
+                "`{}` is not a known error format. Expected `text` or `json`",
+                s
+            ),
+        }
+    }
+}
+
+/// How the logs are written to the standard error
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable line per event, with the fields of its spans
+    #[default]
+    Text,
+    /// JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!(
+                "`{}` is not a known log format. Expected `text` or `json`",
+                s
+            ),
+        }
+    }
+}
+
+/// Names of the commands, given as the first argument
+const COMMANDS: [&str; 6] = [
+    "statement",
+    "verify-audit",
+    "reconcile",
+    "diff",
+    "simulate",
+    "query",
+];
+
+/// Command selected by the first argument
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Processes the input and outputs the balances of all the clients. Used if no command is given.
+    Process,
+
+    /// Outputs the statement of a single client
+    Statement(StatementArgs),
+
+    /// Verifies the hash chain of the audit log given as the input
+    VerifyAudit(VerifyAuditArgs),
+
+    /// Compares the balances of all the clients with the expected ones
+    Reconcile(ReconcileArgs),
+
+    /// Compares two balance outputs, the later one given as the input
+    Diff(DiffArgs),
+
+    /// Previews the effect of a batch of transactions applied after the input
+    Simulate(SimulateArgs),
+
+    /// Outputs the state of the clients at a given point of the input
+    Query(QueryArgs),
+}
+
+/// Arguments of the `statement` command
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct StatementArgs {
+    #[get = "pub"]
+    client: ClientId,
+
+    #[get = "pub"]
+    filter: StatementFilter,
+
+    #[get = "pub"]
+    format: Format,
+}
+
+/// Arguments of the `verify-audit` command
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct VerifyAuditArgs {
+    /// Head of the log recorded earlier, kept apart from it
+    #[get = "pub"]
+    head: Option<Head>,
+}
+
+/// Arguments of the `reconcile` command
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct ReconcileArgs {
+    /// Path to the expected balances CSV file
+    #[get = "pub"]
+    expected: String,
+
+    #[get = "pub"]
+    tolerance: Tolerance,
+}
+
+/// Arguments of the `diff` command
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct DiffArgs {
+    /// Path to the earlier balance output, or open disputes file
+    #[get = "pub"]
+    before: String,
+
+    #[get = "pub"]
+    format: Format,
+
+    /// Whether open disputes files are compared instead of balance outputs
+    #[get = "pub"]
+    disputes: bool,
+}
+
+/// Arguments of the `simulate` command
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct SimulateArgs {
+    /// Path to the CSV file of the simulated transactions
+    #[get = "pub"]
+    batch: String,
+
+    /// Whether the batch should be applied, instead of previewed
+    #[get = "pub"]
+    commit: bool,
+
+    #[get = "pub"]
+    format: Format,
+}
+
+/// Arguments of the `query` command
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct QueryArgs {
+    /// Client to query, all the clients if not given
+    #[get = "pub"]
+    client: Option<ClientId>,
+
+    /// Point of the input, the end of it if not given
+    #[get = "pub"]
+    as_of: Option<AsOf>,
+
+    /// Whether the open disputes should be output, instead of the balances
+    #[get = "pub"]
+    disputes: bool,
+}
+
+impl Args {
+    /// Parses the arguments, excluding the binary name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let command = args.next_if(|a| COMMANDS.contains(&a.as_str()));
+
+        let mut positional = Vec::new();
+        let mut events = None;
+        let mut config = None;
+        let mut fx_report = None;
+        let mut ledger = None;
+        let mut audit = None;
+        let mut summary = false;
+        let mut metrics = None;
+        let mut opening_balances = None;
+        let mut open_disputes = None;
+        let mut client = None;
+        let mut from = None;
+        let mut to = None;
+        let mut from_tx = None;
+        let mut to_tx = None;
+        let mut format = Format::Csv;
+        let mut expected = None;
+        let mut tolerance = None;
+        let mut relative_tolerance = None;
+        let mut batch = None;
+        let mut commit = false;
+        let mut as_of = None;
+        let mut disputes = false;
+        let mut head = None;
+        let mut progress = false;
+        let mut error_format = ErrorFormat::default();
+        let mut log_level = None;
+        let mut log_format = LogFormat::default();
+
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), command.as_deref()) {
+                ("--config", None | Some("statement" | "reconcile" | "simulate" | "query")) => {
+                    config = Some(value_of(&arg, &mut args)?)
+                }
+                (
+                    "--opening-balances",
+                    None | Some("statement" | "reconcile" | "simulate" | "query"),
+                ) => opening_balances = Some(value_of(&arg, &mut args)?),
+                (
+                    "--open-disputes",
+                    None | Some("statement" | "reconcile" | "simulate" | "query"),
+                ) => open_disputes = Some(value_of(&arg, &mut args)?),
+                ("--progress", None | Some("statement" | "reconcile" | "simulate" | "query")) => {
+                    progress = true
+                }
+                ("--error-format", _) => error_format = value_of(&arg, &mut args)?.parse()?,
+                ("--log-level", _) => {
+                    let value = value_of(&arg, &mut args)?;
+                    log_level = Some(value.parse().map_err(|_| {
+                        eyre!(
+                            "`{}` is not a known log level. Expected `error`, `warn`, `info`, `debug` or `trace`",
+                            value
+                        )
+                    })?)
+                }
+                ("--log-format", _) => log_format = value_of(&arg, &mut args)?.parse()?,
+                ("--events", None) => events = Some(value_of(&arg, &mut args)?),
+                ("--fx-report", None) => fx_report = Some(value_of(&arg, &mut args)?),
+                ("--ledger", None) => ledger = Some(value_of(&arg, &mut args)?),
+                ("--audit", None) => audit = Some(value_of(&arg, &mut args)?),
+                ("--summary", None) => summary = true,
+                ("--metrics", None) => metrics = Some(value_of(&arg, &mut args)?),
+                ("--client", Some("statement" | "query")) => {
+                    client = Some(
+                        value_of(&arg, &mut args)?
+                            .parse()
+                            .wrap_err("Option `--client` needs a client id")?,
+                    )
+                }
+                ("--from", Some("statement")) => {
+                    from = Some(parse_timestamp(&value_of(&arg, &mut args)?)?)
+                }
+                ("--to", Some("statement")) => {
+                    to = Some(parse_timestamp(&value_of(&arg, &mut args)?)?)
+                }
+                ("--from-tx", Some("statement")) => {
+                    from_tx = Some(
+                        value_of(&arg, &mut args)?
+                            .parse()
+                            .wrap_err("Option `--from-tx` needs a transaction id")?,
+                    )
+                }
+                ("--to-tx", Some("statement")) => {
+                    to_tx = Some(
+                        value_of(&arg, &mut args)?
+                            .parse()
+                            .wrap_err("Option `--to-tx` needs a transaction id")?,
+                    )
+                }
+                ("--format", Some("statement" | "diff" | "simulate")) => {
+                    format = value_of(&arg, &mut args)?.parse()?
+                }
+                ("--expected", Some("reconcile")) => expected = Some(value_of(&arg, &mut args)?),
+                ("--tolerance", Some("reconcile")) => tolerance = Some(amount_of(&arg, &mut args)?),
+                ("--relative-tolerance", Some("reconcile")) => {
+                    relative_tolerance = Some(amount_of(&arg, &mut args)?)
+                }
+                ("--batch", Some("simulate")) => batch = Some(value_of(&arg, &mut args)?),
+                ("--commit", Some("simulate")) => commit = true,
+                ("--at-tx", Some("query")) => {
+                    let tx = value_of(&arg, &mut args)?
+                        .parse()
+                        .wrap_err("Option `--at-tx` needs a transaction id")?;
+                    as_of = Some(AsOf::Tx(tx))
+                }
+                ("--at", Some("query")) => {
+                    as_of = Some(AsOf::Time(parse_timestamp(&value_of(&arg, &mut args)?)?))
+                }
+                ("--disputes", Some("query" | "diff")) => disputes = true,
+                ("--head", Some("verify-audit")) => {
+                    head = Some(value_of(&arg, &mut args)?.parse()?)
+                }
+                (a, _) if a.starts_with("--") => bail!("Unknown option `{}`", a),
+                _ => positional.push(arg),
+            }
+        }
+
+        // `diff` takes the earlier balance output before the input
+        let arguments = if command.as_deref() == Some("diff") {
+            2
+        } else {
+            1
+        };
+        if positional.len() != arguments {
+            bail!("Incorrect number of arguments");
+        }
+        let input = positional.pop().unwrap_or_default();
+
+        let command = match command.as_deref() {
+            Some("statement") => {
+                let client =
+                    client.ok_or_else(|| eyre!("Command `statement` needs option `--client`"))?;
+                Command::Statement(StatementArgs {
+                    client,
+                    filter: StatementFilter::new(from, to, from_tx, to_tx),
+                    format,
+                })
+            }
+            Some("verify-audit") => Command::VerifyAudit(VerifyAuditArgs { head }),
+            Some("diff") => Command::Diff(DiffArgs {
+                before: positional.remove(0),
+                format,
+                disputes,
+            }),
+            Some("reconcile") => {
+                let expected = expected
+                    .ok_or_else(|| eyre!("Command `reconcile` needs option `--expected`"))?;
+                let default = Tolerance::default();
+                Command::Reconcile(ReconcileArgs {
+                    expected,
+                    tolerance: Tolerance::new(
+                        tolerance.unwrap_or(*default.absolute()),
+                        relative_tolerance.unwrap_or(*default.relative()),
+                    ),
+                })
+            }
+            Some("simulate") => Command::Simulate(SimulateArgs {
+                batch: batch.ok_or_else(|| eyre!("Command `simulate` needs option `--batch`"))?,
+                commit,
+                format,
+            }),
+            Some("query") => Command::Query(QueryArgs {
+                client,
+                as_of,
+                disputes,
+            }),
+            _ => Command::Process,
+        };
+
+        Ok(Self {
+            command,
+            input,
+            events,
+            config,
+            fx_report,
+            ledger,
+            audit,
+            summary,
+            metrics,
+            opening_balances,
+            open_disputes,
+            progress,
+            error_format,
+            log_level,
+            log_format,
+        })
+    }
+}
+
+/// Takes the value of the `option` from the arguments
+fn value_of(option: &str, args: &mut impl Iterator<Item = String>) -> Result<String> {
+    args.next()
+        .ok_or_else(|| eyre!("Option `{}` needs a value", option))
+}
+
+/// Takes the value of the `option` from the arguments, as non-negative amount
+fn amount_of(option: &str, args: &mut impl Iterator<Item = String>) -> Result<f64> {
+    let value = value_of(option, args)?;
+    match value.parse::<f64>() {
+        Ok(amount) if amount.is_finite() && amount >= 0.0 => Ok(amount),
+        _ => bail!(
+            "Option `{}` needs a non-negative amount, got `{}`",
+            option,
+            value
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Args, Command, ErrorFormat, LogFormat, QueryArgs, VerifyAuditArgs};
+    use crate::{query::AsOf, table::Format};
+    use tracing::Level;
+
+    fn parse(args: &[&str]) -> color_eyre::Result<Args> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_input_only() {
+        let args = parse(&["input.csv"]).unwrap();
+
+        assert_eq!(args.input(), "input.csv");
+        assert_eq!(args.command(), &Command::Process);
+        assert_eq!(args.events(), &None);
+    }
+
+    #[test]
+    fn parses_events_option() {
+        let args = parse(&["--events", "events.csv", "input.csv"]).unwrap();
+
+        assert_eq!(args.input(), "input.csv");
+        assert_eq!(args.events().as_deref(), Some("events.csv"));
+    }
+
+    #[test]
+    fn parses_config_option() {
+        let args = parse(&["input.csv", "--config", "config.toml"]).unwrap();
+
+        assert_eq!(args.input(), "input.csv");
+        assert_eq!(args.config().as_deref(), Some("config.toml"));
+    }
+
+    #[test]
+    fn parses_fx_report_option() {
+        let args = parse(&["--fx-report", "pnl.csv", "input.csv"]).unwrap();
+
+        assert_eq!(args.input(), "input.csv");
+        assert_eq!(args.fx_report().as_deref(), Some("pnl.csv"));
+    }
+
+    #[test]
+    fn parses_ledger_option() {
+        let args = parse(&["input.csv", "--ledger", "ledger.csv"]).unwrap();
+
+        assert_eq!(args.input(), "input.csv");
+        assert_eq!(args.ledger().as_deref(), Some("ledger.csv"));
+    }
+
+    #[test]
+    fn option_without_value_is_an_error() {
+        assert!(parse(&["input.csv", "--events"]).is_err());
+    }
+
+    #[test]
+    fn unknown_option_is_an_error() {
+        assert!(parse(&["--verbose", "input.csv"]).is_err());
+        assert!(parse(&["input.csv", "--client", "1"]).is_err());
+    }
+
+    #[test]
+    fn parses_statement_command() {
+        let args = parse(&[
+            "statement",
+            "--client",
+            "42",
+            "--from-tx",
+            "10",
+            "--from",
+            "2022-01-01T00:00:00Z",
+            "--format",
+            "table",
+            "input.csv",
+        ])
+        .unwrap();
+
+        assert_eq!(args.input(), "input.csv");
+        match args.command() {
+            Command::Statement(s) => {
+                assert_eq!(*s.client(), 42);
+                assert_eq!(*s.filter().from_tx(), Some(10));
+                assert_eq!(s.filter().to_tx(), &None);
+                assert!(s.filter().from().is_some());
+                assert_eq!(*s.format(), Format::Table);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn parses_audit_option_and_verify_command() {
+        let args = parse(&["--audit", "audit.csv", "input.csv"]).unwrap();
+        let verify = parse(&["verify-audit", "audit.csv"]).unwrap();
+
+        assert_eq!(args.audit().as_deref(), Some("audit.csv"));
+        assert_eq!(
+            verify.command(),
+            &Command::VerifyAudit(VerifyAuditArgs { head: None })
+        );
+        assert_eq!(verify.input(), "audit.csv");
+        assert!(matches!(
+            parse(&["verify-audit", "--head", "3:abc", "audit.csv"]).unwrap().command(),
+            Command::VerifyAudit(a) if a.head().as_ref().map(|h| *h.seq()) == Some(3)
+        ));
+        assert!(parse(&["verify-audit", "--head", "abc", "audit.csv"]).is_err());
+        assert!(parse(&["verify-audit", "--config", "c.toml", "audit.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_metrics_options() {
+        let args = parse(&["--summary", "--metrics", "metrics.prom", "input.csv"]).unwrap();
+
+        assert!(*args.summary());
+        assert_eq!(args.metrics().as_deref(), Some("metrics.prom"));
+        assert!(!*parse(&["input.csv"]).unwrap().summary());
+        assert!(parse(&["diff", "--summary", "a.csv", "b.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_reconcile_command() {
+        let args = parse(&[
+            "reconcile",
+            "--expected",
+            "expected.csv",
+            "--tolerance",
+            "0.01",
+            "input.csv",
+        ])
+        .unwrap();
+
+        match args.command() {
+            Command::Reconcile(r) => {
+                assert_eq!(r.expected(), "expected.csv");
+                assert_eq!(*r.tolerance().absolute(), 0.01);
+                assert_eq!(*r.tolerance().relative(), 0.0);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+        assert!(parse(&["reconcile", "input.csv"]).is_err());
+        assert!(parse(&[
+            "reconcile",
+            "--expected",
+            "e.csv",
+            "--tolerance",
+            "-1",
+            "i.csv"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parses_opening_state_options() {
+        let args = parse(&[
+            "statement",
+            "--client",
+            "1",
+            "--opening-balances",
+            "closing.csv",
+            "--open-disputes",
+            "disputes.csv",
+            "input.csv",
+        ])
+        .unwrap();
+
+        assert_eq!(args.opening_balances().as_deref(), Some("closing.csv"));
+        assert_eq!(args.open_disputes().as_deref(), Some("disputes.csv"));
+    }
+
+    #[test]
+    fn parses_diff_command() {
+        let args = parse(&["diff", "--format", "table", "before.csv", "after.csv"]).unwrap();
+
+        assert_eq!(args.input(), "after.csv");
+        match args.command() {
+            Command::Diff(d) => {
+                assert_eq!(d.before(), "before.csv");
+                assert_eq!(*d.format(), Format::Table);
+                assert!(!*d.disputes());
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+        assert!(parse(&["diff", "after.csv"]).is_err());
+
+        let args = parse(&["diff", "--disputes", "before.csv", "after.csv"]).unwrap();
+        match args.command() {
+            Command::Diff(d) => assert!(*d.disputes()),
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn parses_simulate_command() {
+        let args = parse(&["simulate", "--batch", "batch.csv", "--commit", "input.csv"]).unwrap();
+
+        assert_eq!(args.input(), "input.csv");
+        match args.command() {
+            Command::Simulate(s) => {
+                assert_eq!(s.batch(), "batch.csv");
+                assert!(*s.commit());
+                assert_eq!(*s.format(), Format::Csv);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+        assert!(parse(&["simulate", "input.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_query_command() {
+        let args = parse(&["query", "--client", "7", "--at-tx", "1000", "input.csv"]).unwrap();
+
+        assert_eq!(
+            args.command(),
+            &Command::Query(QueryArgs {
+                client: Some(7),
+                as_of: Some(AsOf::Tx(1000)),
+                disputes: false,
+            })
+        );
+        assert!(parse(&["query", "--at", "yesterday", "input.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_error_format_option() {
+        let args = parse(&["diff", "--error-format", "json", "a.csv", "b.csv"]).unwrap();
+
+        assert_eq!(args.error_format(), &ErrorFormat::Json);
+        assert_eq!(
+            parse(&["input.csv"]).unwrap().error_format(),
+            &ErrorFormat::Text
+        );
+        assert!(parse(&["--error-format", "xml", "input.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_progress_option() {
+        assert!(*parse(&["--progress", "input.csv"]).unwrap().progress());
+        assert!(*parse(&["query", "--progress", "input.csv"])
+            .unwrap()
+            .progress());
+        assert!(!*parse(&["input.csv"]).unwrap().progress());
+        assert!(parse(&["verify-audit", "--progress", "audit.csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_log_options() {
+        let args = parse(&["--log-level", "debug", "--log-format", "json", "input.csv"]).unwrap();
+
+        assert_eq!(args.log_level(), &Some(Level::DEBUG));
+        assert_eq!(args.log_format(), &LogFormat::Json);
+        assert_eq!(parse(&["input.csv"]).unwrap().log_level(), &None);
+        assert!(parse(&["--log-level", "loud", "input.csv"]).is_err());
+    }
+
+    #[test]
+    fn statement_needs_client() {
+        assert!(parse(&["statement", "input.csv"]).is_err());
+        assert!(parse(&["statement", "--client", "x", "input.csv"]).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Engine configuration, loaded from a TOML file
+
+use crate::{
+    limits::LimitsConfig,
+    transaction::{ClientId, Timestamp},
+};
+use chrono::Duration;
+use color_eyre::{eyre::Context, Result};
+use getset::Getters;
+use serde::Deserialize;
+use std::{collections::HashSet, env, fs, path::PathBuf};
+
+/// Configuration of the engine. Every section is optional.
+#[derive(Debug, Clone, Default, Deserialize, Getters)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[get = "pub"]
+    policies: PoliciesConfig,
+
+    #[get = "pub"]
+    limits: LimitsConfig,
+
+    #[get = "pub"]
+    disputes: DisputesConfig,
+
+    #[get = "pub"]
+    history: HistoryConfig,
+
+    #[get = "pub"]
+    fx: FxConfig,
+
+    #[get = "pub"]
+    accounts: AccountsConfig,
+
+    #[get = "pub"]
+    authorizations: AuthorizationsConfig,
+}
+
+impl Config {
+    /// Loads the configuration from TOML file at `path`
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Can not read config file `{}`", path))?;
+
+        toml::from_str(&content).wrap_err_with(|| format!("Invalid config file `{}`", path))
+    }
+}
+
+/// Built-in transaction policies. Each policy is enabled only if configured.
+#[derive(Debug, Clone, Default, Deserialize, Getters)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoliciesConfig {
+    /// Clients whose deposits are rejected
+    #[get = "pub"]
+    blocked_deposit_clients: HashSet<ClientId>,
+
+    /// Limit on withdrawals made by new clients
+    #[get = "pub"]
+    new_client_withdrawals: Option<NewClientWithdrawalsConfig>,
+
+    /// Deposits above this amount get annotated
+    #[get = "pub"]
+    flag_deposits_above: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Getters)]
+#[serde(deny_unknown_fields)]
+pub struct NewClientWithdrawalsConfig {
+    /// Maximum amount of a single withdrawal
+    #[get = "pub"]
+    max_amount: f64,
+
+    /// Number of deposits, withdrawals and captures after which the client is no longer considered new
+    #[get = "pub"]
+    min_transactions: usize,
+}
+
+/// Storage of the transaction history
+#[derive(Debug, Clone, Deserialize, Getters)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Approximate number of bytes of the history kept in memory, before spilling it to disk
+    #[get = "pub"]
+    memory_budget: usize,
+
+    /// Directory the spilled history is written to
+    #[get = "pub"]
+    spill_dir: PathBuf,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            memory_budget: 256 * 1024 * 1024,
+            spill_dir: env::temp_dir(),
+        }
+    }
+}
+
+/// Dispute windows and expiry. Apply to timestamped transactions only.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputesConfig {
+    /// Time, in seconds since the deposit, after which the deposit can no longer be disputed
+    window_seconds: Option<u64>,
+
+    /// Time, in seconds since the dispute, after which an unresolved dispute expires
+    max_duration_seconds: Option<u64>,
+
+    /// What happens with an expired dispute
+    expiry_action: ExpiryAction,
+}
+
+impl DisputesConfig {
+    pub fn window(&self) -> Option<Duration> {
+        self.window_seconds.map(seconds)
+    }
+
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_duration_seconds.map(seconds)
+    }
+
+    /// Returns the time at which dispute made at `disputed_at` expires, if it does
+    pub fn deadline(&self, disputed_at: Option<Timestamp>) -> Option<Timestamp> {
+        disputed_at?.checked_add_signed(self.max_duration()?)
+    }
+
+    pub fn expiry_action(&self) -> ExpiryAction {
+        self.expiry_action
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryAction {
+    #[default]
+    Resolve,
+    Chargeback,
+}
+
+/// Currency conversion
+#[derive(Debug, Clone, Deserialize, Getters)]
+#[serde(default, deny_unknown_fields)]
+pub struct FxConfig {
+    /// Path to the CSV file with exchange rates
+    #[get = "pub"]
+    rates: Option<String>,
+
+    /// Fraction of the converted amount kept by the engine
+    spread: f64,
+
+    /// Number of decimal places the converted amount is rounded to
+    #[get = "pub"]
+    decimals: u32,
+
+    /// How the converted amount is rounded
+    rounding: Rounding,
+}
+
+impl FxConfig {
+    pub fn spread(&self) -> f64 {
+        self.spread
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+}
+
+impl Default for FxConfig {
+    fn default() -> Self {
+        Self {
+            rates: None,
+            spread: 0.0,
+            decimals: 4,
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    /// Half away from zero
+    #[default]
+    Nearest,
+    Down,
+    Up,
+}
+
+impl Rounding {
+    /// Rounds the `value` to `decimals` decimal places
+    pub fn round(self, value: f64, decimals: u32) -> f64 {
+        let factor = 10f64.powi(decimals as i32);
+        // drop the floating point noise first, so that e.g. 14.85 is not rounded down to 14.84
+        let scaled = (value * factor * 1e6).round() / 1e6;
+        let rounded = match self {
+            Rounding::Nearest => scaled.round(),
+            Rounding::Down => scaled.floor(),
+            Rounding::Up => scaled.ceil(),
+        };
+        rounded / factor
+    }
+}
+
+/// Authorization holds. Expiry applies to timestamped transactions only.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthorizationsConfig {
+    /// Time, in seconds since the authorization, after which the hold is released
+    expiry_seconds: Option<u64>,
+}
+
+impl AuthorizationsConfig {
+    pub fn expiry(&self) -> Option<Duration> {
+        self.expiry_seconds.map(seconds)
+    }
+
+    /// Returns the time at which authorization made at `authorized_at` expires, if it does
+    pub fn deadline(&self, authorized_at: Option<Timestamp>) -> Option<Timestamp> {
+        authorized_at?.checked_add_signed(self.expiry()?)
+    }
+}
+
+/// Multiple accounts per client
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// What gets locked after a chargeback
+    chargeback_lock: LockScope,
+}
+
+impl AccountsConfig {
+    pub fn chargeback_lock(&self) -> LockScope {
+        self.chargeback_lock
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockScope {
+    /// All the accounts of the client
+    #[default]
+    Client,
+    /// Only the account the charged back deposit was made to
+    Account,
+}
+
+/// Converts configured number of seconds to `Duration`, saturating on overflow
+fn seconds(secs: u64) -> Duration {
+    Duration::seconds(secs.min(i64::MAX as u64 / 1000) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Rounding};
+
+    #[test]
+    fn empty_config_is_valid() {
+        let config: Config = toml::from_str("").unwrap();
+
+        assert!(config.policies().blocked_deposit_clients().is_empty());
+        assert!(config.policies().new_client_withdrawals().is_none());
+    }
+
+    #[test]
+    fn parses_policies() {
+        let config: Config = toml::from_str(
+            r#"
+            [policies]
+            blocked_deposit_clients = [3, 4]
+            flag_deposits_above = 1000.0
+
+            [policies.new_client_withdrawals]
+            max_amount = 100.0
+            min_transactions = 5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.policies().blocked_deposit_clients().len(), 2);
+        assert_eq!(*config.policies().flag_deposits_above(), Some(1000.0));
+        let withdrawals = config.policies().new_client_withdrawals().as_ref().unwrap();
+        assert_eq!(*withdrawals.min_transactions(), 5);
+    }
+
+    #[test]
+    fn rounding_modes() {
+        assert_eq!(Rounding::Nearest.round(1.005, 2), 1.01);
+        assert_eq!(Rounding::Down.round(1.019, 2), 1.01);
+        assert_eq!(Rounding::Up.round(1.011, 2), 1.02);
+        assert_eq!(Rounding::Down.round(14.85, 2), 14.85);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("foo = 1").is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! Currency codes
+
+use crate::errors::DeserializationError;
+use std::{fmt, str::FromStr};
+
+/// Three-letter currency code (e.g. `EUR`). Amounts given without currency are held in `Currency::NONE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// Currency of amounts given without currency
+    pub const NONE: Currency = Currency([0; 3]);
+
+    pub fn is_none(&self) -> bool {
+        *self == Currency::NONE
+    }
+
+    /// Returns the raw bytes of the code
+    pub fn to_bytes(self) -> [u8; 3] {
+        self.0
+    }
+
+    /// Creates the currency from raw bytes returned by `to_bytes`
+    pub fn from_bytes(bytes: [u8; 3]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = DeserializationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.to_ascii_uppercase();
+        match code.as_bytes() {
+            &[a, b, c] if code.bytes().all(|b| b.is_ascii_uppercase()) => Ok(Self([a, b, c])),
+            _ => Err(DeserializationError::InvalidCurrency(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_none() {
+            return Ok(());
+        }
+
+        // only ASCII letters can be parsed, so this is always valid UTF-8
+        f.write_str(std::str::from_utf8(&self.0).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Currency;
+
+    #[test]
+    fn parses_and_normalizes_code() {
+        let c: Currency = "eur".parse().unwrap();
+
+        assert_eq!(c.to_string(), "EUR");
+        assert!(!c.is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        assert!("EU".parse::<Currency>().is_err());
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+    }
+}
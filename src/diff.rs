@@ -0,0 +1,290 @@
+//! Differences between two snapshots of the client balances, or of the open disputes
+
+use crate::snapshot::{BalanceKey, DisputeKey, DisputeSnapshot, Snapshot, Tolerance};
+use getset::Getters;
+use std::collections::BTreeMap;
+
+/// How a balance, or an open dispute, differs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// Present only in the later snapshot
+    Added,
+    /// Present only in the earlier snapshot
+    Removed,
+    Changed,
+}
+
+impl Change {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        }
+    }
+}
+
+/// Difference of a single field of the balance, or of the open dispute
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Delta {
+    #[get = "pub"]
+    field: &'static str,
+
+    #[get = "pub"]
+    before: Option<String>,
+
+    #[get = "pub"]
+    after: Option<String>,
+
+    /// `after - before` of the amounts, missing values counting as zero
+    #[get = "pub"]
+    delta: Option<String>,
+}
+
+/// Difference of a single balance, or a single open dispute, between the snapshots
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Difference<K> {
+    #[get = "pub"]
+    key: K,
+
+    #[get = "pub"]
+    change: Change,
+
+    /// Changed fields, or all the fields of an added or removed balance or dispute
+    #[get = "pub"]
+    deltas: Vec<Delta>,
+}
+
+/// Returns the differences of the `after` balances from the `before` ones, ordered by client, account and currency
+pub fn diff(
+    before: &BTreeMap<BalanceKey, Snapshot>,
+    after: &BTreeMap<BalanceKey, Snapshot>,
+) -> Vec<Difference<BalanceKey>> {
+    // amounts equal in the four decimal places of the output are not reported
+    let tolerance = Tolerance::default();
+
+    differences(before, after, |change, b, a| {
+        let mut deltas = Vec::new();
+        for (field, before, after) in [
+            ("available", b.map(|s| s.available), a.map(|s| s.available)),
+            ("held", b.map(|s| s.held), a.map(|s| s.held)),
+            ("total", b.map(|s| s.total), a.map(|s| s.total)),
+        ] {
+            if change == Change::Changed
+                && tolerance.allows(before.unwrap_or_default(), after.unwrap_or_default())
+            {
+                continue;
+            }
+            deltas.push(Delta {
+                field,
+                before: before.map(|v| format!("{:.4}", v)),
+                after: after.map(|v| format!("{:.4}", v)),
+                delta: Some(format!(
+                    "{:.4}",
+                    after.unwrap_or_default() - before.unwrap_or_default()
+                )),
+            });
+        }
+        let (locked_before, locked_after) = (b.map(|s| s.locked), a.map(|s| s.locked));
+        if change != Change::Changed || locked_before != locked_after {
+            deltas.push(Delta {
+                field: "locked",
+                before: locked_before.map(|l| l.to_string()),
+                after: locked_after.map(|l| l.to_string()),
+                delta: None,
+            });
+        }
+
+        deltas
+    })
+}
+
+/// Returns the differences of the `after` open disputes from the `before` ones, ordered by client and transaction
+pub fn diff_disputes(
+    before: &BTreeMap<DisputeKey, DisputeSnapshot>,
+    after: &BTreeMap<DisputeKey, DisputeSnapshot>,
+) -> Vec<Difference<DisputeKey>> {
+    let tolerance = Tolerance::default();
+
+    differences(before, after, |change, b, a| {
+        let mut deltas = Vec::new();
+        let (amount_before, amount_after) = (b.map(|s| s.amount), a.map(|s| s.amount));
+        if change != Change::Changed
+            || !tolerance.allows(
+                amount_before.unwrap_or_default(),
+                amount_after.unwrap_or_default(),
+            )
+        {
+            deltas.push(Delta {
+                field: "amount",
+                before: amount_before.map(|v| format!("{:.4}", v)),
+                after: amount_after.map(|v| format!("{:.4}", v)),
+                delta: Some(format!(
+                    "{:.4}",
+                    amount_after.unwrap_or_default() - amount_before.unwrap_or_default()
+                )),
+            });
+        }
+        for (field, before, after) in [
+            (
+                "account",
+                b.map(|s| s.account.to_string()),
+                a.map(|s| s.account.to_string()),
+            ),
+            (
+                "currency",
+                b.map(|s| s.currency.to_string()),
+                a.map(|s| s.currency.to_string()),
+            ),
+            (
+                "disputed_at",
+                b.and_then(|s| s.disputed_at).map(|t| t.to_rfc3339()),
+                a.and_then(|s| s.disputed_at).map(|t| t.to_rfc3339()),
+            ),
+        ] {
+            if change != Change::Changed || before != after {
+                deltas.push(Delta {
+                    field,
+                    before,
+                    after,
+                    delta: None,
+                });
+            }
+        }
+
+        deltas
+    })
+}
+
+/// Returns the differences of the `after` snapshot from the `before` one, ordered by the key.
+/// The `deltas` of a key are given the change, and the values before and after.
+fn differences<K, V>(
+    before: &BTreeMap<K, V>,
+    after: &BTreeMap<K, V>,
+    deltas: impl Fn(Change, Option<&V>, Option<&V>) -> Vec<Delta>,
+) -> Vec<Difference<K>>
+where
+    K: Ord + Copy,
+{
+    let keys: Vec<_> = before
+        .keys()
+        .chain(after.keys().filter(|k| !before.contains_key(k)))
+        .copied()
+        .collect();
+
+    let mut differences = Vec::new();
+    for key in keys {
+        let (b, a) = (before.get(&key), after.get(&key));
+        let change = match (b, a) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Removed,
+            _ => Change::Changed,
+        };
+
+        let deltas = deltas(change, b, a);
+        if !deltas.is_empty() {
+            differences.push(Difference {
+                key,
+                change,
+                deltas,
+            });
+        }
+    }
+    differences.sort_by_key(|d| d.key);
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, diff_disputes, Change};
+    use crate::{
+        account::Account,
+        currency::Currency,
+        snapshot::{DisputeSnapshot, Snapshot},
+    };
+    use std::collections::BTreeMap;
+
+    fn balance(available: f64, locked: bool) -> Snapshot {
+        Snapshot {
+            available,
+            held: 0.0,
+            total: available,
+            locked,
+        }
+    }
+
+    #[test]
+    fn reports_added_removed_and_changed_balances() {
+        let key = |client| (client, Account::MAIN, Currency::NONE);
+        let before = BTreeMap::from([
+            (key(1), balance(1.0, false)),
+            (key(2), balance(2.0, false)),
+            (key(3), balance(3.0, false)),
+        ]);
+        let after = BTreeMap::from([
+            (key(1), balance(1.5, true)),
+            (key(3), balance(3.0, false)),
+            (key(4), balance(4.0, false)),
+        ]);
+
+        let differences = diff(&before, &after);
+
+        let changes: Vec<_> = differences
+            .iter()
+            .map(|d| (d.key().0, *d.change()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (1, Change::Changed),
+                (2, Change::Removed),
+                (4, Change::Added)
+            ]
+        );
+        let fields: Vec<_> = differences[0].deltas().iter().map(|d| *d.field()).collect();
+        assert_eq!(fields, vec!["available", "total", "locked"]);
+        assert_eq!(
+            differences[0].deltas()[0].delta().as_deref(),
+            Some("0.5000")
+        );
+        assert_eq!(
+            differences[1].deltas()[0].delta().as_deref(),
+            Some("-2.0000")
+        );
+        assert_eq!(differences[2].deltas().len(), 4);
+    }
+
+    #[test]
+    fn reports_changed_fields_of_open_disputes() {
+        let dispute = |amount| DisputeSnapshot {
+            amount,
+            account: Account::MAIN,
+            currency: Currency::NONE,
+            disputed_at: None,
+        };
+        let before = BTreeMap::from([((1, 1), dispute(1.0)), ((1, 2), dispute(2.0))]);
+        let after = BTreeMap::from([
+            ((1, 1), dispute(1.00001)),
+            (
+                (1, 2),
+                DisputeSnapshot {
+                    account: "savings".parse().unwrap(),
+                    ..dispute(2.5)
+                },
+            ),
+        ]);
+
+        let differences = diff_disputes(&before, &after);
+
+        assert_eq!(differences.len(), 1);
+        assert_eq!(*differences[0].key(), (1, 2));
+        assert_eq!(*differences[0].change(), Change::Changed);
+        let fields: Vec<_> = differences[0].deltas().iter().map(|d| *d.field()).collect();
+        assert_eq!(fields, vec!["amount", "account"]);
+        assert_eq!(
+            differences[0].deltas()[0].delta().as_deref(),
+            Some("0.5000")
+        );
+    }
+}
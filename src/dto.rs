@@ -2,8 +2,23 @@
 
 use getset::Getters;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-use crate::repo::Client;
+use crate::{
+    account::Account,
+    audit::AuditEntry,
+    currency::Currency,
+    diff::Difference,
+    errors::{DeserializationError, ErrorCode, RepositoryError},
+    events::Event,
+    fx::Pnl,
+    history::{Entry, EntryKind},
+    ledger::Posting,
+    reconcile::Break,
+    repo::{Balance, Client},
+    snapshot::{BalanceKey, DisputeKey, Snapshot},
+    transaction::{parse_timestamp, ClientId, Timestamp, TxId},
+};
 
 #[derive(Debug, Deserialize, Clone, Getters)]
 pub struct InputRecord {
@@ -11,18 +26,43 @@ pub struct InputRecord {
     r#type: String,
 
     #[get = "pub"]
-    client: u16,
+    client: ClientId,
 
     #[get = "pub"]
-    tx: u32,
+    tx: TxId,
 
     #[get = "pub"]
     amount: Option<f64>,
+
+    /// RFC 3339 date and time, or seconds since the Unix epoch
+    #[get = "pub"]
+    timestamp: Option<String>,
+
+    /// Three-letter currency code of the amount
+    #[get = "pub"]
+    currency: Option<String>,
+
+    /// Three-letter currency code the amount is converted to
+    #[get = "pub"]
+    to_currency: Option<String>,
+
+    /// Name of the client's account the transaction concerns
+    #[get = "pub"]
+    account: Option<String>,
+
+    /// Name of the client's account the amount is transferred to
+    #[get = "pub"]
+    to_account: Option<String>,
 }
 
 impl InputRecord {
     #[cfg(test)]
-    pub fn new<T: Into<String>>(r#type: T, client: u16, tx: u32, amount: Option<f64>) -> Self {
+    pub fn new<T: Into<String>>(
+        r#type: T,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<f64>,
+    ) -> Self {
         let owned_type = r#type.into();
 
         Self {
@@ -30,42 +70,787 @@ impl InputRecord {
             client,
             tx,
             amount,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            account: None,
+            to_account: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_timestamp<T: Into<String>>(mut self, timestamp: T) -> Self {
+        self.timestamp = Some(timestamp.into());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_currency<T: Into<String>>(mut self, currency: T) -> Self {
+        self.currency = Some(currency.into());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_to_currency<T: Into<String>>(mut self, to_currency: T) -> Self {
+        self.to_currency = Some(to_currency.into());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_account<T: Into<String>>(mut self, account: T) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_to_account<T: Into<String>>(mut self, to_account: T) -> Self {
+        self.to_account = Some(to_account.into());
+        self
+    }
+}
+
+/// Single row of the FX rates file
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateRecord {
+    from: String,
+    to: String,
+    rate: f64,
+
+    /// Time since which the rate is effective, RFC 3339 date and time or seconds since the Unix epoch
+    effective: Option<String>,
+}
+
+impl RateRecord {
+    /// Returns (from, to, rate, effective)
+    pub fn parse(
+        &self,
+    ) -> Result<(Currency, Currency, f64, Option<Timestamp>), DeserializationError> {
+        if !self.rate.is_normal() || self.rate.is_sign_negative() {
+            return Err(DeserializationError::InvalidRate(self.rate));
         }
+
+        let effective = match self.effective.as_deref() {
+            Some(e) if !e.is_empty() => Some(parse_timestamp(e)?),
+            _ => None,
+        };
+
+        Ok((self.from.parse()?, self.to.parse()?, self.rate, effective))
     }
 }
 
-#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct OutputRecord {
-    client: u16,
+    client: ClientId,
     available: String,
     held: String,
     total: String,
     locked: bool,
+
+    /// Present only in the multi-account output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account: Option<String>,
+
+    /// Present only in the multi-currency output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<String>,
+
+    /// Present only if any client has an overdraft
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credit_used: Option<String>,
+
+    /// Present only if any client has an overdraft
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credit_remaining: Option<String>,
+
+    /// Client, account and currency of the balance, whether output or not
+    #[serde(skip)]
+    key: BalanceKey,
+
+    /// Unformatted balance
+    #[serde(skip)]
+    snapshot: Snapshot,
+}
+
+/// Optional columns of the output
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputColumns {
+    pub account: bool,
+    pub currency: bool,
+    pub credit: bool,
 }
 
 impl OutputRecord {
-    pub fn new(client: u16, available: f64, held: f64, total: f64, locked: bool) -> Self {
+    pub fn new(client: ClientId, available: f64, held: f64, total: f64, locked: bool) -> Self {
         Self {
             client,
             available: format!("{:.4}", available),
             held: format!("{:.4}", held),
             total: format!("{:.4}", total),
             locked,
+            account: None,
+            currency: None,
+            credit_used: None,
+            credit_remaining: None,
+            key: (client, Account::MAIN, Currency::NONE),
+            snapshot: Snapshot {
+                available,
+                held,
+                total,
+                locked,
+            },
+        }
+    }
+
+    /// Returns the client, account and currency of the balance, with the balance itself
+    pub fn snapshot(&self) -> (BalanceKey, Snapshot) {
+        (self.key, self.snapshot)
+    }
+
+    /// Returns one record per account and currency held by the client `c`, or a single record if the client has no funds at all.
+    /// The optional columns are output only if set in `columns`.
+    pub fn rows(c: &Client, columns: OutputColumns) -> Vec<Self> {
+        let mut balances: Vec<_> = c.balances().iter().map(|(k, v)| (*k, *v)).collect();
+        if balances.is_empty() {
+            balances.push(((Account::MAIN, Currency::NONE), Balance::default()));
+        }
+
+        balances
+            .into_iter()
+            .map(|((account, currency), b)| {
+                let mut record = Self::new(
+                    *c.id(),
+                    *b.available(),
+                    *b.held(),
+                    b.total(),
+                    c.is_locked(account),
+                );
+                record.key = (*c.id(), account, currency);
+                if columns.account {
+                    record.account = Some(account.to_string());
+                }
+                if columns.currency {
+                    record.currency = Some(currency.to_string());
+                }
+                if columns.credit {
+                    // the overdraft is shared by all the accounts in the currency
+                    let limit = c.limits().credit_limit().unwrap_or(0.0);
+                    record.credit_used = Some(format!("{:.4}", b.credit_used()));
+                    record.credit_remaining =
+                        Some(format!("{:.4}", (limit - c.credit_used(currency)).max(0.0)));
+                }
+                record
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct EventRecord {
+    event: &'static str,
+    client: ClientId,
+    account: String,
+    tx: TxId,
+    timestamp: Option<String>,
+    currency: Option<String>,
+    amount: Option<String>,
+    note: Option<String>,
+}
+
+impl From<&Event> for EventRecord {
+    fn from(e: &Event) -> Self {
+        Self {
+            event: e.kind().name(),
+            client: *e.client(),
+            account: e.account().to_string(),
+            tx: *e.tx(),
+            timestamp: e.timestamp().map(|t| t.to_rfc3339()),
+            currency: e.kind().currency().map(|c| c.to_string()),
+            amount: e.kind().amount().map(|a| format!("{:.4}", a)),
+            note: e.kind().note(),
+        }
+    }
+}
+
+/// Single row of the FX P&L report
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct PnlRecord {
+    currency: String,
+    conversions: usize,
+    pnl: String,
+}
+
+impl PnlRecord {
+    pub fn new(currency: Currency, pnl: &Pnl) -> Self {
+        Self {
+            currency: currency.to_string(),
+            conversions: *pnl.conversions(),
+            pnl: format!("{:.4}", pnl.total()),
+        }
+    }
+}
+
+/// Single row of the ledger export
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct PostingRecord {
+    entry: u64,
+    client: ClientId,
+    tx: TxId,
+    timestamp: Option<String>,
+    debit: String,
+    credit: String,
+    currency: String,
+    amount: String,
+}
+
+impl PostingRecord {
+    /// Creates the record of the `entry`-th posting
+    pub fn new(entry: u64, p: &Posting) -> Self {
+        Self {
+            entry,
+            client: *p.client(),
+            tx: *p.tx(),
+            timestamp: p.timestamp().map(|t| t.to_rfc3339()),
+            debit: p.debit().to_string(),
+            credit: p.credit().to_string(),
+            currency: p.currency().to_string(),
+            amount: format!("{:.4}", p.amount()),
+        }
+    }
+}
+
+/// Single row of the client statement
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct StatementRecord {
+    r#type: String,
+    tx: TxId,
+    timestamp: Option<String>,
+    amount: Option<String>,
+
+    /// `applied` or `rejected`
+    status: &'static str,
+
+    /// Account and currency of the balance after the transaction
+    account: String,
+    currency: String,
+    available: String,
+    held: String,
+    total: String,
+
+    /// Numeric code of the rejection
+    error_code: Option<u16>,
+
+    /// Name and category of the rejection, as in the error envelope
+    error_name: Option<&'static str>,
+    error_category: Option<&'static str>,
+
+    /// Reason of the rejection
+    error: Option<String>,
+}
+
+impl StatementRecord {
+    /// Names of the columns, in order
+    pub const COLUMNS: [&'static str; 14] = [
+        "type",
+        "tx",
+        "timestamp",
+        "amount",
+        "status",
+        "account",
+        "currency",
+        "available",
+        "held",
+        "total",
+        "error_code",
+        "error_name",
+        "error_category",
+        "error",
+    ];
+
+    /// Creates the record of the transaction read from `record`, with the `balance` of the client's `account` in `currency` after it
+    pub fn new(
+        record: &InputRecord,
+        timestamp: Option<Timestamp>,
+        error: Option<&RepositoryError>,
+        account: Account,
+        currency: Currency,
+        balance: &Balance,
+    ) -> Self {
+        Self {
+            r#type: record.r#type().clone(),
+            tx: *record.tx(),
+            timestamp: timestamp.map(|t| t.to_rfc3339()),
+            amount: record.amount().map(|a| format!("{:.4}", a)),
+            status: if error.is_some() {
+                "rejected"
+            } else {
+                "applied"
+            },
+            account: account.to_string(),
+            currency: currency.to_string(),
+            available: format!("{:.4}", balance.available()),
+            held: format!("{:.4}", balance.held()),
+            total: format!("{:.4}", balance.total()),
+            error_code: error.map(|e| e.code()),
+            error_name: error.map(|e| e.name()),
+            error_category: error.map(|e| e.category().name()),
+            error: error.map(|e| e.to_string()),
+        }
+    }
+
+    /// Creates the record of the dispute or authorization expired by the `event`, with the `available` and `held` funds
+    /// of the client's `account` in `currency` after it
+    pub fn expiry(
+        event: &Event,
+        account: Account,
+        currency: Currency,
+        available: f64,
+        held: f64,
+    ) -> Self {
+        Self {
+            r#type: event.kind().name().to_owned(),
+            tx: *event.tx(),
+            timestamp: event.timestamp().map(|t| t.to_rfc3339()),
+            amount: None,
+            status: "applied",
+            account: account.to_string(),
+            currency: currency.to_string(),
+            available: format!("{:.4}", available),
+            held: format!("{:.4}", held),
+            total: format!("{:.4}", available + held),
+            error_code: None,
+            error_name: None,
+            error_category: None,
+            error: None,
+        }
+    }
+
+    /// Returns the values of the columns, in order
+    pub fn cells(&self) -> [String; 14] {
+        [
+            self.r#type.clone(),
+            self.tx.to_string(),
+            self.timestamp.clone().unwrap_or_default(),
+            self.amount.clone().unwrap_or_default(),
+            self.status.to_owned(),
+            self.account.clone(),
+            self.currency.clone(),
+            self.available.clone(),
+            self.held.clone(),
+            self.total.clone(),
+            self.error_code.map(|c| c.to_string()).unwrap_or_default(),
+            self.error_name.unwrap_or_default().to_owned(),
+            self.error_category.unwrap_or_default().to_owned(),
+            self.error.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// Single row of the audit log
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Getters)]
+pub struct AuditRecord {
+    /// Position of the entry in the log, starting from 1
+    #[get = "pub"]
+    seq: u64,
+
+    #[get = "pub"]
+    action: String,
+
+    #[get = "pub"]
+    client: Option<ClientId>,
+
+    #[get = "pub"]
+    tx: Option<TxId>,
+
+    #[get = "pub"]
+    timestamp: Option<String>,
+
+    #[get = "pub"]
+    amount: Option<String>,
+
+    #[get = "pub"]
+    detail: Option<String>,
+
+    /// Hash of the previous entry
+    #[get = "pub"]
+    prev_hash: String,
+
+    /// Hash of this entry, covering the hash of the previous one
+    #[get = "pub"]
+    hash: String,
+}
+
+impl AuditRecord {
+    pub fn new(seq: u64, entry: &AuditEntry, prev_hash: String, hash: String) -> Self {
+        Self {
+            seq,
+            action: entry.action().clone(),
+            client: *entry.client(),
+            tx: *entry.tx(),
+            timestamp: entry.timestamp().clone(),
+            amount: entry.amount().clone(),
+            detail: entry.detail().clone(),
+            prev_hash,
+            hash,
+        }
+    }
+
+    /// Returns the logged entry
+    pub fn entry(&self) -> AuditEntry {
+        AuditEntry::new(
+            self.action.clone(),
+            self.client,
+            self.tx,
+            self.timestamp.clone(),
+            self.amount.clone(),
+            self.detail.clone(),
+        )
+    }
+}
+
+/// Single row of a balances file, in the shape of `OutputRecord`
+#[derive(Debug, Deserialize, Clone)]
+pub struct BalanceRecord {
+    client: ClientId,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    account: Option<String>,
+    currency: Option<String>,
+}
+
+impl BalanceRecord {
+    /// Returns (client, account, currency, balance)
+    pub fn parse(&self) -> Result<(ClientId, Account, Currency, Snapshot), DeserializationError> {
+        let account = match self.account.as_deref() {
+            Some(a) if !a.is_empty() => a.parse()?,
+            _ => Account::MAIN,
+        };
+        let currency = match self.currency.as_deref() {
+            Some(c) if !c.is_empty() => c.parse()?,
+            _ => Currency::NONE,
+        };
+
+        Ok((
+            self.client,
+            account,
+            currency,
+            Snapshot {
+                available: self.available,
+                held: self.held,
+                total: self.total,
+                locked: self.locked,
+            },
+        ))
+    }
+}
+
+/// Single row of the reconciliation report
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct BreakRecord {
+    client: ClientId,
+    account: String,
+    currency: String,
+    r#break: &'static str,
+    expected: Option<String>,
+    actual: Option<String>,
+}
+
+impl From<&Break> for BreakRecord {
+    fn from(b: &Break) -> Self {
+        let (client, account, currency) = *b.key();
+        Self {
+            client,
+            account: account.to_string(),
+            currency: currency.to_string(),
+            r#break: b.kind().name(),
+            expected: b.expected().clone(),
+            actual: b.actual().clone(),
+        }
+    }
+}
+
+/// Single row of the opening balances file, in the shape of `OutputRecord`. The `total` and credit columns, if any, are ignored.
+#[derive(Debug, Deserialize, Clone)]
+pub struct OpeningBalanceRecord {
+    client: ClientId,
+    available: f64,
+    held: f64,
+    locked: bool,
+    account: Option<String>,
+    currency: Option<String>,
+}
+
+impl OpeningBalanceRecord {
+    /// Returns (client, account, currency, available, held, locked)
+    pub fn parse(
+        &self,
+    ) -> Result<(ClientId, Account, Currency, f64, f64, bool), DeserializationError> {
+        for amount in [self.available, self.held] {
+            if !amount.is_finite() {
+                return Err(DeserializationError::InvalidBalance(amount));
+            }
+        }
+        if self.held < 0.0 {
+            return Err(DeserializationError::NegativeHeld(self.held));
+        }
+        let account = match self.account.as_deref() {
+            Some(a) if !a.is_empty() => a.parse()?,
+            _ => Account::MAIN,
+        };
+        let currency = match self.currency.as_deref() {
+            Some(c) if !c.is_empty() => c.parse()?,
+            _ => Currency::NONE,
+        };
+
+        Ok((
+            self.client,
+            account,
+            currency,
+            self.available,
+            self.held,
+            self.locked,
+        ))
+    }
+}
+
+/// Single row of the open disputes file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenDisputeRecord {
+    client: ClientId,
+
+    /// Id of the disputed deposit
+    tx: TxId,
+
+    /// Amount of the disputed deposit
+    amount: f64,
+    account: Option<String>,
+    currency: Option<String>,
+
+    /// Time of the dispute, RFC 3339 date and time or seconds since the Unix epoch
+    disputed_at: Option<String>,
+}
+
+impl OpenDisputeRecord {
+    /// Returns the record of the open dispute of the client's `deposit` with id `tx`
+    pub fn new(
+        client: ClientId,
+        tx: TxId,
+        deposit: &Entry,
+        disputed_at: Option<Timestamp>,
+    ) -> Self {
+        Self {
+            client,
+            tx,
+            amount: deposit.amount,
+            account: Some(deposit.account.to_string()),
+            currency: Some(deposit.currency.to_string()),
+            disputed_at: disputed_at.map(|t| t.to_rfc3339()),
         }
     }
+
+    /// Returns (client, tx, disputed deposit, disputed at)
+    pub fn parse(
+        &self,
+    ) -> Result<(ClientId, TxId, Entry, Option<Timestamp>), DeserializationError> {
+        if !self.amount.is_normal() || self.amount.is_sign_negative() {
+            return Err(DeserializationError::InvalidAmount(self.amount));
+        }
+        let account = match self.account.as_deref() {
+            Some(a) if !a.is_empty() => a.parse()?,
+            _ => Account::MAIN,
+        };
+        let currency = match self.currency.as_deref() {
+            Some(c) if !c.is_empty() => c.parse()?,
+            _ => Currency::NONE,
+        };
+        let disputed_at = match self.disputed_at.as_deref() {
+            Some(d) if !d.is_empty() => Some(parse_timestamp(d)?),
+            _ => None,
+        };
+
+        let deposit = Entry {
+            kind: EntryKind::Deposit,
+            amount: self.amount,
+            currency,
+            account,
+            timestamp: None,
+        };
+        Ok((self.client, self.tx, deposit, disputed_at))
+    }
+}
+
+/// Single row of the diff report, one per changed field
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct DiffRecord {
+    client: ClientId,
+    account: String,
+    currency: String,
+    change: &'static str,
+    field: &'static str,
+    before: Option<String>,
+    after: Option<String>,
+    delta: Option<String>,
+}
+
+impl DiffRecord {
+    /// Names of the columns, in order
+    pub const COLUMNS: [&'static str; 8] = [
+        "client", "account", "currency", "change", "field", "before", "after", "delta",
+    ];
+
+    /// Returns the records of the `difference`, one per field
+    pub fn rows(difference: &Difference<BalanceKey>) -> Vec<Self> {
+        let (client, account, currency) = *difference.key();
+        difference
+            .deltas()
+            .iter()
+            .map(|d| Self {
+                client,
+                account: account.to_string(),
+                currency: currency.to_string(),
+                change: difference.change().name(),
+                field: d.field(),
+                before: d.before().clone(),
+                after: d.after().clone(),
+                delta: d.delta().clone(),
+            })
+            .collect()
+    }
+
+    /// Returns the values of the columns, in order
+    pub fn cells(&self) -> Vec<String> {
+        vec![
+            self.client.to_string(),
+            self.account.clone(),
+            self.currency.clone(),
+            self.change.to_owned(),
+            self.field.to_owned(),
+            self.before.clone().unwrap_or_default(),
+            self.after.clone().unwrap_or_default(),
+            self.delta.clone().unwrap_or_default(),
+        ]
+    }
 }
 
-impl From<&Client> for OutputRecord {
-    fn from(c: &Client) -> Self {
-        let available = *c.available();
-        let held = *c.held();
-        let total = available + held;
-        Self::new(*c.id(), available, held, total, *c.locked())
+/// Single row of the diff report of the open disputes, one per changed field
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct DisputeDiffRecord {
+    client: ClientId,
+    tx: TxId,
+    change: &'static str,
+    field: &'static str,
+    before: Option<String>,
+    after: Option<String>,
+    delta: Option<String>,
+}
+
+impl DisputeDiffRecord {
+    /// Names of the columns, in order
+    pub const COLUMNS: [&'static str; 7] = [
+        "client", "tx", "change", "field", "before", "after", "delta",
+    ];
+
+    /// Returns the records of the `difference`, one per field
+    pub fn rows(difference: &Difference<DisputeKey>) -> Vec<Self> {
+        let (client, tx) = *difference.key();
+        difference
+            .deltas()
+            .iter()
+            .map(|d| Self {
+                client,
+                tx,
+                change: difference.change().name(),
+                field: d.field(),
+                before: d.before().clone(),
+                after: d.after().clone(),
+                delta: d.delta().clone(),
+            })
+            .collect()
+    }
+
+    /// Returns the values of the columns, in order
+    pub fn cells(&self) -> Vec<String> {
+        vec![
+            self.client.to_string(),
+            self.tx.to_string(),
+            self.change.to_owned(),
+            self.field.to_owned(),
+            self.before.clone().unwrap_or_default(),
+            self.after.clone().unwrap_or_default(),
+            self.delta.clone().unwrap_or_default(),
+        ]
     }
 }
 
-impl From<Client> for OutputRecord {
-    fn from(c: Client) -> Self {
-        OutputRecord::from(&c)
+/// Envelope of an error, the same on every output channel
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ErrorRecord {
+    code: u16,
+    name: &'static str,
+    category: &'static str,
+    severity: &'static str,
+    message: String,
+
+    /// Type, client and id of the transaction the error concerns, if any
+    r#type: Option<String>,
+    client: Option<ClientId>,
+    tx: Option<TxId>,
+}
+
+impl ErrorRecord {
+    /// Code of the errors that have none of their own, e.g. an unreadable file
+    const OTHER: u16 = 9001;
+
+    /// Returns the envelope of the coded `error`
+    pub fn new(error: &dyn ErrorCode) -> Self {
+        Self {
+            code: error.code(),
+            name: error.name(),
+            category: error.category().name(),
+            severity: error.severity().name(),
+            message: error.to_string(),
+            r#type: None,
+            client: None,
+            tx: None,
+        }
+    }
+
+    /// Returns the envelope of an error without a code of its own
+    pub fn other(message: String) -> Self {
+        Self {
+            code: Self::OTHER,
+            name: "other",
+            category: "system",
+            severity: "critical",
+            message,
+            r#type: None,
+            client: None,
+            tx: None,
+        }
+    }
+
+    /// Sets the message, e.g. to add the context of the error
+    pub fn with_message(mut self, message: String) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// Sets the input record of the transaction the error concerns
+    pub fn with_record(mut self, record: &InputRecord) -> Self {
+        self.r#type = Some(record.r#type().clone());
+        self.client = Some(*record.client());
+        self.tx = Some(*record.tx());
+        self
+    }
+}
+
+impl fmt::Display for ErrorRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} {}]", self.code, self.name)?;
+        if let (Some(r#type), Some(tx)) = (&self.r#type, self.tx) {
+            write!(f, " {} {}", r#type, tx)?;
+        }
+        write!(f, ": {}", self.message)
     }
 }
@@ -1,5 +1,73 @@
+use crate::{
+    limits::Limit,
+    transaction::{ClientId, TxId},
+};
 use thiserror::Error;
 
+/// Kind of the error, so that the tools consuming the error output can route it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// Malformed input
+    Validation,
+    /// Transaction rejected by a limit, a policy or a lack of funds
+    BusinessRule,
+    /// Transaction that does not fit the state of the client, e.g. a duplicate id or a dispute of a missing deposit
+    StateConflict,
+    /// Failure of the engine itself, or of its storage
+    System,
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Validation => "validation",
+            Category::BusinessRule => "business_rule",
+            Category::StateConflict => "state_conflict",
+            Category::System => "system",
+        }
+    }
+}
+
+/// How serious the error is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Transaction rightly rejected, the processing goes on
+    Warning,
+    /// Input that needs fixing
+    Error,
+    /// Results of the run can not be trusted
+    Critical,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// Stable identification of the error, independent of its message
+pub trait ErrorCode: std::fmt::Display {
+    /// Numeric code, unique across all the errors
+    fn code(&self) -> u16;
+
+    /// Name of the error, unique across all the errors
+    fn name(&self) -> &'static str;
+
+    fn category(&self) -> Category;
+
+    fn severity(&self) -> Severity {
+        match self.category() {
+            Category::BusinessRule => Severity::Warning,
+            Category::Validation | Category::StateConflict => Severity::Error,
+            Category::System => Severity::Critical,
+        }
+    }
+}
+
 #[derive(Error, Debug, PartialEq)]
 pub enum DeserializationError {
     #[error("`{0}` is not a known transaction type")]
@@ -10,28 +78,497 @@ pub enum DeserializationError {
 
     #[error("`{0}` is not valid value. Amount has to be non-zero, positive, finite value")]
     InvalidAmount(f64),
+
+    #[error("`{0}` is not valid timestamp. Expected RFC 3339 date and time or seconds since the Unix epoch")]
+    InvalidTimestamp(String),
+
+    #[error("`{0}` is not valid currency. Expected three-letter currency code")]
+    InvalidCurrency(String),
+
+    #[error("Transaction type `{0}` needs target currency")]
+    TargetCurrencyMissing(String),
+
+    #[error("`{0}` is not valid account name. Expected up to 16 letters, digits, `_` or `-`")]
+    InvalidAccount(String),
+
+    #[error("Transaction type `{0}` needs target account")]
+    TargetAccountMissing(String),
+
+    #[error("`{0}` is not valid balance. Balance has to be finite value")]
+    InvalidBalance(f64),
+
+    #[error("`{0}` is not valid exchange rate. Rate has to be non-zero, positive, finite value")]
+    InvalidRate(f64),
+
+    #[error("Conversion needs target currency other than source currency `{0}`")]
+    SameCurrency(String),
+
+    #[error("`{0}` is not valid held balance. Held funds can not be negative")]
+    NegativeHeld(f64),
+
+    #[error("Transfer needs target account other than source account `{0}`")]
+    SameAccount(String),
+
+    #[error("Malformed record: {0}")]
+    MalformedRecord(String),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LedgerError {
+    #[error("Ledger system accounts do not match the funds of the clients in currency `{0}`")]
+    Unbalanced(String),
+
+    #[error(
+        "Ledger does not agree with the balance of account `{1}` of client `{0}` in currency `{2}`"
+    )]
+    ClientMismatch(ClientId, String, String),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AuditError {
+    #[error("Audit log entry `{0}` is malformed: {1}")]
+    Malformed(u64, String),
+
+    #[error("Audit log entry `{0}` does not follow the previous entry - entries were removed, inserted or reordered")]
+    BrokenChain(u64),
+
+    #[error("Audit log entry `{0}` was tampered with - its hash does not match its content")]
+    Tampered(u64),
+
+    #[error(
+        "Audit log ends at entry `{1}`, before its head `{0}` - entries were removed from its end"
+    )]
+    Truncated(u64, u64),
+
+    #[error("Audit log entry `{0}` does not match its head - the chain was rewritten")]
+    HeadMismatch(u64),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum OutputError {
+    #[error("Can not write event: {0}")]
+    Event(String),
+
+    #[error("Can not write posting: {0}")]
+    Posting(String),
 }
 
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum RepositoryError {
     #[error("Withdrawal operation on client `{0}` would result in a negative amount")]
-    InsufficientFunds(u16),
+    InsufficientFunds(ClientId),
 
     #[error("Transaction id `{0}` already exists")]
-    DuplicateTransactionId(u32),
+    DuplicateTransactionId(TxId),
 
-    #[error("REferenced transaction ID `{0}` does not exist under client `{1}`")]
-    TransactionDoesNotExist(u32, u16),
+    #[error("Referenced transaction ID `{0}` does not exist under client `{1}`")]
+    TransactionDoesNotExist(TxId, ClientId),
 
     #[error("Wrong reference transaction type")]
     WrongReferenceTransactionType,
 
     #[error("Transaction ID `{0}` is already disputed")]
-    TransactionAlreadyDisputed(u32),
+    TransactionAlreadyDisputed(TxId),
 
     #[error("Transaction ID `{0}` is not disputed")]
-    TransactionNotDisputed(u32),
+    TransactionNotDisputed(TxId),
+
+    #[error("Transaction ID `{0}` is disputed and can not be reversed")]
+    TransactionDisputed(TxId),
+
+    #[error("Transaction ID `{0}` is reversed")]
+    TransactionReversed(TxId),
 
     #[error("Client ID `{0}` is locked")]
-    ClientLocked(u16),
+    ClientLocked(ClientId),
+
+    #[error("Account `{1}` of client ID `{0}` is locked")]
+    AccountLocked(ClientId, String),
+
+    #[error("Policy `{0}` rejected the transaction: {1}")]
+    PolicyViolation(String, String),
+
+    #[error("Transaction on client `{0}` exceeds `{1}` limit")]
+    LimitExceeded(ClientId, Limit),
+
+    #[error("Transaction ID `{0}` is older than the last transaction of client `{1}`")]
+    NonMonotonicTimestamp(TxId, ClientId),
+
+    #[error("Transaction ID `{0}` can no longer be disputed")]
+    DisputeWindowClosed(TxId),
+
+    #[error("Authorization ID `{0}` does not exist under client `{1}`")]
+    AuthorizationDoesNotExist(TxId, ClientId),
+
+    #[error("Capture of authorization ID `{0}` exceeds the held amount")]
+    CaptureExceedsAuthorization(TxId),
+
+    #[error("No exchange rate from `{0}` to `{1}`")]
+    RateNotAvailable(String, String),
+
+    #[error(
+        "Opening balance of account `{1}` of client `{0}` in currency `{2}` is already loaded"
+    )]
+    DuplicateOpeningBalance(ClientId, String, String),
+
+    #[error("Transaction history storage failed: {0}")]
+    Storage(String),
+
+    #[error("Expiry of transaction ID `{0}` of client `{1}` failed: {2}")]
+    ExpiryFailed(TxId, ClientId, Box<RepositoryError>),
+
+    #[error("Opening balance of account `{1}` of client `{0}` in currency `{2}` is negative beyond the client's overdraft")]
+    OpeningBalanceOverdrawn(ClientId, String, String),
+}
+
+impl From<std::io::Error> for RepositoryError {
+    fn from(e: std::io::Error) -> Self {
+        RepositoryError::Storage(e.to_string())
+    }
+}
+
+impl ErrorCode for DeserializationError {
+    fn code(&self) -> u16 {
+        match self {
+            DeserializationError::UnknownTransactionType(_) => 1001,
+            DeserializationError::AmountMissing(_) => 1002,
+            DeserializationError::InvalidAmount(_) => 1003,
+            DeserializationError::InvalidTimestamp(_) => 1004,
+            DeserializationError::InvalidCurrency(_) => 1005,
+            DeserializationError::TargetCurrencyMissing(_) => 1006,
+            DeserializationError::InvalidAccount(_) => 1007,
+            DeserializationError::TargetAccountMissing(_) => 1008,
+            DeserializationError::InvalidBalance(_) => 1009,
+            DeserializationError::InvalidRate(_) => 1010,
+            DeserializationError::SameCurrency(_) => 1011,
+            DeserializationError::NegativeHeld(_) => 1012,
+            DeserializationError::SameAccount(_) => 1013,
+            DeserializationError::MalformedRecord(_) => 1014,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DeserializationError::UnknownTransactionType(_) => "unknown_transaction_type",
+            DeserializationError::AmountMissing(_) => "amount_missing",
+            DeserializationError::InvalidAmount(_) => "invalid_amount",
+            DeserializationError::InvalidTimestamp(_) => "invalid_timestamp",
+            DeserializationError::InvalidCurrency(_) => "invalid_currency",
+            DeserializationError::TargetCurrencyMissing(_) => "target_currency_missing",
+            DeserializationError::InvalidAccount(_) => "invalid_account",
+            DeserializationError::TargetAccountMissing(_) => "target_account_missing",
+            DeserializationError::InvalidBalance(_) => "invalid_balance",
+            DeserializationError::InvalidRate(_) => "invalid_rate",
+            DeserializationError::SameCurrency(_) => "same_currency",
+            DeserializationError::NegativeHeld(_) => "negative_held",
+            DeserializationError::SameAccount(_) => "same_account",
+            DeserializationError::MalformedRecord(_) => "malformed_record",
+        }
+    }
+
+    fn category(&self) -> Category {
+        Category::Validation
+    }
+}
+
+impl ErrorCode for RepositoryError {
+    fn code(&self) -> u16 {
+        match self {
+            RepositoryError::InsufficientFunds(_) => 2001,
+            RepositoryError::DuplicateTransactionId(_) => 2002,
+            RepositoryError::TransactionDoesNotExist(_, _) => 2003,
+            RepositoryError::WrongReferenceTransactionType => 2004,
+            RepositoryError::TransactionAlreadyDisputed(_) => 2005,
+            RepositoryError::TransactionNotDisputed(_) => 2006,
+            RepositoryError::TransactionDisputed(_) => 2007,
+            RepositoryError::TransactionReversed(_) => 2008,
+            RepositoryError::ClientLocked(_) => 2009,
+            RepositoryError::AccountLocked(_, _) => 2010,
+            RepositoryError::PolicyViolation(_, _) => 2011,
+            RepositoryError::LimitExceeded(_, _) => 2012,
+            RepositoryError::NonMonotonicTimestamp(_, _) => 2013,
+            RepositoryError::DisputeWindowClosed(_) => 2014,
+            RepositoryError::AuthorizationDoesNotExist(_, _) => 2015,
+            RepositoryError::CaptureExceedsAuthorization(_) => 2016,
+            RepositoryError::RateNotAvailable(_, _) => 2017,
+            RepositoryError::DuplicateOpeningBalance(_, _, _) => 2018,
+            RepositoryError::Storage(_) => 2019,
+            RepositoryError::ExpiryFailed(_, _, _) => 2020,
+            RepositoryError::OpeningBalanceOverdrawn(_, _, _) => 2021,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            RepositoryError::InsufficientFunds(_) => "insufficient_funds",
+            RepositoryError::DuplicateTransactionId(_) => "duplicate_transaction_id",
+            RepositoryError::TransactionDoesNotExist(_, _) => "transaction_does_not_exist",
+            RepositoryError::WrongReferenceTransactionType => "wrong_reference_transaction_type",
+            RepositoryError::TransactionAlreadyDisputed(_) => "transaction_already_disputed",
+            RepositoryError::TransactionNotDisputed(_) => "transaction_not_disputed",
+            RepositoryError::TransactionDisputed(_) => "transaction_disputed",
+            RepositoryError::TransactionReversed(_) => "transaction_reversed",
+            RepositoryError::ClientLocked(_) => "client_locked",
+            RepositoryError::AccountLocked(_, _) => "account_locked",
+            RepositoryError::PolicyViolation(_, _) => "policy_violation",
+            RepositoryError::LimitExceeded(_, _) => "limit_exceeded",
+            RepositoryError::NonMonotonicTimestamp(_, _) => "non_monotonic_timestamp",
+            RepositoryError::DisputeWindowClosed(_) => "dispute_window_closed",
+            RepositoryError::AuthorizationDoesNotExist(_, _) => "authorization_does_not_exist",
+            RepositoryError::CaptureExceedsAuthorization(_) => "capture_exceeds_authorization",
+            RepositoryError::RateNotAvailable(_, _) => "rate_not_available",
+            RepositoryError::DuplicateOpeningBalance(_, _, _) => "duplicate_opening_balance",
+            RepositoryError::Storage(_) => "storage",
+            RepositoryError::ExpiryFailed(_, _, _) => "expiry_failed",
+            RepositoryError::OpeningBalanceOverdrawn(_, _, _) => "opening_balance_overdrawn",
+        }
+    }
+
+    fn category(&self) -> Category {
+        match self {
+            RepositoryError::InsufficientFunds(_)
+            | RepositoryError::ClientLocked(_)
+            | RepositoryError::AccountLocked(_, _)
+            | RepositoryError::PolicyViolation(_, _)
+            | RepositoryError::LimitExceeded(_, _)
+            | RepositoryError::DisputeWindowClosed(_)
+            | RepositoryError::CaptureExceedsAuthorization(_)
+            | RepositoryError::RateNotAvailable(_, _)
+            | RepositoryError::OpeningBalanceOverdrawn(_, _, _) => Category::BusinessRule,
+            RepositoryError::DuplicateTransactionId(_)
+            | RepositoryError::TransactionDoesNotExist(_, _)
+            | RepositoryError::WrongReferenceTransactionType
+            | RepositoryError::TransactionAlreadyDisputed(_)
+            | RepositoryError::TransactionNotDisputed(_)
+            | RepositoryError::TransactionDisputed(_)
+            | RepositoryError::TransactionReversed(_)
+            | RepositoryError::NonMonotonicTimestamp(_, _)
+            | RepositoryError::AuthorizationDoesNotExist(_, _)
+            | RepositoryError::DuplicateOpeningBalance(_, _, _) => Category::StateConflict,
+            RepositoryError::Storage(_) | RepositoryError::ExpiryFailed(_, _, _) => {
+                Category::System
+            }
+        }
+    }
+}
+
+impl ErrorCode for LedgerError {
+    fn code(&self) -> u16 {
+        match self {
+            LedgerError::Unbalanced(_) => 3001,
+            LedgerError::ClientMismatch(_, _, _) => 3002,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LedgerError::Unbalanced(_) => "ledger_unbalanced",
+            LedgerError::ClientMismatch(_, _, _) => "ledger_client_mismatch",
+        }
+    }
+
+    fn category(&self) -> Category {
+        Category::System
+    }
+}
+
+impl ErrorCode for AuditError {
+    fn code(&self) -> u16 {
+        match self {
+            AuditError::Malformed(_, _) => 4001,
+            AuditError::BrokenChain(_) => 4002,
+            AuditError::Tampered(_) => 4003,
+            AuditError::Truncated(_, _) => 4004,
+            AuditError::HeadMismatch(_) => 4005,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            AuditError::Malformed(_, _) => "audit_entry_malformed",
+            AuditError::BrokenChain(_) => "audit_chain_broken",
+            AuditError::Tampered(_) => "audit_entry_tampered",
+            AuditError::Truncated(_, _) => "audit_log_truncated",
+            AuditError::HeadMismatch(_) => "audit_head_mismatch",
+        }
+    }
+
+    fn category(&self) -> Category {
+        match self {
+            AuditError::Malformed(_, _) => Category::Validation,
+            AuditError::BrokenChain(_)
+            | AuditError::Tampered(_)
+            | AuditError::Truncated(_, _)
+            | AuditError::HeadMismatch(_) => Category::System,
+        }
+    }
+}
+
+impl ErrorCode for OutputError {
+    fn code(&self) -> u16 {
+        match self {
+            OutputError::Event(_) => 5001,
+            OutputError::Posting(_) => 5002,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            OutputError::Event(_) => "event_write_failed",
+            OutputError::Posting(_) => "posting_write_failed",
+        }
+    }
+
+    fn category(&self) -> Category {
+        Category::System
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AuditError, Category, DeserializationError, ErrorCode, LedgerError, OutputError,
+        RepositoryError,
+    };
+    use crate::limits::Limit;
+    use std::collections::HashSet;
+
+    /// One error of every kind
+    fn all() -> Vec<Box<dyn ErrorCode>> {
+        let s = String::new;
+        vec![
+            Box::new(DeserializationError::UnknownTransactionType(s())),
+            Box::new(DeserializationError::AmountMissing(s())),
+            Box::new(DeserializationError::InvalidAmount(0.0)),
+            Box::new(DeserializationError::InvalidTimestamp(s())),
+            Box::new(DeserializationError::InvalidCurrency(s())),
+            Box::new(DeserializationError::TargetCurrencyMissing(s())),
+            Box::new(DeserializationError::InvalidAccount(s())),
+            Box::new(DeserializationError::TargetAccountMissing(s())),
+            Box::new(DeserializationError::InvalidBalance(0.0)),
+            Box::new(DeserializationError::InvalidRate(0.0)),
+            Box::new(DeserializationError::SameCurrency(s())),
+            Box::new(DeserializationError::NegativeHeld(0.0)),
+            Box::new(DeserializationError::SameAccount(s())),
+            Box::new(DeserializationError::MalformedRecord(s())),
+            Box::new(RepositoryError::InsufficientFunds(1)),
+            Box::new(RepositoryError::DuplicateTransactionId(1)),
+            Box::new(RepositoryError::TransactionDoesNotExist(1, 1)),
+            Box::new(RepositoryError::WrongReferenceTransactionType),
+            Box::new(RepositoryError::TransactionAlreadyDisputed(1)),
+            Box::new(RepositoryError::TransactionNotDisputed(1)),
+            Box::new(RepositoryError::TransactionDisputed(1)),
+            Box::new(RepositoryError::TransactionReversed(1)),
+            Box::new(RepositoryError::ClientLocked(1)),
+            Box::new(RepositoryError::AccountLocked(1, s())),
+            Box::new(RepositoryError::PolicyViolation(s(), s())),
+            Box::new(RepositoryError::LimitExceeded(1, Limit::MaxWithdrawal)),
+            Box::new(RepositoryError::NonMonotonicTimestamp(1, 1)),
+            Box::new(RepositoryError::DisputeWindowClosed(1)),
+            Box::new(RepositoryError::AuthorizationDoesNotExist(1, 1)),
+            Box::new(RepositoryError::CaptureExceedsAuthorization(1)),
+            Box::new(RepositoryError::RateNotAvailable(s(), s())),
+            Box::new(RepositoryError::DuplicateOpeningBalance(1, s(), s())),
+            Box::new(RepositoryError::Storage(s())),
+            Box::new(RepositoryError::ExpiryFailed(
+                1,
+                1,
+                Box::new(RepositoryError::Storage(s())),
+            )),
+            Box::new(RepositoryError::OpeningBalanceOverdrawn(1, s(), s())),
+            Box::new(LedgerError::Unbalanced(s())),
+            Box::new(LedgerError::ClientMismatch(1, s(), s())),
+            Box::new(AuditError::Malformed(1, s())),
+            Box::new(AuditError::BrokenChain(1)),
+            Box::new(AuditError::Tampered(1)),
+            Box::new(AuditError::Truncated(1, 1)),
+            Box::new(AuditError::HeadMismatch(1)),
+            Box::new(OutputError::Event(s())),
+            Box::new(OutputError::Posting(s())),
+        ]
+    }
+
+    #[test]
+    fn codes_and_names_are_unique() {
+        let errors = all();
+        let codes: HashSet<_> = errors.iter().map(|e| e.code()).collect();
+        let names: HashSet<_> = errors.iter().map(|e| e.name()).collect();
+
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(names.len(), errors.len());
+    }
+
+    #[test]
+    fn codes_names_and_categories_are_stable() {
+        let pinned: Vec<_> = all()
+            .iter()
+            .map(|e| (e.code(), e.name(), e.category()))
+            .collect();
+
+        assert_eq!(
+            pinned,
+            vec![
+                (1001, "unknown_transaction_type", Category::Validation),
+                (1002, "amount_missing", Category::Validation),
+                (1003, "invalid_amount", Category::Validation),
+                (1004, "invalid_timestamp", Category::Validation),
+                (1005, "invalid_currency", Category::Validation),
+                (1006, "target_currency_missing", Category::Validation),
+                (1007, "invalid_account", Category::Validation),
+                (1008, "target_account_missing", Category::Validation),
+                (1009, "invalid_balance", Category::Validation),
+                (1010, "invalid_rate", Category::Validation),
+                (1011, "same_currency", Category::Validation),
+                (1012, "negative_held", Category::Validation),
+                (1013, "same_account", Category::Validation),
+                (1014, "malformed_record", Category::Validation),
+                (2001, "insufficient_funds", Category::BusinessRule),
+                (2002, "duplicate_transaction_id", Category::StateConflict),
+                (2003, "transaction_does_not_exist", Category::StateConflict),
+                (
+                    2004,
+                    "wrong_reference_transaction_type",
+                    Category::StateConflict
+                ),
+                (
+                    2005,
+                    "transaction_already_disputed",
+                    Category::StateConflict
+                ),
+                (2006, "transaction_not_disputed", Category::StateConflict),
+                (2007, "transaction_disputed", Category::StateConflict),
+                (2008, "transaction_reversed", Category::StateConflict),
+                (2009, "client_locked", Category::BusinessRule),
+                (2010, "account_locked", Category::BusinessRule),
+                (2011, "policy_violation", Category::BusinessRule),
+                (2012, "limit_exceeded", Category::BusinessRule),
+                (2013, "non_monotonic_timestamp", Category::StateConflict),
+                (2014, "dispute_window_closed", Category::BusinessRule),
+                (
+                    2015,
+                    "authorization_does_not_exist",
+                    Category::StateConflict
+                ),
+                (
+                    2016,
+                    "capture_exceeds_authorization",
+                    Category::BusinessRule
+                ),
+                (2017, "rate_not_available", Category::BusinessRule),
+                (2018, "duplicate_opening_balance", Category::StateConflict),
+                (2019, "storage", Category::System),
+                (2020, "expiry_failed", Category::System),
+                (2021, "opening_balance_overdrawn", Category::BusinessRule),
+                (3001, "ledger_unbalanced", Category::System),
+                (3002, "ledger_client_mismatch", Category::System),
+                (4001, "audit_entry_malformed", Category::Validation),
+                (4002, "audit_chain_broken", Category::System),
+                (4003, "audit_entry_tampered", Category::System),
+                (4004, "audit_log_truncated", Category::System),
+                (4005, "audit_head_mismatch", Category::System),
+                (5001, "event_write_failed", Category::System),
+                (5002, "posting_write_failed", Category::System),
+            ]
+        );
+    }
 }
@@ -0,0 +1,241 @@
+//! Domain events - notifications about every change of the client state
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    transaction::{ClientId, Timestamp, TxId},
+};
+use getset::Getters;
+use std::{fmt, rc::Rc};
+
+/// Represents a single change of the client state
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Event {
+    /// Id of the client this event concerns
+    #[get = "pub"]
+    client: ClientId,
+
+    /// Account of the client this event concerns
+    #[get = "pub"]
+    account: Account,
+
+    /// Id of the transaction that caused this event
+    #[get = "pub"]
+    tx: TxId,
+
+    /// Timestamp of the transaction that caused this event, if known
+    #[get = "pub"]
+    timestamp: Option<Timestamp>,
+
+    /// What has happened
+    #[get = "pub"]
+    kind: EventKind,
+}
+
+impl Event {
+    pub fn new(client: ClientId, tx: TxId, timestamp: Option<Timestamp>, kind: EventKind) -> Self {
+        Self {
+            client,
+            account: Account::MAIN,
+            tx,
+            timestamp,
+            kind,
+        }
+    }
+
+    /// Sets the account this event concerns
+    pub fn with_account(mut self, account: Account) -> Self {
+        self.account = account;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    FundsDeposited {
+        amount: f64,
+        currency: Currency,
+    },
+    FundsWithdrawn {
+        amount: f64,
+        currency: Currency,
+    },
+    FundsHeld {
+        amount: f64,
+        currency: Currency,
+    },
+    FundsReleased {
+        amount: f64,
+        currency: Currency,
+    },
+    ChargedBack {
+        amount: f64,
+        currency: Currency,
+    },
+    ConvertedFrom {
+        amount: f64,
+        currency: Currency,
+    },
+    ConvertedTo {
+        amount: f64,
+        currency: Currency,
+    },
+    TransferredOut {
+        amount: f64,
+        currency: Currency,
+    },
+    TransferredIn {
+        amount: f64,
+        currency: Currency,
+    },
+    FundsAuthorized {
+        amount: f64,
+        currency: Currency,
+    },
+    FundsCaptured {
+        amount: f64,
+        currency: Currency,
+    },
+    AuthorizationReleased {
+        amount: f64,
+        currency: Currency,
+    },
+    DepositReversed {
+        amount: f64,
+        currency: Currency,
+    },
+    WithdrawalReversed {
+        amount: f64,
+        currency: Currency,
+    },
+    /// Balance loaded from the opening balances, before any transaction. Caused by no transaction, so its `tx` is 0.
+    BalanceOpened {
+        available: f64,
+        held: f64,
+        currency: Currency,
+    },
+    AccountLocked,
+    DisputeExpired,
+    AuthorizationExpired,
+    Annotated {
+        policy: &'static str,
+        note: String,
+    },
+}
+
+impl EventKind {
+    /// Returns the name of the event, as used in the events output
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::FundsDeposited { .. } => "funds_deposited",
+            EventKind::FundsWithdrawn { .. } => "funds_withdrawn",
+            EventKind::FundsHeld { .. } => "funds_held",
+            EventKind::FundsReleased { .. } => "funds_released",
+            EventKind::ChargedBack { .. } => "charged_back",
+            EventKind::ConvertedFrom { .. } => "converted_from",
+            EventKind::ConvertedTo { .. } => "converted_to",
+            EventKind::TransferredOut { .. } => "transferred_out",
+            EventKind::TransferredIn { .. } => "transferred_in",
+            EventKind::FundsAuthorized { .. } => "funds_authorized",
+            EventKind::FundsCaptured { .. } => "funds_captured",
+            EventKind::AuthorizationReleased { .. } => "authorization_released",
+            EventKind::DepositReversed { .. } => "deposit_reversed",
+            EventKind::WithdrawalReversed { .. } => "withdrawal_reversed",
+            EventKind::BalanceOpened { .. } => "balance_opened",
+            EventKind::AccountLocked => "account_locked",
+            EventKind::DisputeExpired => "dispute_expired",
+            EventKind::AuthorizationExpired => "authorization_expired",
+            EventKind::Annotated { .. } => "annotated",
+        }
+    }
+
+    /// Returns the amount of funds moved by this event, if any
+    pub fn amount(&self) -> Option<f64> {
+        match self {
+            EventKind::FundsDeposited { amount, .. }
+            | EventKind::FundsWithdrawn { amount, .. }
+            | EventKind::FundsHeld { amount, .. }
+            | EventKind::FundsReleased { amount, .. }
+            | EventKind::ChargedBack { amount, .. }
+            | EventKind::ConvertedFrom { amount, .. }
+            | EventKind::ConvertedTo { amount, .. }
+            | EventKind::TransferredOut { amount, .. }
+            | EventKind::TransferredIn { amount, .. }
+            | EventKind::FundsAuthorized { amount, .. }
+            | EventKind::FundsCaptured { amount, .. }
+            | EventKind::AuthorizationReleased { amount, .. }
+            | EventKind::DepositReversed { amount, .. }
+            | EventKind::WithdrawalReversed { amount, .. } => Some(*amount),
+            EventKind::BalanceOpened {
+                available, held, ..
+            } => Some(available + held),
+            EventKind::AccountLocked
+            | EventKind::DisputeExpired
+            | EventKind::AuthorizationExpired
+            | EventKind::Annotated { .. } => None,
+        }
+    }
+
+    /// Returns the currency of funds moved by this event, if any
+    pub fn currency(&self) -> Option<Currency> {
+        match self {
+            EventKind::FundsDeposited { currency, .. }
+            | EventKind::FundsWithdrawn { currency, .. }
+            | EventKind::FundsHeld { currency, .. }
+            | EventKind::FundsReleased { currency, .. }
+            | EventKind::ChargedBack { currency, .. }
+            | EventKind::ConvertedFrom { currency, .. }
+            | EventKind::ConvertedTo { currency, .. }
+            | EventKind::TransferredOut { currency, .. }
+            | EventKind::TransferredIn { currency, .. }
+            | EventKind::FundsAuthorized { currency, .. }
+            | EventKind::FundsCaptured { currency, .. }
+            | EventKind::AuthorizationReleased { currency, .. }
+            | EventKind::DepositReversed { currency, .. }
+            | EventKind::WithdrawalReversed { currency, .. }
+            | EventKind::BalanceOpened { currency, .. } => Some(*currency),
+            EventKind::AccountLocked
+            | EventKind::DisputeExpired
+            | EventKind::AuthorizationExpired
+            | EventKind::Annotated { .. } => None,
+        }
+    }
+
+    /// Returns the note attached to this event, if any
+    pub fn note(&self) -> Option<String> {
+        match self {
+            EventKind::Annotated { policy, note } => Some(format!("{}: {}", policy, note)),
+            EventKind::BalanceOpened {
+                available, held, ..
+            } => Some(format!("available={:.4} held={:.4}", available, held)),
+            _ => None,
+        }
+    }
+}
+
+/// Callback notified about every event emitted by the `Repository`
+pub type Subscriber = Rc<dyn Fn(&Event)>;
+
+/// List of subscribers registered in the `Repository`
+#[derive(Clone, Default)]
+pub struct Subscribers(Vec<Subscriber>);
+
+impl Subscribers {
+    /// Registers new subscriber
+    pub fn push(&mut self, subscriber: Subscriber) {
+        self.0.push(subscriber);
+    }
+
+    /// Notifies all the subscribers about the `event`
+    pub fn notify(&self, event: &Event) {
+        for s in &self.0 {
+            s(event);
+        }
+    }
+}
+
+impl fmt::Debug for Subscribers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Subscribers({})", self.0.len())
+    }
+}
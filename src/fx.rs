@@ -0,0 +1,250 @@
+//! Currency conversion - FX rate table, quoting and P&L
+
+use crate::{
+    config::FxConfig, currency::Currency, dto::RateRecord, errors::RepositoryError,
+    transaction::Timestamp,
+};
+use color_eyre::{eyre::Context, Result};
+use csv::Trim;
+use getset::Getters;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+};
+
+/// Rates of a single currency pair, as (effective since, rate)
+type Rates = Vec<(Option<Timestamp>, f64)>;
+
+/// Exchange rates per currency pair, each pair ordered by the time the rate became effective
+#[derive(Debug, Clone, Default)]
+pub struct RateTable {
+    /// Rates without effective time come first, as `None` is ordered before any timestamp
+    rates: HashMap<(Currency, Currency), Rates>,
+}
+
+impl RateTable {
+    /// Loads the rates from CSV file at `path`
+    pub fn load(path: &str) -> Result<Self> {
+        let file =
+            File::open(path).wrap_err_with(|| format!("Can not open rates file `{}`", path))?;
+        let mut rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(file);
+
+        let mut table = Self::default();
+        for result in rdr.deserialize() {
+            let record: RateRecord =
+                result.wrap_err_with(|| format!("Invalid rates file `{}`", path))?;
+            let (from, to, rate, effective) = record
+                .parse()
+                .wrap_err_with(|| format!("Invalid rates file `{}`", path))?;
+            table.insert(from, to, rate, effective);
+        }
+
+        Ok(table)
+    }
+
+    /// Adds the `rate` of `from` to `to` conversion, effective since `effective`
+    pub fn insert(
+        &mut self,
+        from: Currency,
+        to: Currency,
+        rate: f64,
+        effective: Option<Timestamp>,
+    ) {
+        let rates = self.rates.entry((from, to)).or_default();
+        let i = rates.partition_point(|(e, _)| *e <= effective);
+        rates.insert(i, (effective, rate));
+    }
+
+    /// Returns the rate of `from` to `to` conversion effective at `at`, or the latest rate if `at` is not known.
+    /// The inverse of the `to` to `from` rate is used if there is no direct one.
+    pub fn rate(&self, from: Currency, to: Currency, at: Option<Timestamp>) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+
+        let effective = |rates: &Rates| match at {
+            Some(at) => {
+                let i = rates.partition_point(|(e, _)| e.map_or(true, |e| e <= at));
+                i.checked_sub(1).map(|i| rates[i].1)
+            }
+            None => rates.last().map(|(_, r)| *r),
+        };
+
+        self.rates.get(&(from, to)).and_then(effective).or_else(|| {
+            self.rates
+                .get(&(to, from))
+                .and_then(effective)
+                .map(|r| 1.0 / r)
+        })
+    }
+}
+
+/// Price of a single conversion
+#[derive(Debug, Clone, Copy, PartialEq, Getters)]
+pub struct Quote {
+    /// Mid-market rate used
+    #[get = "pub"]
+    rate: f64,
+
+    /// Amount credited to the client, in the target currency
+    #[get = "pub"]
+    amount: f64,
+
+    /// Gain of the engine on this conversion (spread and rounding), in the target currency
+    #[get = "pub"]
+    pnl: f64,
+}
+
+impl Quote {
+    #[cfg(test)]
+    pub fn new(rate: f64, amount: f64, pnl: f64) -> Self {
+        Self { rate, amount, pnl }
+    }
+}
+
+/// FX P&L accumulated in a single currency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Getters)]
+pub struct Pnl {
+    /// Number of conversions into this currency
+    #[get = "pub"]
+    conversions: usize,
+
+    /// Total gain of the engine
+    #[get = "pub"]
+    total: f64,
+}
+
+/// Quotes the conversions and keeps track of the FX P&L
+#[derive(Debug, Clone, Default)]
+pub struct Fx {
+    rates: RateTable,
+    config: FxConfig,
+    pnl: BTreeMap<Currency, Pnl>,
+}
+
+impl Fx {
+    pub fn new(config: FxConfig) -> Self {
+        Self {
+            rates: RateTable::default(),
+            config,
+            pnl: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces the rate table
+    pub fn set_rates(&mut self, rates: RateTable) {
+        self.rates = rates;
+    }
+
+    /// Prices conversion of `amount` from `from` to `to` at `at`
+    pub fn quote(
+        &self,
+        from: Currency,
+        to: Currency,
+        amount: f64,
+        at: Option<Timestamp>,
+    ) -> Result<Quote, RepositoryError> {
+        let rate = self
+            .rates
+            .rate(from, to, at)
+            .ok_or_else(|| RepositoryError::RateNotAvailable(from.to_string(), to.to_string()))?;
+
+        let mid = amount * rate;
+        let credited = self
+            .config
+            .rounding()
+            .round(mid * (1.0 - self.config.spread()), *self.config.decimals());
+
+        Ok(Quote {
+            rate,
+            amount: credited,
+            pnl: mid - credited,
+        })
+    }
+
+    /// Records the P&L of accepted conversion into `currency`
+    pub fn record(&mut self, currency: Currency, quote: &Quote) {
+        let pnl = self.pnl.entry(currency).or_default();
+        pnl.conversions += 1;
+        pnl.total += quote.pnl;
+    }
+
+    /// Returns the P&L per target currency
+    pub fn pnl(&self) -> &BTreeMap<Currency, Pnl> {
+        &self.pnl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fx, RateTable};
+    use crate::{config::FxConfig, currency::Currency, errors::RepositoryError};
+    use chrono::{TimeZone, Utc};
+
+    fn eur() -> Currency {
+        "EUR".parse().unwrap()
+    }
+
+    fn usd() -> Currency {
+        "USD".parse().unwrap()
+    }
+
+    #[test]
+    fn rate_effective_at_given_time_is_used() {
+        let mut table = RateTable::default();
+        let t = |s| Some(Utc.timestamp_opt(s, 0).unwrap());
+        table.insert(eur(), usd(), 1.2, t(200));
+        table.insert(eur(), usd(), 1.1, t(100));
+        table.insert(eur(), usd(), 1.0, None);
+
+        assert_eq!(table.rate(eur(), usd(), t(50)), Some(1.0));
+        assert_eq!(table.rate(eur(), usd(), t(100)), Some(1.1));
+        assert_eq!(table.rate(eur(), usd(), t(199)), Some(1.1));
+        assert_eq!(table.rate(eur(), usd(), t(300)), Some(1.2));
+        assert_eq!(table.rate(eur(), usd(), None), Some(1.2));
+    }
+
+    #[test]
+    fn inverse_rate_is_used_if_there_is_no_direct_one() {
+        let mut table = RateTable::default();
+        table.insert(eur(), usd(), 1.25, None);
+
+        assert_eq!(table.rate(usd(), eur(), None), Some(0.8));
+    }
+
+    #[test]
+    fn missing_rate_results_in_error() {
+        let fx = Fx::new(FxConfig::default());
+
+        assert_eq!(
+            fx.quote(eur(), usd(), 1.0, None),
+            Err(RepositoryError::RateNotAvailable(
+                "EUR".to_owned(),
+                "USD".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn quote_applies_spread_and_rounding() {
+        let config: FxConfig = toml::from_str(
+            r#"
+            spread = 0.01
+            decimals = 2
+            rounding = "down"
+            "#,
+        )
+        .unwrap();
+        let mut fx = Fx::new(config);
+        let mut table = RateTable::default();
+        table.insert(eur(), usd(), 1.5, None);
+        fx.set_rates(table);
+
+        let quote = fx.quote(eur(), usd(), 10.0, None).unwrap();
+        fx.record(usd(), &quote);
+
+        assert_eq!(*quote.amount(), 14.85);
+        assert!((quote.pnl() - 0.15).abs() < 1e-9);
+        assert_eq!(*fx.pnl()[&usd()].conversions(), 1);
+    }
+}
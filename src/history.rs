@@ -0,0 +1,611 @@
+//! Transaction history - deposits and withdrawals, the only transactions referenced later (by disputes and reversals),
+//! kept for those lookups and duplicate detection.
+//!
+//! Most recent entries are kept in memory. Once their number exceeds the memory budget, they are
+//! spilled to disk as a sorted run file. Every run has an index of the first key of every block of
+//! records, kept on disk next to the run, and only every `INDEX_BLOCK_KEYS`-th key of it is kept in memory.
+//! A lookup costs at most one index block read and one block read per run whose key range contains the
+//! looked up key. Runs of the same size are merged together once there is `MERGE_FACTOR` of
+//! them, so that the number of runs, and the number of times an entry is rewritten, stay
+//! logarithmic in the history size.
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    transaction::{ClientId, Timestamp, TxId},
+};
+use chrono::DateTime;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Size of a single serialized entry, together with its key
+const RECORD_SIZE: usize = 64;
+
+/// Size of a single serialized key
+const KEY_SIZE: usize = 16;
+
+/// Number of records in a single indexed block of a run file
+const BLOCK_RECORDS: usize = 256;
+
+/// Number of keys in a single block of the on-disk index of a run file
+const INDEX_BLOCK_KEYS: usize = 256;
+
+/// Number of runs of the same level that get merged into a single run of the next level
+const MERGE_FACTOR: usize = 4;
+
+/// Estimated memory cost of a single in-memory entry, including the hash map overhead
+const ENTRY_COST: usize = 96;
+
+/// Used to keep the spill files of different `History` instances apart
+static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+/// (tx, client) - ordered by the transaction id first, so that the key ranges of the runs
+/// do not overlap much when transaction ids are increasing
+type Key = (TxId, ClientId);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// Deposit or withdrawal, as kept in the history
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub amount: f64,
+    pub currency: Currency,
+    pub account: Account,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Store of the deposits and withdrawals of all the clients
+#[derive(Debug)]
+pub struct History {
+    /// Most recent entries
+    memory: HashMap<Key, Entry>,
+
+    /// Number of entries kept in memory before they are spilled to disk
+    capacity: usize,
+
+    /// Directory the run files are created in
+    dir: PathBuf,
+
+    /// Prefix of the names of run files, unique for this instance
+    prefix: String,
+
+    /// Spilled entries, oldest run first
+    runs: Vec<Run>,
+
+    /// Sequence number of the next run file
+    next_run: usize,
+
+    /// Entries inserted since `stage` was called, kept apart until they are committed or discarded
+    staged: Option<HashMap<Key, Entry>>,
+}
+
+impl History {
+    /// Creates new history keeping roughly `memory_budget` bytes of entries in memory,
+    /// spilling older ones to files in `dir`
+    pub fn new(memory_budget: usize, dir: PathBuf) -> Self {
+        let instance = INSTANCES.fetch_add(1, Ordering::Relaxed);
+
+        Self {
+            memory: HashMap::new(),
+            capacity: (memory_budget / ENTRY_COST).max(1),
+            dir,
+            prefix: format!("toy-payments-engine-{}-{}", process::id(), instance),
+            runs: Vec::new(),
+            next_run: 0,
+            staged: None,
+        }
+    }
+
+    /// Returns the entry of transaction `tx` of `client`
+    pub fn get(&self, client: ClientId, tx: TxId) -> io::Result<Option<Entry>> {
+        let key = (tx, client);
+        if let Some(entry) = self.staged.as_ref().and_then(|s| s.get(&key)) {
+            return Ok(Some(*entry));
+        }
+        if let Some(entry) = self.memory.get(&key) {
+            return Ok(Some(*entry));
+        }
+
+        // newer runs are more likely to contain the entry
+        for run in self.runs.iter().rev() {
+            if let Some(entry) = run.get(key)? {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Inserts the entry of transaction `tx` of `client`. The caller is responsible for checking that it does not exist yet.
+    /// The entry is not inserted if the older entries, making room for it, can not be spilled.
+    pub fn insert(&mut self, client: ClientId, tx: TxId, entry: Entry) -> io::Result<()> {
+        if let Some(staged) = &mut self.staged {
+            staged.insert((tx, client), entry);
+            return Ok(());
+        }
+
+        if self.memory.len() + 1 >= self.capacity {
+            self.spill()?;
+        }
+        self.memory.insert((tx, client), entry);
+
+        Ok(())
+    }
+
+    /// Keeps the entries inserted from now on apart, until they are committed or discarded
+    pub fn stage(&mut self) {
+        self.staged.get_or_insert_with(HashMap::new);
+    }
+
+    /// Inserts the staged entries
+    pub fn commit_staged(&mut self) -> io::Result<()> {
+        for ((tx, client), entry) in self.staged.take().unwrap_or_default() {
+            self.insert(client, tx, entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the staged entries
+    pub fn discard_staged(&mut self) {
+        self.staged = None;
+    }
+
+    /// Writes all the in-memory entries to a new run file. The entries stay in memory if the run can not be written.
+    fn spill(&mut self) -> io::Result<()> {
+        if self.memory.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries: Vec<_> = self.memory.iter().map(|(k, e)| (*k, *e)).collect();
+        entries.sort_unstable_by_key(|(k, _)| *k);
+
+        let path = self.next_run_path();
+        let run = Run::write(path, 0, entries.into_iter().map(Ok))?;
+        self.memory.clear();
+        self.runs.push(run);
+
+        while self.runs.len() >= MERGE_FACTOR {
+            let from = self.runs.len() - MERGE_FACTOR;
+            let level = self.runs[from].level;
+            if self.runs[from..].iter().any(|r| r.level != level) {
+                break;
+            }
+            self.merge(from)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges the runs starting at index `from` into a single run of the next level
+    fn merge(&mut self, from: usize) -> io::Result<()> {
+        let level = self.runs[from].level + 1;
+        let mut readers = self.runs[from..]
+            .iter()
+            .map(|r| r.reader())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut heads = BinaryHeap::new();
+        for (i, r) in readers.iter_mut().enumerate() {
+            if let Some((key, entry)) = r.next().transpose()? {
+                heads.push(Reverse(((key, i), RecordEntry(entry))));
+            }
+        }
+
+        let merged = std::iter::from_fn(|| {
+            let Reverse(((key, i), RecordEntry(entry))) = heads.pop()?;
+            match readers[i].next().transpose() {
+                Ok(Some((k, e))) => heads.push(Reverse(((k, i), RecordEntry(e)))),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            Some(Ok((key, entry)))
+        });
+
+        let path = self.next_run_path();
+        let run = Run::write(path, level, merged)?;
+
+        for old in self.runs.drain(from..) {
+            old.remove();
+        }
+        self.runs.push(run);
+
+        Ok(())
+    }
+
+    fn next_run_path(&mut self) -> PathBuf {
+        let path = self
+            .dir
+            .join(format!("{}-{}.run", self.prefix, self.next_run));
+        self.next_run += 1;
+        path
+    }
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        for run in self.runs.drain(..) {
+            run.remove();
+        }
+    }
+}
+
+/// Entry wrapper ordered arbitrarily, so that it can be carried in a heap ordered by the keys
+#[derive(Debug)]
+struct RecordEntry(Entry);
+
+impl PartialEq for RecordEntry {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RecordEntry {}
+
+impl PartialOrd for RecordEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RecordEntry {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+/// Sorted file of entries, with the index of its blocks in a file of its own
+#[derive(Debug)]
+struct Run {
+    path: PathBuf,
+    file: File,
+
+    /// First key of every block of `BLOCK_RECORDS` records of the file
+    index_file: File,
+
+    /// Number of merges this run is a result of
+    level: u32,
+
+    /// Number of records in the file
+    len: usize,
+
+    /// Smallest and largest key in the file
+    range: (Key, Key),
+
+    /// First key of every block of `INDEX_BLOCK_KEYS` keys of the index file
+    index: Vec<Key>,
+}
+
+impl Run {
+    /// Writes entries, sorted by key, to a new run file at `path`
+    fn write<I>(path: PathBuf, level: u32, entries: I) -> io::Result<Self>
+    where
+        I: Iterator<Item = io::Result<(Key, Entry)>>,
+    {
+        let index_path = Self::index_path(&path);
+        let written = Self::write_records(&path, &index_path, entries);
+        if written.is_err() {
+            // a partially written run is of no use
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(&index_path);
+        }
+        let (len, index, range) = written?;
+
+        Ok(Self {
+            file: File::open(&path)?,
+            index_file: File::open(&index_path)?,
+            path,
+            level,
+            len,
+            range,
+            index,
+        })
+    }
+
+    /// Returns the path of the index file of the run file at `path`
+    fn index_path(path: &Path) -> PathBuf {
+        path.with_extension("idx")
+    }
+
+    /// Writes the sorted `entries` to a new file at `path`, and the first key of every block of them to a new file
+    /// at `index_path`, returning their number, the in-memory index and the key range
+    fn write_records<I>(
+        path: &Path,
+        index_path: &Path,
+        entries: I,
+    ) -> io::Result<(usize, Vec<Key>, (Key, Key))>
+    where
+        I: Iterator<Item = io::Result<(Key, Entry)>>,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut index_writer = BufWriter::new(File::create(index_path)?);
+        let mut len: usize = 0;
+        let mut blocks: usize = 0;
+        let mut index = Vec::new();
+        let mut range = ((0, 0), (0, 0));
+
+        for e in entries {
+            let (key, entry) = e?;
+            if len % BLOCK_RECORDS == 0 {
+                if blocks % INDEX_BLOCK_KEYS == 0 {
+                    index.push(key);
+                }
+                index_writer.write_all(&encode_key(key))?;
+                blocks += 1;
+            }
+            if len == 0 {
+                range.0 = key;
+            }
+            range.1 = key;
+            writer.write_all(&encode(key, &entry))?;
+            len += 1;
+        }
+
+        writer.flush()?;
+        index_writer.flush()?;
+
+        Ok((len, index, range))
+    }
+
+    /// Looks up the entry with given `key`
+    fn get(&self, key: Key) -> io::Result<Option<Entry>> {
+        if self.len == 0 || key < self.range.0 || key > self.range.1 {
+            return Ok(None);
+        }
+
+        // the block of the index file, and then the block of the records, that may contain the key
+        let index_block = self.index.partition_point(|k| *k <= key) - 1;
+        let first_key = index_block * INDEX_BLOCK_KEYS;
+        let count = INDEX_BLOCK_KEYS.min(self.len.div_ceil(BLOCK_RECORDS) - first_key);
+        let mut buf = vec![0u8; count * KEY_SIZE];
+        let mut index_file = &self.index_file;
+        index_file.seek(SeekFrom::Start((first_key * KEY_SIZE) as u64))?;
+        index_file.read_exact(&mut buf)?;
+        let keys: Vec<_> = buf.chunks_exact(KEY_SIZE).map(decode_key).collect();
+
+        let block = first_key + keys.partition_point(|k| *k <= key) - 1;
+        let first = block * BLOCK_RECORDS;
+        let count = BLOCK_RECORDS.min(self.len - first);
+
+        let mut buf = vec![0u8; count * RECORD_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start((first * RECORD_SIZE) as u64))?;
+        file.read_exact(&mut buf)?;
+
+        let records: Vec<_> = buf.chunks_exact(RECORD_SIZE).collect();
+        Ok(records
+            .binary_search_by_key(&key, |r| decode(r).0)
+            .ok()
+            .map(|i| decode(records[i]).1))
+    }
+
+    /// Returns a reader iterating over all the entries, in order
+    fn reader(&self) -> io::Result<RunReader> {
+        Ok(RunReader {
+            reader: BufReader::new(File::open(&self.path)?),
+            remaining: self.len,
+        })
+    }
+
+    /// Removes the run file and its index
+    fn remove(self) {
+        // nothing sensible can be done if the temporary files can not be removed
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(Self::index_path(&self.path));
+    }
+}
+
+struct RunReader {
+    reader: BufReader<File>,
+    remaining: usize,
+}
+
+impl Iterator for RunReader {
+    type Item = io::Result<(Key, Entry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let mut buf = [0u8; RECORD_SIZE];
+        Some(self.reader.read_exact(&mut buf).map(|_| decode(&buf)))
+    }
+}
+
+/// Key layout: tx (8) | client (8)
+fn encode_key(key: Key) -> [u8; KEY_SIZE] {
+    let mut buf = [0u8; KEY_SIZE];
+    buf[0..8].copy_from_slice(&key.0.to_le_bytes());
+    buf[8..16].copy_from_slice(&key.1.to_le_bytes());
+    buf
+}
+
+fn decode_key(buf: &[u8]) -> Key {
+    let field = |from: usize| -> [u8; 8] {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&buf[from..from + 8]);
+        b
+    };
+
+    (
+        TxId::from_le_bytes(field(0)),
+        ClientId::from_le_bytes(field(8)),
+    )
+}
+
+/// Record layout: key (16) | kind (1) | has timestamp (1) | amount (8) | timestamp in microseconds (8) | currency (3) | account (16) | padding (11)
+fn encode(key: Key, entry: &Entry) -> [u8; RECORD_SIZE] {
+    let mut buf = [0u8; RECORD_SIZE];
+    buf[0..KEY_SIZE].copy_from_slice(&encode_key(key));
+    buf[16] = match entry.kind {
+        EntryKind::Deposit => 0,
+        EntryKind::Withdrawal => 1,
+    };
+    buf[17] = entry.timestamp.is_some() as u8;
+    buf[18..26].copy_from_slice(&entry.amount.to_le_bytes());
+    let micros = entry.timestamp.map_or(0, |t| t.timestamp_micros());
+    buf[26..34].copy_from_slice(&micros.to_le_bytes());
+    buf[34..37].copy_from_slice(&entry.currency.to_bytes());
+    buf[37..53].copy_from_slice(&entry.account.to_bytes());
+    buf
+}
+
+fn decode(buf: &[u8]) -> (Key, Entry) {
+    let field = |from: usize| -> [u8; 8] {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&buf[from..from + 8]);
+        b
+    };
+
+    let key = decode_key(buf);
+    let kind = match buf[16] {
+        0 => EntryKind::Deposit,
+        _ => EntryKind::Withdrawal,
+    };
+    let amount = f64::from_le_bytes(field(18));
+    let currency = Currency::from_bytes([buf[34], buf[35], buf[36]]);
+    let mut account = [0u8; 16];
+    account.copy_from_slice(&buf[37..53]);
+    let account = Account::from_bytes(account);
+    let timestamp = match buf[17] {
+        0 => None,
+        _ => DateTime::from_timestamp_micros(i64::from_le_bytes(field(26))),
+    };
+
+    (
+        key,
+        Entry {
+            kind,
+            amount,
+            currency,
+            account,
+            timestamp,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, EntryKind, History, Run, ENTRY_COST, MERGE_FACTOR};
+    use crate::{account::Account, currency::Currency, transaction::TxId};
+    use std::{env, fs, process};
+
+    fn entry(tx: TxId) -> Entry {
+        Entry {
+            kind: if tx % 3 == 0 {
+                EntryKind::Withdrawal
+            } else {
+                EntryKind::Deposit
+            },
+            amount: tx as f64 / 4.0,
+            currency: if tx % 4 == 0 {
+                Currency::NONE
+            } else {
+                "EUR".parse().unwrap()
+            },
+            account: if tx % 5 == 0 {
+                "savings".parse().unwrap()
+            } else {
+                Account::MAIN
+            },
+            timestamp: if tx % 2 == 0 {
+                None
+            } else {
+                "2022-09-01T10:00:00.123456Z".parse().ok()
+            },
+        }
+    }
+
+    #[test]
+    fn spilled_entries_can_be_looked_up() {
+        let mut history = History::new(10 * ENTRY_COST, env::temp_dir());
+        // enough entries to create, and merge, multiple runs; inserted in shuffled order
+        let n = 10 * (MERGE_FACTOR * MERGE_FACTOR + 2) as TxId;
+        for i in 0..n {
+            let tx = (i * 7919) % n;
+            history.insert(tx % 5, tx, entry(tx)).unwrap();
+        }
+
+        assert!(history.runs.iter().any(|r| r.level == 2));
+        for tx in 0..n {
+            assert_eq!(
+                history.get(tx % 5, tx).unwrap(),
+                Some(entry(tx)),
+                "tx {}",
+                tx
+            );
+            assert_eq!(history.get(tx % 5 + 1, tx).unwrap(), None);
+        }
+        assert_eq!(history.get(0, n).unwrap(), None);
+    }
+
+    #[test]
+    fn staged_entries_are_committed_or_discarded() {
+        let mut history = History::new(2 * ENTRY_COST, env::temp_dir());
+        history.stage();
+        for tx in 1..=3 {
+            history.insert(1, tx, entry(tx)).unwrap();
+        }
+        assert!(history.runs.is_empty());
+        assert_eq!(history.get(1, 2).unwrap(), Some(entry(2)));
+
+        history.discard_staged();
+        assert_eq!(history.get(1, 2).unwrap(), None);
+
+        history.stage();
+        history.insert(1, 2, entry(2)).unwrap();
+        history.commit_staged().unwrap();
+        history.insert(1, 3, entry(3)).unwrap();
+        assert_eq!(history.get(1, 2).unwrap(), Some(entry(2)));
+        assert_eq!(history.get(1, 3).unwrap(), Some(entry(3)));
+    }
+
+    #[test]
+    fn failed_inserts_can_be_retried() {
+        let dir = env::temp_dir().join(format!("toy-payments-engine-missing-{}", process::id()));
+        let mut history = History::new(2 * ENTRY_COST, dir.clone());
+        history.insert(1, 1, entry(1)).unwrap();
+        assert!(history.insert(1, 2, entry(2)).is_err());
+        assert_eq!(history.get(1, 1).unwrap(), Some(entry(1)));
+        assert_eq!(history.get(1, 2).unwrap(), None);
+
+        // accepted once the directory can be written to
+        fs::create_dir(&dir).unwrap();
+        history.insert(1, 2, entry(2)).unwrap();
+        assert_eq!(history.runs.len(), 1);
+        assert_eq!(history.get(1, 1).unwrap(), Some(entry(1)));
+        assert_eq!(history.get(1, 2).unwrap(), Some(entry(2)));
+        drop(history);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn run_files_are_removed_on_drop() {
+        let mut history = History::new(ENTRY_COST, env::temp_dir());
+        history.insert(1, 1, entry(1)).unwrap();
+        history.insert(1, 2, entry(2)).unwrap();
+        let path = history.runs[0].path.clone();
+        let index_path = Run::index_path(&path);
+        assert!(path.exists());
+        assert!(index_path.exists());
+
+        drop(history);
+
+        assert!(!path.exists());
+        assert!(!index_path.exists());
+    }
+}
@@ -0,0 +1,377 @@
+//! Double-entry ledger - every movement of funds posted as a balanced debit/credit pair
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    errors::LedgerError,
+    events::{Event, EventKind},
+    repo::Client,
+    transaction::{ClientId, Timestamp, TxId},
+};
+use getset::Getters;
+use std::{collections::BTreeMap, fmt};
+
+/// Smallest tolerance of the trial balance, absorbing the floating point rounding of small amounts
+const EPSILON: f64 = 1e-6;
+
+/// Account of the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LedgerAccount {
+    /// Funds the client can use
+    Available(ClientId, Account),
+    /// Funds of the client held by disputes and authorizations
+    Held(ClientId, Account),
+    /// Funds deposited to, and withdrawn from, the engine
+    Cash,
+    /// Captured funds awaiting settlement, and funds in transfer between the client's accounts
+    Suspense,
+    /// Currency conversions
+    Fx,
+    /// Balances brought forward from the opening balances
+    OpeningBalances,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::Available(client, account) => {
+                write!(f, "client:{}:{}:available", client, account)
+            }
+            LedgerAccount::Held(client, account) => write!(f, "client:{}:{}:held", client, account),
+            LedgerAccount::Cash => write!(f, "cash"),
+            LedgerAccount::Suspense => write!(f, "suspense"),
+            LedgerAccount::Fx => write!(f, "fx"),
+            LedgerAccount::OpeningBalances => write!(f, "opening_balances"),
+        }
+    }
+}
+
+/// Single balanced entry of the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Getters)]
+pub struct Posting {
+    #[get = "pub"]
+    client: ClientId,
+
+    /// Id of the transaction that caused this posting
+    #[get = "pub"]
+    tx: TxId,
+
+    #[get = "pub"]
+    timestamp: Option<Timestamp>,
+
+    #[get = "pub"]
+    debit: LedgerAccount,
+
+    #[get = "pub"]
+    credit: LedgerAccount,
+
+    #[get = "pub"]
+    amount: f64,
+
+    #[get = "pub"]
+    currency: Currency,
+}
+
+impl Posting {
+    /// Returns the postings of the funds moved by the `event`
+    pub fn from_event(event: &Event) -> Vec<Posting> {
+        let (amount, currency) = match (event.kind().amount(), event.kind().currency()) {
+            (Some(amount), Some(currency)) => (amount, currency),
+            _ => return Vec::new(),
+        };
+
+        let available = LedgerAccount::Available(*event.client(), *event.account());
+        let held = LedgerAccount::Held(*event.client(), *event.account());
+        // (debit, credit, amount)
+        let entries = match event.kind() {
+            EventKind::FundsDeposited { .. } => vec![(LedgerAccount::Cash, available, amount)],
+            EventKind::FundsWithdrawn { .. } | EventKind::DepositReversed { .. } => {
+                vec![(available, LedgerAccount::Cash, amount)]
+            }
+            // the charged back deposit is returned out of the engine from the client's held funds
+            EventKind::ChargedBack { .. } => vec![(held, LedgerAccount::Cash, amount)],
+            EventKind::WithdrawalReversed { .. } => vec![(LedgerAccount::Cash, available, amount)],
+            EventKind::FundsHeld { .. } | EventKind::FundsAuthorized { .. } => {
+                vec![(available, held, amount)]
+            }
+            EventKind::FundsReleased { .. } | EventKind::AuthorizationReleased { .. } => {
+                vec![(held, available, amount)]
+            }
+            EventKind::FundsCaptured { .. } => vec![(held, LedgerAccount::Suspense, amount)],
+            EventKind::ConvertedFrom { .. } => vec![(available, LedgerAccount::Fx, amount)],
+            EventKind::ConvertedTo { .. } => vec![(LedgerAccount::Fx, available, amount)],
+            EventKind::TransferredOut { .. } => {
+                vec![(available, LedgerAccount::Suspense, amount)]
+            }
+            EventKind::TransferredIn { .. } => vec![(LedgerAccount::Suspense, available, amount)],
+            // the opening balance is split between the available and held funds
+            EventKind::BalanceOpened {
+                available: a,
+                held: h,
+                ..
+            } => [(available, *a), (held, *h)]
+                .into_iter()
+                .filter(|(_, amount)| *amount != 0.0)
+                .map(|(credit, amount)| (LedgerAccount::OpeningBalances, credit, amount))
+                .collect(),
+            EventKind::AccountLocked
+            | EventKind::DisputeExpired
+            | EventKind::AuthorizationExpired
+            | EventKind::Annotated { .. } => Vec::new(),
+        };
+
+        entries
+            .into_iter()
+            .map(|(debit, credit, amount)| Posting {
+                client: *event.client(),
+                tx: *event.tx(),
+                timestamp: *event.timestamp(),
+                debit,
+                credit,
+                amount,
+                currency,
+            })
+            .collect()
+    }
+}
+
+/// Balances of the ledger accounts. Debit balances are positive, credit balances negative.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    balances: BTreeMap<(LedgerAccount, Currency), f64>,
+
+    /// Sum of the posted amounts, and the number of the postings, in every currency
+    turnover: BTreeMap<Currency, (f64, u64)>,
+}
+
+impl Ledger {
+    /// Records the `posting`
+    pub fn post(&mut self, posting: &Posting) {
+        *self
+            .balances
+            .entry((posting.debit, posting.currency))
+            .or_default() += posting.amount;
+        *self
+            .balances
+            .entry((posting.credit, posting.currency))
+            .or_default() -= posting.amount;
+
+        let turnover = self.turnover.entry(posting.currency).or_default();
+        turnover.0 += posting.amount.abs();
+        turnover.1 += 1;
+    }
+
+    /// Returns the balance of the ledger `account` in `currency`
+    pub fn balance(&self, account: LedgerAccount, currency: Currency) -> f64 {
+        self.balances
+            .get(&(account, currency))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Checks that the funds on the system accounts of the ledger match the total funds of the `clients`
+    /// in every currency, and that the client accounts of the ledger agree with the balances of the `clients`
+    pub fn trial_balance<'a>(
+        &self,
+        clients: impl Iterator<Item = &'a Client>,
+    ) -> Result<(), LedgerError> {
+        let clients: Vec<_> = clients.collect();
+
+        // (system accounts of the ledger, funds of the clients) per currency
+        let mut totals: BTreeMap<Currency, (f64, f64)> = BTreeMap::new();
+        for ((account, currency), balance) in &self.balances {
+            if !matches!(
+                account,
+                LedgerAccount::Available(..) | LedgerAccount::Held(..)
+            ) {
+                totals.entry(*currency).or_default().0 += balance;
+            }
+        }
+        for client in &clients {
+            for ((_, currency), b) in client.balances() {
+                totals.entry(*currency).or_default().1 += b.total();
+            }
+        }
+        if let Some((currency, _)) = totals.iter().find(|(currency, (system, clients))| {
+            (system - clients).abs() > self.tolerance(**currency)
+        }) {
+            return Err(LedgerError::Unbalanced(currency.to_string()));
+        }
+
+        for client in clients {
+            for ((account, currency), b) in client.balances() {
+                // client accounts are liabilities of the engine, so they have credit balances
+                let available =
+                    -self.balance(LedgerAccount::Available(*client.id(), *account), *currency);
+                let held = -self.balance(LedgerAccount::Held(*client.id(), *account), *currency);
+                let tolerance = self.tolerance(*currency);
+                if (available - b.available()).abs() > tolerance
+                    || (held - b.held()).abs() > tolerance
+                {
+                    return Err(LedgerError::ClientMismatch(
+                        *client.id(),
+                        account.to_string(),
+                        currency.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the tolerance of the trial balance in `currency`. The rounding error of the balances grows with
+    /// both the size and the number of the posted amounts, as they are summed in different order on the ledger
+    /// and by the clients, so the tolerance is scaled by both.
+    fn tolerance(&self, currency: Currency) -> f64 {
+        let (turnover, postings) = self.turnover.get(&currency).copied().unwrap_or_default();
+        EPSILON.max(turnover * postings as f64 * f64::EPSILON)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ledger, LedgerAccount, Posting};
+    use crate::{
+        account::Account,
+        currency::Currency,
+        errors::LedgerError,
+        events::{Event, EventKind},
+        repo::Repository,
+        transaction::{Transaction, TransactionData, TransactionDataAmount},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn every_event_is_posted_balanced() {
+        let event = Event::new(
+            1,
+            1,
+            None,
+            EventKind::ChargedBack {
+                amount: 2.0,
+                currency: Currency::NONE,
+            },
+        );
+        let mut ledger = Ledger::default();
+
+        for posting in Posting::from_event(&event) {
+            ledger.post(&posting);
+        }
+
+        assert_eq!(ledger.balance(LedgerAccount::Cash, Currency::NONE), -2.0);
+        assert_eq!(
+            ledger.balance(LedgerAccount::Held(1, Account::MAIN), Currency::NONE),
+            2.0
+        );
+    }
+
+    #[test]
+    fn ledger_agrees_with_the_repository() {
+        let mut repo = Repository::new();
+        let ledger = Rc::new(RefCell::new(Ledger::default()));
+        let l = ledger.clone();
+        repo.subscribe(Rc::new(move |e: &Event| {
+            for posting in Posting::from_event(e) {
+                l.borrow_mut().post(&posting);
+            }
+        }));
+
+        for tr in [
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 5.0).unwrap()),
+            Transaction::Withdrawal(TransactionDataAmount::new(1, 2, 1.0).unwrap()),
+            Transaction::Dispute(TransactionData::new(1, 1)),
+        ] {
+            repo.register_transaction(tr).unwrap();
+        }
+
+        assert_eq!(ledger.borrow().trial_balance(repo.iter_clients()), Ok(()));
+        assert_eq!(
+            ledger.borrow().balance(LedgerAccount::Cash, Currency::NONE),
+            4.0
+        );
+    }
+
+    #[test]
+    fn large_amounts_are_balanced() {
+        let mut repo = Repository::new();
+        let ledger = Rc::new(RefCell::new(Ledger::default()));
+        let l = ledger.clone();
+        repo.subscribe(Rc::new(move |e: &Event| {
+            for posting in Posting::from_event(e) {
+                l.borrow_mut().post(&posting);
+            }
+        }));
+
+        // deterministic pseudo-random amounts with four decimal places
+        let mut seed: u64 = 42;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for tx in 1..=40_000 {
+            let r = next();
+            let client = r % 50 + 1;
+            let tr = match r % 4 {
+                0 if tx > 1_000 => Transaction::Withdrawal(
+                    TransactionDataAmount::new(client, tx, (next() % 1_000_000) as f64 / 1e4)
+                        .unwrap(),
+                ),
+                _ => Transaction::Deposit(
+                    TransactionDataAmount::new(
+                        client,
+                        tx,
+                        1e6 + (next() % 9_990_000_000_000) as f64 / 1e4,
+                    )
+                    .unwrap(),
+                ),
+            };
+            repo.register_transaction(tr).unwrap();
+        }
+
+        assert_eq!(ledger.borrow().trial_balance(repo.iter_clients()), Ok(()));
+    }
+
+    #[test]
+    fn missing_posting_is_detected() {
+        let repo = {
+            let mut repo = Repository::new();
+            repo.register_transaction(Transaction::Deposit(
+                TransactionDataAmount::new(1, 1, 5.0).unwrap(),
+            ))
+            .unwrap();
+            repo
+        };
+
+        assert!(Ledger::default()
+            .trial_balance(repo.iter_clients())
+            .is_err());
+    }
+
+    #[test]
+    fn posting_of_unknown_client_is_detected() {
+        let mut repo = Repository::new();
+        let mut ledger = Ledger::default();
+        let deposit = EventKind::FundsDeposited {
+            amount: 5.0,
+            currency: Currency::NONE,
+        };
+        repo.register_transaction(Transaction::Deposit(
+            TransactionDataAmount::new(1, 1, 5.0).unwrap(),
+        ))
+        .unwrap();
+
+        for client in [1, 2] {
+            for posting in Posting::from_event(&Event::new(client, 1, None, deposit.clone())) {
+                ledger.post(&posting);
+            }
+        }
+
+        assert_eq!(
+            ledger.trial_balance(repo.iter_clients()),
+            Err(LedgerError::Unbalanced("".to_owned()))
+        );
+    }
+}
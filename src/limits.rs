@@ -0,0 +1,226 @@
+//! Risk limits applied to deposits and withdrawals
+
+use crate::transaction::ClientId;
+use getset::Getters;
+use serde::{de::IgnoredAny, Deserialize};
+use std::{collections::BTreeMap, fmt};
+
+/// Identifies the limit that was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    MaxWithdrawal,
+    WithdrawalVelocity,
+    WithdrawalRate,
+    MaxDailyDeposit,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::MaxWithdrawal => write!(f, "max_withdrawal"),
+            Limit::WithdrawalVelocity => write!(f, "withdrawal_velocity"),
+            Limit::WithdrawalRate => write!(f, "withdrawal_rate"),
+            Limit::MaxDailyDeposit => write!(f, "max_daily_deposit"),
+        }
+    }
+}
+
+/// Set of limits applied to a single client. Unset limits are not enforced.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Getters)]
+#[serde(default)]
+pub struct Limits {
+    /// Maximum amount of a single withdrawal
+    #[get = "pub"]
+    max_withdrawal: Option<f64>,
+
+    /// Maximum number of withdrawals within a number of consecutive transactions
+    #[get = "pub"]
+    withdrawal_velocity: Option<Velocity>,
+
+    /// Maximum number of withdrawals within a time window. Applies to timestamped transactions only.
+    #[get = "pub"]
+    withdrawal_rate: Option<Rate>,
+
+    /// Maximum total of deposits within a calendar day (UTC), in each currency. Applies to timestamped transactions only.
+    #[get = "pub"]
+    max_daily_deposit: Option<f64>,
+
+    /// Approved overdraft - withdrawals are allowed down to minus this amount
+    #[get = "pub"]
+    credit_limit: Option<f64>,
+}
+
+impl Limits {
+    /// Returns limits with values set in `other` taking precedence over values set in `self`
+    fn merge(&self, other: &Limits) -> Limits {
+        Limits {
+            max_withdrawal: other.max_withdrawal.or(self.max_withdrawal),
+            withdrawal_velocity: other.withdrawal_velocity.or(self.withdrawal_velocity),
+            withdrawal_rate: other.withdrawal_rate.or(self.withdrawal_rate),
+            max_daily_deposit: other.max_daily_deposit.or(self.max_daily_deposit),
+            credit_limit: other.credit_limit.or(self.credit_limit),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Getters)]
+#[serde(deny_unknown_fields)]
+pub struct Velocity {
+    /// Maximum number of withdrawals allowed within the window
+    #[get = "pub"]
+    max_withdrawals: usize,
+
+    /// Size of the window, in client's deposits and withdrawals, including the checked one
+    #[get = "pub"]
+    per_transactions: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Getters)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    /// Maximum number of withdrawals allowed within the window
+    #[get = "pub"]
+    max_withdrawals: usize,
+
+    /// Length of the window, in seconds, ending with the checked withdrawal
+    #[get = "pub"]
+    window_seconds: u64,
+}
+
+/// Limits configuration: defaults for all the clients, and per-client overrides
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Getters)]
+#[serde(try_from = "RawLimitsConfig")]
+pub struct LimitsConfig {
+    #[get = "pub"]
+    defaults: Limits,
+
+    #[get = "pub"]
+    overrides: Vec<LimitsOverride>,
+}
+
+/// Limits configuration as written, with the keys no limit is named by.
+/// `deny_unknown_fields` does not work together with `flatten`, so they are rejected by hand.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawLimitsConfig {
+    #[serde(flatten)]
+    defaults: Limits,
+
+    overrides: Vec<RawLimitsOverride>,
+
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawLimitsOverride {
+    client: ClientId,
+
+    #[serde(flatten)]
+    limits: Limits,
+
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// Fails on the first of the `unknown` keys of the limits, if any
+fn check_known(unknown: &BTreeMap<String, IgnoredAny>) -> Result<(), String> {
+    match unknown.keys().next() {
+        Some(key) => Err(format!(
+            "unknown limit `{}`, expected one of `max_withdrawal`, `withdrawal_velocity`, \
+             `withdrawal_rate`, `max_daily_deposit`, `credit_limit`",
+            key
+        )),
+        None => Ok(()),
+    }
+}
+
+impl TryFrom<RawLimitsConfig> for LimitsConfig {
+    type Error = String;
+
+    fn try_from(raw: RawLimitsConfig) -> Result<Self, Self::Error> {
+        check_known(&raw.unknown)?;
+        let overrides = raw
+            .overrides
+            .into_iter()
+            .map(|o| {
+                check_known(&o.unknown)?;
+                Ok(LimitsOverride {
+                    client: o.client,
+                    limits: o.limits,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self {
+            defaults: raw.defaults,
+            overrides,
+        })
+    }
+}
+
+impl LimitsConfig {
+    /// Returns limits effective for the `client`
+    pub fn for_client(&self, client: ClientId) -> Limits {
+        self.overrides
+            .iter()
+            .filter(|o| o.client == client)
+            .fold(self.defaults.clone(), |acc, o| acc.merge(&o.limits))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct LimitsOverride {
+    #[get = "pub"]
+    client: ClientId,
+
+    #[get = "pub"]
+    limits: Limits,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LimitsConfig;
+
+    #[test]
+    fn override_takes_precedence_over_defaults() {
+        let config: LimitsConfig = toml::from_str(
+            r#"
+            max_withdrawal = 100.0
+
+            [withdrawal_velocity]
+            max_withdrawals = 1
+            per_transactions = 2
+
+            [[overrides]]
+            client = 42
+            max_withdrawal = 500.0
+            credit_limit = 50.0
+            "#,
+        )
+        .unwrap();
+
+        let limits = config.for_client(42);
+        assert_eq!(*limits.max_withdrawal(), Some(500.0));
+        assert_eq!(*limits.credit_limit(), Some(50.0));
+        assert_eq!(
+            limits.withdrawal_velocity(),
+            config.defaults().withdrawal_velocity()
+        );
+        assert_eq!(config.for_client(1), *config.defaults());
+    }
+
+    #[test]
+    fn misspelled_limits_are_rejected() {
+        assert!(toml::from_str::<LimitsConfig>("max_withdrawl = 100.0").is_err());
+        assert!(toml::from_str::<LimitsConfig>(
+            r#"
+            [[overrides]]
+            client = 42
+            credit_limt = 50.0
+            "#
+        )
+        .is_err());
+        assert!(toml::from_str::<LimitsConfig>("").is_ok());
+    }
+}
@@ -1,56 +1,743 @@
+mod account;
+mod audit;
+mod cli;
+mod config;
+mod currency;
+mod diff;
 mod dto;
 mod errors;
+mod events;
+mod fx;
+mod history;
+mod ledger;
+mod limits;
+mod metrics;
+mod opening;
+mod policy;
+mod progress;
+mod query;
+mod reconcile;
 mod repo;
+mod snapshot;
+mod statement;
+mod table;
 mod transaction;
 
-use crate::dto::{InputRecord, OutputRecord};
+use crate::dto::{
+    BreakRecord, DiffRecord, DisputeDiffRecord, ErrorRecord, EventRecord, InputRecord,
+    OpenDisputeRecord, OutputColumns, OutputRecord, PnlRecord, PostingRecord,
+};
+use audit::{AuditEntry, AuditLog, Head};
+use cli::{
+    Args, Command, DiffArgs, ErrorFormat, LogFormat, QueryArgs, ReconcileArgs, SimulateArgs,
+    StatementArgs, VerifyAuditArgs,
+};
 use color_eyre::{
     eyre::{bail, Context},
     Result,
 };
+use config::Config;
 use csv::Trim;
-use repo::Repository;
-use std::{convert::TryInto, env, fs::File};
+use diff::{Change, Difference};
+use errors::{
+    AuditError, DeserializationError, ErrorCode, LedgerError, OutputError, RepositoryError,
+};
+use events::{Event, EventKind};
+use fx::RateTable;
+use ledger::{Ledger, Posting};
+use metrics::Metrics;
+use policy::{BlockedDeposits, LargeDeposits, NewClientWithdrawals};
+use progress::{CountingReader, Progress};
+use repo::{Client, Repository};
+use serde::Serialize;
+use snapshot::BalanceKey;
+use statement::Statement;
+use std::{
+    cell::{Cell, RefCell},
+    convert::TryInto,
+    env,
+    fs::File,
+    io::IsTerminal,
+    rc::Rc,
+};
+use table::Format;
 use transaction::Transaction;
 
 fn print_usage() {
     let bin = env!("CARGO_BIN_NAME");
-    eprintln!("USAGE: {} INPUT_PATH", bin);
+    eprintln!(
+        "USAGE: {0} [--config CONFIG_PATH] [--events EVENTS_PATH] [--fx-report FX_REPORT_PATH] [--ledger LEDGER_PATH] [--audit AUDIT_PATH] [--summary] [--metrics METRICS_PATH] [--opening-balances OPENING_PATH] [--open-disputes DISPUTES_PATH] [--progress] INPUT_PATH
+       {0} statement --client CLIENT [--from TIME] [--to TIME] [--from-tx TX] [--to-tx TX] [--format csv|table] [--config CONFIG_PATH] [--progress] INPUT_PATH
+       {0} verify-audit [--head SEQ:HASH] AUDIT_PATH
+       {0} reconcile --expected EXPECTED_PATH [--tolerance AMOUNT] [--relative-tolerance FRACTION] [--config CONFIG_PATH] [--progress] INPUT_PATH
+       {0} diff [--format csv|table] [--disputes] BEFORE_PATH AFTER_PATH
+       {0} simulate --batch BATCH_PATH [--commit] [--format csv|table] [--config CONFIG_PATH] [--opening-balances OPENING_PATH] [--open-disputes DISPUTES_PATH] [--progress] INPUT_PATH
+       {0} query [--client CLIENT] [--at-tx TX | --at TIME] [--disputes] [--config CONFIG_PATH] [--opening-balances OPENING_PATH] [--open-disputes DISPUTES_PATH] [--progress] INPUT_PATH
+       {0} --help
+
+Every command accepts [--error-format text|json] [--log-level LEVEL] [--log-format text|json].
+
+`query` replays the input from its start (or from the opening state, if given) up to the queried point, so it
+takes as long as processing that part of the input. Start from the opening state of a later day to replay less.",
+        bin
+    );
+}
+
+/// Registers the built-in policies enabled in the `config`
+fn register_policies(repo: &mut Repository, config: &Config) {
+    let policies = config.policies();
+    if !policies.blocked_deposit_clients().is_empty() {
+        repo.register_policy(Rc::new(BlockedDeposits::new(
+            policies.blocked_deposit_clients().clone(),
+        )));
+    }
+    if let Some(c) = policies.new_client_withdrawals() {
+        repo.register_policy(Rc::new(NewClientWithdrawals::new(
+            *c.max_amount(),
+            *c.min_transactions(),
+        )));
+    }
+    if let Some(threshold) = policies.flag_deposits_above() {
+        repo.register_policy(Rc::new(LargeDeposits::new(*threshold)));
+    }
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
+    if env::args().skip(1).any(|a| a == "--help") {
         print_usage();
-        bail!("Incorrect number of arguments");
+        return Ok(());
     }
+    let args = Args::parse(env::args().skip(1)).inspect_err(|_| print_usage())?;
+    init_logging(&args);
 
-    // `Repository` holds the internal state of the clients
-    let mut repo = Repository::new();
+    // even the errors ending the run are reported in the envelope, in the requested format
+    if let Err(e) = run(&args) {
+        report(*args.error_format(), "ERROR", &envelope(&e));
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Runs the command given in the `args`
+fn run(args: &Args) -> Result<()> {
+    let config = match args.config() {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    match args.command() {
+        Command::Process => process(args, &config),
+        Command::Statement(s) => statement(args, s, &config),
+        Command::VerifyAudit(v) => verify_audit(args, v),
+        Command::Reconcile(r) => reconcile(args, r, &config),
+        Command::Diff(d) => diff(args, d),
+        Command::Simulate(s) => simulate(args, s, &config),
+        Command::Query(q) => query(args, q, &config),
+    }
+}
+
+/// Writes the logs of the requested level, if any, to the standard error
+fn init_logging(args: &Args) {
+    let level = match args.log_level() {
+        Some(level) => *level,
+        None => return,
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(std::io::stderr().is_terminal())
+        // every log line starts on a line of its own, not in the middle of the progress
+        .with_writer(|| {
+            progress::clear();
+            std::io::stderr()
+        });
+    match args.log_format() {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+/// Reports the `error` on the standard error, in the text format prefixed by the `label`
+fn report(format: ErrorFormat, label: &str, error: &ErrorRecord) {
+    progress::clear();
+    match format {
+        ErrorFormat::Text => eprintln!("{} {}", label, error),
+        ErrorFormat::Json => match serde_json::to_string(error) {
+            Ok(json) => eprintln!("{}", json),
+            Err(_) => eprintln!("{} {}", label, error),
+        },
+    }
+}
+
+/// Reports the expiries that failed while the transactions were registered in `repo`, apart from the transactions
+fn report_expiry_errors(repo: &mut Repository, format: ErrorFormat) {
+    for e in repo.take_expiry_errors() {
+        report(format, "ERROR", &ErrorRecord::new(&e));
+    }
+}
+
+/// Returns the envelope of the error `e`, with the code of its coded cause, if any
+fn envelope(e: &color_eyre::Report) -> ErrorRecord {
+    let mut messages: Vec<String> = Vec::new();
+    for c in e.chain() {
+        let message = c.to_string();
+        // some errors repeat the message of their source in their own
+        if !messages.last().is_some_and(|m| m.ends_with(&message)) {
+            messages.push(message);
+        }
+    }
+    let message = messages.join(": ");
+
+    // malformed CSV records have no code of their own
+    let malformed = e
+        .chain()
+        .filter_map(|c| c.downcast_ref::<csv::Error>())
+        .find(|c| {
+            matches!(
+                c.kind(),
+                csv::ErrorKind::Utf8 { .. }
+                    | csv::ErrorKind::UnequalLengths { .. }
+                    | csv::ErrorKind::Deserialize { .. }
+            )
+        })
+        .map(|c| DeserializationError::MalformedRecord(c.to_string()));
+
+    let coded = e.chain().find_map(|c| -> Option<&dyn ErrorCode> {
+        c.downcast_ref::<DeserializationError>()
+            .map(|e| e as &dyn ErrorCode)
+            .or_else(|| {
+                c.downcast_ref::<RepositoryError>()
+                    .map(|e| e as &dyn ErrorCode)
+            })
+            .or_else(|| c.downcast_ref::<LedgerError>().map(|e| e as &dyn ErrorCode))
+            .or_else(|| c.downcast_ref::<AuditError>().map(|e| e as &dyn ErrorCode))
+    });
+    let coded = coded.or(malformed.as_ref().map(|e| e as &dyn ErrorCode));
+
+    match coded {
+        Some(coded) => ErrorRecord::new(coded).with_message(message),
+        None => ErrorRecord::other(message),
+    }
+}
 
-    let file = File::open(args[1].as_str())
-        .wrap_err_with(|| format!("Can not open file `{}`", args[1].as_str()))?;
-    let mut rdr = csv::ReaderBuilder::new()
+/// Returns new `Repository` configured with `config`, with the policies and rates it enables
+fn repository(config: &Config) -> Result<Repository> {
+    let mut repo = Repository::with_config(config);
+    register_policies(&mut repo, config);
+    if let Some(path) = config.fx().rates() {
+        repo.set_rates(RateTable::load(path)?);
+    }
+
+    Ok(repo)
+}
+
+/// Loads the opening balances and open disputes given in the `args`, if any, returning the audit entries of the loaded state
+fn load_opening_state(repo: &mut Repository, args: &Args) -> Result<Vec<AuditEntry>> {
+    let mut entries = Vec::new();
+    if let Some(path) = args.opening_balances() {
+        entries.extend(opening::load_balances(path, repo)?);
+    }
+    if let Some(path) = args.open_disputes() {
+        entries.extend(opening::load_disputes(path, repo)?);
+    }
+
+    Ok(entries)
+}
+
+/// Reads the input CSV file at `path`, calling `f` with every record and the transaction parsed from it
+fn read_transactions(
+    path: &str,
+    progress: bool,
+    mut f: impl FnMut(&InputRecord, Transaction) -> Result<()>,
+) -> Result<()> {
+    for result in transactions(path, progress)? {
+        let (record, transaction) = result?;
+        f(&record, transaction)?;
+    }
+
+    Ok(())
+}
+
+/// Returns an iterator over the records of the input CSV file at `path`, and the transactions parsed from them
+fn transactions(
+    path: &str,
+    progress: bool,
+) -> Result<impl Iterator<Item = Result<(InputRecord, Transaction)>>> {
+    let file = File::open(path).wrap_err_with(|| format!("Can not open file `{}`", path))?;
+    let size = file.metadata()?.len();
+    let file = CountingReader::new(file);
+    // dropped together with the iterator, drawing the final state
+    let mut progress = match progress {
+        true => Progress::new(size, file.count()),
+        false => None,
+    };
+    let rdr = csv::ReaderBuilder::new()
         .flexible(true)
         .trim(Trim::All)
         .from_reader(file);
-    for result in rdr.deserialize() {
-        let record: InputRecord = result?;
-        let transaction: Transaction = record.try_into()?;
 
+    let span = tracing::info_span!("read_input", path);
+    Ok(rdr.into_deserialize().map(move |result| {
+        let _entered = span.enter();
+        if let Some(progress) = &mut progress {
+            progress.row();
+        }
+        let record: InputRecord =
+            result.inspect_err(|e| tracing::warn!(error = %e, "malformed input record"))?;
+        tracing::trace!(
+            client = record.client(),
+            tx = record.tx(),
+            kind = %record.r#type(),
+            "input record read"
+        );
+        let transaction: Transaction = (&record).try_into().inspect_err(|e| {
+            tracing::warn!(
+                client = record.client(),
+                tx = record.tx(),
+                error = %e,
+                "invalid input record"
+            )
+        })?;
+        Ok((record, transaction))
+    }))
+}
+
+/// Processes the input and outputs the balances of all the clients
+fn process(args: &Args, config: &Config) -> Result<()> {
+    // `Repository` holds the internal state of the clients
+    let mut repo = repository(config)?;
+
+    let events_wtr = match args.events() {
+        Some(path) => {
+            let file = File::create(path)
+                .wrap_err_with(|| format!("Can not create events file `{}`", path))?;
+            Some(Rc::new(RefCell::new(csv::Writer::from_writer(file))))
+        }
+        None => None,
+    };
+    let format = *args.error_format();
+    if let Some(wtr) = events_wtr.clone() {
+        repo.subscribe(Rc::new(move |e: &Event| {
+            let record: EventRecord = e.into();
+            if let Err(e) = wtr.borrow_mut().serialize(record) {
+                report(
+                    format,
+                    "ERROR",
+                    &ErrorRecord::new(&OutputError::Event(e.to_string())),
+                );
+            }
+        }));
+    }
+
+    // every movement of funds is posted to the ledger, checked by the trial balance at the end of the run
+    let ledger = Rc::new(RefCell::new(Ledger::default()));
+    let ledger_wtr = match args.ledger() {
+        Some(path) => {
+            let file = File::create(path)
+                .wrap_err_with(|| format!("Can not create ledger file `{}`", path))?;
+            Some(Rc::new(RefCell::new(csv::Writer::from_writer(file))))
+        }
+        None => None,
+    };
+    {
+        let ledger = ledger.clone();
+        let ledger_wtr = ledger_wtr.clone();
+        let entry = Cell::new(0);
+        repo.subscribe(Rc::new(move |e: &Event| {
+            for posting in Posting::from_event(e) {
+                ledger.borrow_mut().post(&posting);
+                if let Some(wtr) = &ledger_wtr {
+                    entry.set(entry.get() + 1);
+                    if let Err(e) = wtr
+                        .borrow_mut()
+                        .serialize(PostingRecord::new(entry.get(), &posting))
+                    {
+                        report(
+                            format,
+                            "ERROR",
+                            &ErrorRecord::new(&OutputError::Posting(e.to_string())),
+                        );
+                    }
+                }
+            }
+        }));
+    }
+
+    // accepted transactions and the actions of the engine itself are chained in the audit log
+    let audit = match args.audit() {
+        Some(path) => {
+            let mut audit = AuditLog::open(path)?;
+            let detail = format!(
+                "input={} config={}",
+                args.input(),
+                args.config().as_deref().unwrap_or_default()
+            );
+            audit.append(&AuditEntry::admin("run_started", detail))?;
+            Some(Rc::new(RefCell::new(audit)))
+        }
+        None => None,
+    };
+    // an expiry that can not be audited fails the run, once the transaction that caused it is registered
+    let audit_error = Rc::new(RefCell::new(None));
+    if let Some(audit) = audit.clone() {
+        let audit_error = audit_error.clone();
+        repo.subscribe(Rc::new(move |e: &Event| {
+            if let EventKind::DisputeExpired | EventKind::AuthorizationExpired = e.kind() {
+                if let Err(e) = audit.borrow_mut().append(&AuditEntry::event(e)) {
+                    audit_error.borrow_mut().get_or_insert(e);
+                }
+            }
+        }));
+    }
+
+    let metrics = Rc::new(RefCell::new(Metrics::start()));
+    {
+        let metrics = metrics.clone();
+        repo.subscribe(Rc::new(move |e: &Event| {
+            metrics.borrow_mut().record_event(e)
+        }));
+    }
+
+    // loaded once the subscribers are registered, so that the opening balances are posted to the ledger
+    let entries = load_opening_state(&mut repo, args)?;
+    if let Some(audit) = &audit {
+        for entry in &entries {
+            audit.borrow_mut().append(entry)?;
+        }
+    }
+
+    read_transactions(args.input(), *args.progress(), |record, transaction| {
         let result = repo.register_transaction(transaction);
-        if let Err(e) = result {
+        if let Some(e) = audit_error.borrow_mut().take() {
+            return Err(e.wrap_err("Can not write audit log"));
+        }
+        report_expiry_errors(&mut repo, format);
+        metrics.borrow_mut().record(&transaction, &result);
+        match result {
+            Ok(_) => {
+                if let Some(audit) = &audit {
+                    audit
+                        .borrow_mut()
+                        .append(&AuditEntry::transaction(record, &transaction))?;
+                }
+            }
             // In real system this would probably be logged in some other system
-            eprintln!("ERROR: {}", e)
+            Err(e) => report(format, "ERROR", &ErrorRecord::new(&e).with_record(record)),
         }
+        Ok(())
+    })?;
+
+    write_balances(&repo.iter_clients().collect::<Vec<_>>())?;
+
+    let mut metrics = metrics.borrow_mut();
+    metrics.finish(repo.iter_clients());
+    if *args.summary() {
+        metrics.write_summary(std::io::stderr().lock())?;
+    }
+    if let Some(path) = args.metrics() {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("Can not create metrics file `{}`", path))?;
+        metrics.write_prometheus(file)?;
+    }
+
+    if let Some(wtr) = events_wtr {
+        wtr.borrow_mut().flush()?;
+    }
+    if let Some(wtr) = ledger_wtr {
+        wtr.borrow_mut().flush()?;
+    }
+    if let Some(audit) = audit {
+        audit.borrow_mut().flush()?;
+    }
+
+    if let Some(path) = args.fx_report() {
+        let mut wtr = csv::Writer::from_path(path)
+            .wrap_err_with(|| format!("Can not create FX report file `{}`", path))?;
+        for (currency, pnl) in repo.fx_pnl() {
+            wtr.serialize(PnlRecord::new(*currency, pnl))?;
+        }
+        wtr.flush()?;
+    }
+
+    // checked once everything is written, so that a failed check does not hold back the outputs
+    ledger
+        .borrow()
+        .trial_balance(repo.iter_clients())
+        .wrap_err("Trial balance failed")?;
+
+    Ok(())
+}
+
+/// Outputs the balances of the `clients`
+fn write_balances(clients: &[&Client]) -> Result<()> {
+    // the optional columns are output only if any funds are held in other than the main account,
+    // in an explicit currency, or if any client has an overdraft
+    let columns = OutputColumns {
+        account: clients
+            .iter()
+            .any(|c| c.balances().keys().any(|(a, _)| !a.is_main())),
+        currency: clients
+            .iter()
+            .any(|c| c.balances().keys().any(|(_, c)| !c.is_none())),
+        credit: clients.iter().any(|c| c.limits().credit_limit().is_some()),
+    };
+    let mut wtr = csv::Writer::from_writer(std::io::stdout());
+    for c in clients {
+        for or in OutputRecord::rows(c, columns) {
+            wtr.serialize(or)?;
+        }
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Outputs every transaction of a single client, with the balances after each of them
+fn statement(args: &Args, s: &StatementArgs, config: &Config) -> Result<()> {
+    let mut repo = repository(config)?;
+    load_opening_state(&mut repo, args)?;
+    let mut statement = Statement::new(*s.client(), *s.filter());
+    repo.subscribe(statement.subscriber());
+    read_transactions(args.input(), *args.progress(), |record, transaction| {
+        // the expiries due by the transaction are recorded before it, with the balances right after them.
+        // A failed expiry is retried, and reported, when the transaction is registered.
+        if let Some(ts) = transaction.timestamp() {
+            if repo.advance_clock(ts).is_ok() {
+                statement.record_expiries(&repo);
+            }
+        }
+        let result = repo.register_transaction(transaction);
+        report_expiry_errors(&mut repo, *args.error_format());
+        statement.record(record, &transaction, &result, &repo);
+        Ok(())
+    })?;
+
+    match s.format() {
+        Format::Csv => statement.write_csv(std::io::stdout()),
+        Format::Table => statement.write_table(std::io::stdout()),
+    }
+}
+
+/// Verifies the hash chain of the audit log, failing on the first tampered entry, or if it does not reach the recorded heads
+fn verify_audit(args: &Args, v: &VerifyAuditArgs) -> Result<()> {
+    let path = args.input();
+    let heads: Vec<_> = Head::read(path)?
+        .into_iter()
+        .chain(v.head().clone())
+        .collect();
+    let file = File::open(path).wrap_err_with(|| format!("Can not open audit log `{}`", path))?;
+    let head = audit::verify(file, &heads)?;
+    println!(
+        "Audit log `{}` is intact: {} entries, head `{}`",
+        path,
+        head.seq(),
+        head
+    );
+
+    Ok(())
+}
+
+/// Compares the balances of all the clients with the expected ones, failing if there are any breaks
+fn reconcile(args: &Args, r: &ReconcileArgs, config: &Config) -> Result<()> {
+    let expected = snapshot::load(r.expected())?;
+    let mut repo = repository(config)?;
+    load_opening_state(&mut repo, args)?;
+    read_transactions(args.input(), *args.progress(), |record, transaction| {
+        if let Err(e) = repo.register_transaction(transaction) {
+            report(
+                *args.error_format(),
+                "ERROR",
+                &ErrorRecord::new(&e).with_record(record),
+            );
+        }
+        report_expiry_errors(&mut repo, *args.error_format());
+        Ok(())
+    })?;
+
+    let breaks = reconcile::reconcile(
+        &expected,
+        &snapshot::of(repo.iter_clients()),
+        *r.tolerance(),
+    );
+    let mut wtr = csv::Writer::from_writer(std::io::stdout());
+    for b in &breaks {
+        wtr.serialize(BreakRecord::from(b))?;
+    }
+    wtr.flush()?;
+
+    if !breaks.is_empty() {
+        bail!("Reconciliation found {} breaks", breaks.len());
     }
 
+    Ok(())
+}
+
+/// Compares two balance outputs, or two open disputes files, reporting the added, removed and changed
+/// balances or disputes
+fn diff(args: &Args, d: &DiffArgs) -> Result<()> {
+    let changes = match *d.disputes() {
+        true => {
+            let before = snapshot::load_disputes(d.before())?;
+            let after = snapshot::load_disputes(args.input())?;
+            let differences = diff::diff_disputes(&before, &after);
+            let rows: Vec<_> = differences
+                .iter()
+                .flat_map(DisputeDiffRecord::rows)
+                .collect();
+            write_rows(
+                &DisputeDiffRecord::COLUMNS,
+                &rows,
+                DisputeDiffRecord::cells,
+                *d.format(),
+            )?;
+            differences.iter().map(|d| *d.change()).collect()
+        }
+        false => {
+            let before = snapshot::load(d.before())?;
+            let after = snapshot::load(args.input())?;
+            let differences = diff::diff(&before, &after);
+            write_differences(&differences, *d.format())?;
+            differences.iter().map(|d| *d.change()).collect::<Vec<_>>()
+        }
+    };
+    if *d.format() == Format::Table {
+        let count = |change| changes.iter().filter(|c| **c == change).count();
+        println!(
+            "\n{} added, {} removed, {} changed",
+            count(Change::Added),
+            count(Change::Removed),
+            count(Change::Changed)
+        );
+    }
+
+    Ok(())
+}
+
+/// Outputs the balance `differences`, one row per changed field
+fn write_differences(differences: &[Difference<BalanceKey>], format: Format) -> Result<()> {
+    let rows: Vec<_> = differences.iter().flat_map(DiffRecord::rows).collect();
+    write_rows(&DiffRecord::COLUMNS, &rows, DiffRecord::cells, format)
+}
+
+/// Outputs the `rows` with given `columns`, as CSV or as a table of their `cells`
+fn write_rows<R: Serialize>(
+    columns: &[&str],
+    rows: &[R],
+    cells: impl Fn(&R) -> Vec<String>,
+    format: Format,
+) -> Result<()> {
+    match format {
+        Format::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(std::io::stdout());
+            wtr.write_record(columns)?;
+            for row in rows {
+                wtr.serialize(row)?;
+            }
+            wtr.flush()?;
+        }
+        Format::Table => {
+            let cells: Vec<_> = rows.iter().map(cells).collect();
+            table::write(std::io::stdout(), columns, &cells)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the batch of transactions to the state left by the input, and outputs the balances it changes,
+/// or, if committed, the resulting balances of all the clients
+fn simulate(args: &Args, s: &SimulateArgs, config: &Config) -> Result<()> {
+    let mut repo = repository(config)?;
+    load_opening_state(&mut repo, args)?;
+    read_transactions(args.input(), *args.progress(), |record, transaction| {
+        if let Err(e) = repo.register_transaction(transaction) {
+            report(
+                *args.error_format(),
+                "ERROR",
+                &ErrorRecord::new(&e).with_record(record),
+            );
+        }
+        report_expiry_errors(&mut repo, *args.error_format());
+        Ok(())
+    })?;
+
+    let mut simulation = repo.simulate();
+    let mut rejected = 0;
+    read_transactions(s.batch(), false, |record, transaction| {
+        if let Err(e) = simulation.register_transaction(transaction) {
+            report(
+                *args.error_format(),
+                "REJECTED",
+                &ErrorRecord::new(&e).with_record(record),
+            );
+            rejected += 1;
+        }
+        for e in simulation.take_expiry_errors() {
+            report(*args.error_format(), "ERROR", &ErrorRecord::new(&e));
+        }
+        Ok(())
+    })?;
+
+    if *s.commit() {
+        simulation.commit()?;
+        return write_balances(&repo.iter_clients().collect::<Vec<_>>());
+    }
+
+    let changes: Vec<_> = simulation.changes().collect();
+    let before = snapshot::of(changes.iter().filter_map(|(before, _)| *before));
+    let after = snapshot::of(changes.iter().map(|(_, after)| *after));
+    let differences = diff::diff(&before, &after);
+    write_differences(&differences, *s.format())?;
+    if *s.format() == Format::Table {
+        println!(
+            "\n{} balances changed, {} transactions rejected",
+            differences.len(),
+            rejected
+        );
+    }
+
+    Ok(())
+}
+
+/// Outputs the balances, or the open disputes, of the clients at the point of the input given in the `args`
+fn query(args: &Args, q: &QueryArgs, config: &Config) -> Result<()> {
+    let mut repo = repository(config)?;
+    load_opening_state(&mut repo, args)?;
+    query::replay(
+        &mut repo,
+        transactions(args.input(), *args.progress())?,
+        *q.as_of(),
+        *q.client(),
+        |record, e| {
+            report(
+                *args.error_format(),
+                "ERROR",
+                &ErrorRecord::new(e).with_record(record),
+            )
+        },
+    )?;
+
+    let mut clients: Vec<_> = repo
+        .iter_clients()
+        .filter(|c| q.client().map_or(true, |id| *c.id() == id))
+        .collect();
+    clients.sort_by_key(|c| *c.id());
+
+    if !*q.disputes() {
+        return write_balances(&clients);
+    }
+
+    // in the shape of the open disputes file, so that it can be loaded by the next run
     let mut wtr = csv::Writer::from_writer(std::io::stdout());
-    for c in repo.iter_clients() {
-        let or: OutputRecord = c.into();
-        wtr.serialize(or)?;
+    for c in clients {
+        for (tx, deposit, disputed_at) in repo.open_disputes(*c.id())? {
+            wtr.serialize(OpenDisputeRecord::new(*c.id(), tx, &deposit, disputed_at))?;
+        }
     }
+    wtr.flush()?;
 
     Ok(())
 }
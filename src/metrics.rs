@@ -0,0 +1,321 @@
+//! Processing metrics of a run - outcomes of the transactions, funds moved and throughput
+
+use crate::{
+    currency::Currency,
+    errors::{ErrorCode, RepositoryError},
+    events::{Event, EventKind},
+    repo::Client,
+    table,
+    transaction::Transaction,
+};
+use color_eyre::Result;
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// Funds moved in a single currency
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Totals {
+    deposited: f64,
+    withdrawn: f64,
+    /// Funds ever held by disputes, including the ones released or charged back since
+    held_by_disputes: f64,
+    charged_back: f64,
+}
+
+impl Totals {
+    const NAMES: [&'static str; 4] = ["deposited", "withdrawn", "held_by_disputes", "charged_back"];
+
+    fn values(&self) -> [f64; 4] {
+        [
+            self.deposited,
+            self.withdrawn,
+            self.held_by_disputes,
+            self.charged_back,
+        ]
+    }
+}
+
+/// Metrics collected while the input is processed
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    started: Instant,
+
+    /// Accepted and rejected transactions, per type
+    transactions: BTreeMap<&'static str, (u64, u64)>,
+
+    /// Rejected transactions, per error name, with the error code
+    rejections: BTreeMap<&'static str, (u16, u64)>,
+
+    totals: BTreeMap<Currency, Totals>,
+
+    /// Clients with the client itself, or any of its accounts, locked. Known once finished.
+    locked_clients: usize,
+
+    /// Known once finished
+    elapsed: Duration,
+}
+
+impl Metrics {
+    /// Starts measuring the run
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            transactions: BTreeMap::new(),
+            rejections: BTreeMap::new(),
+            totals: BTreeMap::new(),
+            locked_clients: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Records the outcome of the `transaction`
+    pub fn record<T>(&mut self, transaction: &Transaction, result: &Result<T, RepositoryError>) {
+        let (accepted, rejected) = self.transactions.entry(transaction.name()).or_default();
+        match result {
+            Ok(_) => *accepted += 1,
+            Err(e) => {
+                *rejected += 1;
+                self.rejections.entry(e.name()).or_insert((e.code(), 0)).1 += 1;
+            }
+        }
+    }
+
+    /// Adds the funds moved by the `event` to the totals
+    pub fn record_event(&mut self, event: &Event) {
+        let totals = match event.kind().currency() {
+            Some(currency) => self.totals.entry(currency).or_default(),
+            None => return,
+        };
+        let amount = event.kind().amount().unwrap_or_default();
+        match event.kind() {
+            EventKind::FundsDeposited { .. } => totals.deposited += amount,
+            EventKind::FundsWithdrawn { .. } => totals.withdrawn += amount,
+            EventKind::FundsHeld { .. } => totals.held_by_disputes += amount,
+            EventKind::ChargedBack { .. } => totals.charged_back += amount,
+            _ => {}
+        }
+    }
+
+    /// Stops measuring the run, counting the locked `clients`
+    pub fn finish<'a>(&mut self, clients: impl Iterator<Item = &'a Client>) {
+        self.elapsed = self.started.elapsed();
+        self.locked_clients = clients
+            .filter(|c| *c.locked() || !c.locked_accounts().is_empty())
+            .count();
+    }
+
+    /// Returns the number of transactions read, accepted or not
+    pub fn count(&self) -> u64 {
+        self.transactions.values().map(|(a, r)| a + r).sum()
+    }
+
+    /// Returns the number of transactions processed per second
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.count() as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Writes the summary of the run as tables aligned for reading
+    pub fn write_summary<W: Write>(&self, mut w: W) -> Result<()> {
+        writeln!(
+            w,
+            "Processed {} transactions in {:.3} s ({:.0} transactions/s)",
+            self.count(),
+            self.elapsed.as_secs_f64(),
+            self.throughput()
+        )?;
+
+        writeln!(w)?;
+        let rows: Vec<_> = self
+            .transactions
+            .iter()
+            .map(|(name, (a, r))| vec![name.to_string(), a.to_string(), r.to_string()])
+            .collect();
+        table::write(&mut w, &["type", "accepted", "rejected"], &rows)?;
+
+        if !self.rejections.is_empty() {
+            writeln!(w)?;
+            let rows: Vec<_> = self
+                .rejections
+                .iter()
+                .map(|(name, (code, n))| vec![code.to_string(), name.to_string(), n.to_string()])
+                .collect();
+            table::write(&mut w, &["code", "error", "rejected"], &rows)?;
+        }
+
+        if !self.totals.is_empty() {
+            writeln!(w)?;
+            let rows: Vec<_> = self
+                .totals
+                .iter()
+                .map(|(currency, totals)| {
+                    let mut row = vec![currency.to_string()];
+                    row.extend(totals.values().iter().map(|v| format!("{:.4}", v)));
+                    row
+                })
+                .collect();
+            let mut columns = vec!["currency"];
+            columns.extend(Totals::NAMES);
+            table::write(&mut w, &columns, &rows)?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Locked clients: {}", self.locked_clients)?;
+
+        Ok(())
+    }
+
+    /// Writes the metrics in the Prometheus text exposition format
+    pub fn write_prometheus<W: Write>(&self, mut w: W) -> Result<()> {
+        let header = |w: &mut W, name: &str, kind: &str, help: &str| -> std::io::Result<()> {
+            writeln!(w, "# HELP payments_{} {}", name, help)?;
+            writeln!(w, "# TYPE payments_{} {}", name, kind)
+        };
+
+        header(
+            &mut w,
+            "transactions_total",
+            "counter",
+            "Transactions read from the input, by type and outcome.",
+        )?;
+        for (name, (accepted, rejected)) in &self.transactions {
+            for (outcome, n) in [("accepted", accepted), ("rejected", rejected)] {
+                writeln!(
+                    w,
+                    "payments_transactions_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                    name, outcome, n
+                )?;
+            }
+        }
+
+        header(
+            &mut w,
+            "rejections_total",
+            "counter",
+            "Rejected transactions, by error.",
+        )?;
+        for (name, (code, n)) in &self.rejections {
+            writeln!(
+                w,
+                "payments_rejections_total{{error=\"{}\",code=\"{}\"}} {}",
+                name, code, n
+            )?;
+        }
+
+        header(
+            &mut w,
+            "funds_total",
+            "counter",
+            "Funds moved, by movement and currency.",
+        )?;
+        for (currency, totals) in &self.totals {
+            // an empty label value is the same as a missing label in Prometheus
+            let currency = match currency.is_none() {
+                true => "default".to_owned(),
+                false => currency.to_string(),
+            };
+            for (movement, value) in Totals::NAMES.iter().zip(totals.values()) {
+                writeln!(
+                    w,
+                    "payments_funds_total{{movement=\"{}\",currency=\"{}\"}} {}",
+                    movement, currency, value
+                )?;
+            }
+        }
+
+        header(
+            &mut w,
+            "locked_clients",
+            "gauge",
+            "Clients with the client or any of its accounts locked.",
+        )?;
+        writeln!(w, "payments_locked_clients {}", self.locked_clients)?;
+
+        header(&mut w, "elapsed_seconds", "gauge", "Duration of the run.")?;
+        writeln!(w, "payments_elapsed_seconds {}", self.elapsed.as_secs_f64())?;
+
+        header(
+            &mut w,
+            "throughput_transactions_per_second",
+            "gauge",
+            "Transactions processed per second.",
+        )?;
+        writeln!(
+            w,
+            "payments_throughput_transactions_per_second {}",
+            self.throughput()
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use crate::{
+        events::Event,
+        repo::Repository,
+        transaction::{Transaction, TransactionData, TransactionDataAmount},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn metrics() -> Metrics {
+        let mut repo = Repository::new();
+        let metrics = Rc::new(RefCell::new(Metrics::start()));
+        let m = metrics.clone();
+        repo.subscribe(Rc::new(move |e: &Event| m.borrow_mut().record_event(e)));
+
+        for tr in [
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 5.0).unwrap()),
+            Transaction::Withdrawal(TransactionDataAmount::new(1, 2, 9.0).unwrap()),
+            Transaction::Withdrawal(TransactionDataAmount::new(1, 3, 1.0).unwrap()),
+            Transaction::Dispute(TransactionData::new(1, 1)),
+            Transaction::Chargeback(TransactionData::new(1, 1)),
+        ] {
+            let result = repo.register_transaction(tr);
+            metrics.borrow_mut().record(&tr, &result);
+        }
+        metrics.borrow_mut().finish(repo.iter_clients());
+
+        let metrics = metrics.borrow().clone();
+        metrics
+    }
+
+    #[test]
+    fn summary_counts_outcomes_and_funds() {
+        let mut out = Vec::new();
+        metrics().write_summary(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("Processed 5 transactions in "));
+        assert!(out.contains("withdrawal  1         1\n"));
+        assert!(out.contains("2001  insufficient_funds  1\n"));
+        assert!(out.contains("          5.0000     1.0000     5.0000            5.0000\n"));
+        assert!(out.ends_with("Locked clients: 1\n"));
+    }
+
+    #[test]
+    fn prometheus_metrics_are_labelled() {
+        let mut out = Vec::new();
+        metrics().write_prometheus(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("# TYPE payments_transactions_total counter\n"));
+        assert!(out
+            .contains("payments_transactions_total{type=\"withdrawal\",outcome=\"rejected\"} 1\n"));
+        assert!(out
+            .contains("payments_rejections_total{error=\"insufficient_funds\",code=\"2001\"} 1\n"));
+        assert!(
+            out.contains("payments_funds_total{movement=\"deposited\",currency=\"default\"} 5\n")
+        );
+        assert!(out.contains("payments_locked_clients 1\n"));
+    }
+}
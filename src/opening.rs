@@ -0,0 +1,93 @@
+//! Opening state - balances and open disputes carried over from the previous run
+
+use crate::{
+    account::Account,
+    audit::AuditEntry,
+    currency::Currency,
+    dto::{OpenDisputeRecord, OpeningBalanceRecord},
+    repo::Repository,
+    transaction::ClientId,
+};
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use csv::Trim;
+use std::{collections::BTreeMap, fs::File};
+
+/// Tolerance of the check that the open disputes are covered by the held funds
+const EPSILON: f64 = 1e-6;
+
+/// Loads the opening balances from CSV file at `path` into the `repo`, returning the audit entries of the loaded balances
+pub fn load_balances(path: &str, repo: &mut Repository) -> Result<Vec<AuditEntry>> {
+    let file = File::open(path)
+        .wrap_err_with(|| format!("Can not open opening balances file `{}`", path))?;
+    let mut rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(file);
+
+    let mut entries = Vec::new();
+    for result in rdr.deserialize() {
+        let record: OpeningBalanceRecord =
+            result.wrap_err_with(|| format!("Invalid opening balances file `{}`", path))?;
+        let (client, account, currency, available, held, locked) = record
+            .parse()
+            .wrap_err_with(|| format!("Invalid opening balances file `{}`", path))?;
+        repo.open_balance(client, account, currency, available, held, locked)
+            .wrap_err_with(|| format!("Invalid opening balances file `{}`", path))?;
+        entries.push(AuditEntry::opening_balance(
+            path,
+            client,
+            account,
+            currency,
+            (available, held, locked),
+        ));
+    }
+
+    Ok(entries)
+}
+
+/// Loads the open disputes from CSV file at `path` into the `repo`, returning the audit entries of the loaded disputes.
+/// The opening balances have to be loaded first, as the disputed funds have to be held already.
+pub fn load_disputes(path: &str, repo: &mut Repository) -> Result<Vec<AuditEntry>> {
+    let file =
+        File::open(path).wrap_err_with(|| format!("Can not open open disputes file `{}`", path))?;
+    let mut rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(file);
+
+    let mut disputed: BTreeMap<(ClientId, Account, Currency), f64> = BTreeMap::new();
+    let mut entries = Vec::new();
+    for result in rdr.deserialize() {
+        let record: OpenDisputeRecord =
+            result.wrap_err_with(|| format!("Invalid open disputes file `{}`", path))?;
+        let (client, tx, deposit, disputed_at) = record
+            .parse()
+            .wrap_err_with(|| format!("Invalid open disputes file `{}`", path))?;
+        repo.open_dispute(client, tx, deposit, disputed_at)
+            .wrap_err_with(|| format!("Invalid open disputes file `{}`", path))?;
+        entries.push(AuditEntry::opening_dispute(
+            path,
+            client,
+            tx,
+            &deposit,
+            disputed_at,
+        ));
+        *disputed
+            .entry((client, deposit.account, deposit.currency))
+            .or_default() += deposit.amount;
+    }
+
+    for ((client, account, currency), amount) in disputed {
+        let held = repo
+            .client(client)
+            .map(|c| *c.account_balance(account, currency).held())
+            .unwrap_or_default();
+        if amount > held + EPSILON {
+            bail!(
+                "Open disputes of account `{}` of client `{}` in currency `{}` exceed its held funds",
+                account,
+                client,
+                currency
+            );
+        }
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,275 @@
+//! Transaction policies - custom business rules consulted by the `Repository`
+
+use crate::{
+    events::{Event, EventKind},
+    repo::Client,
+    transaction::{ClientId, Transaction},
+};
+use std::{collections::HashSet, fmt::Debug, rc::Rc};
+
+/// Business rule consulted before and after every transaction is applied
+pub trait TransactionPolicy: Debug {
+    /// Name of the policy, used in errors and annotations
+    fn name(&self) -> &'static str;
+
+    /// Called before `transaction` is applied to `client`. Returning an error vetoes the transaction.
+    fn before(&self, _client: &Client, _transaction: &Transaction) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called after `transaction` was applied to `client`. Can return a note annotating the transaction.
+    fn after(
+        &self,
+        _client: &Client,
+        _transaction: &Transaction,
+        _events: &[Event],
+    ) -> Option<String> {
+        None
+    }
+}
+
+/// Ordered chain of policies
+#[derive(Debug, Clone, Default)]
+pub struct PolicyRegistry {
+    policies: Vec<Rc<dyn TransactionPolicy>>,
+}
+
+impl PolicyRegistry {
+    /// Appends the `policy` to the end of the chain
+    pub fn register(&mut self, policy: Rc<dyn TransactionPolicy>) {
+        self.policies.push(policy);
+    }
+
+    /// Consults all the policies in order, returning the name of the first policy that vetoed the transaction with its reason
+    pub fn before(
+        &self,
+        client: &Client,
+        transaction: &Transaction,
+    ) -> Result<(), (&'static str, String)> {
+        for p in &self.policies {
+            p.before(client, transaction).map_err(|e| (p.name(), e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Consults all the policies in order, returning annotations made by them
+    pub fn after(
+        &self,
+        client: &Client,
+        transaction: &Transaction,
+        events: &[Event],
+    ) -> Vec<Event> {
+        self.policies
+            .iter()
+            .filter_map(|p| {
+                p.after(client, transaction, events).map(|note| {
+                    Event::new(
+                        *client.id(),
+                        transaction.tx(),
+                        transaction.timestamp(),
+                        EventKind::Annotated {
+                            policy: p.name(),
+                            note,
+                        },
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Rejects deposits made by listed clients
+#[derive(Debug, Clone)]
+pub struct BlockedDeposits {
+    clients: HashSet<ClientId>,
+}
+
+impl BlockedDeposits {
+    pub fn new(clients: HashSet<ClientId>) -> Self {
+        Self { clients }
+    }
+}
+
+impl TransactionPolicy for BlockedDeposits {
+    fn name(&self) -> &'static str {
+        "blocked_deposits"
+    }
+
+    fn before(&self, client: &Client, transaction: &Transaction) -> Result<(), String> {
+        if matches!(transaction, Transaction::Deposit(_)) && self.clients.contains(client.id()) {
+            return Err(format!(
+                "deposits from client `{}` are blocked",
+                client.id()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Rejects withdrawals and captures above `max_amount` for clients with less than `min_transactions` deposits, withdrawals and captures
+#[derive(Debug, Clone)]
+pub struct NewClientWithdrawals {
+    max_amount: f64,
+    min_transactions: usize,
+}
+
+impl NewClientWithdrawals {
+    pub fn new(max_amount: f64, min_transactions: usize) -> Self {
+        Self {
+            max_amount,
+            min_transactions,
+        }
+    }
+}
+
+impl TransactionPolicy for NewClientWithdrawals {
+    fn name(&self) -> &'static str {
+        "new_client_withdrawals"
+    }
+
+    fn before(&self, client: &Client, transaction: &Transaction) -> Result<(), String> {
+        let amount = match transaction {
+            Transaction::Withdrawal(data) => *data.amount(),
+            // captured funds leave the engine like withdrawn ones
+            Transaction::Capture(data) => match client.holds().get(data.tx()) {
+                Some(hold) => data.amount().unwrap_or(*hold.amount()),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        if *client.transaction_count() < self.min_transactions && amount > self.max_amount {
+            return Err(format!(
+                "withdrawals above `{}` are not allowed for new clients",
+                self.max_amount
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Annotates deposits above `threshold`
+#[derive(Debug, Clone)]
+pub struct LargeDeposits {
+    threshold: f64,
+}
+
+impl LargeDeposits {
+    pub fn new(threshold: f64) -> Self {
+        Self { threshold }
+    }
+}
+
+impl TransactionPolicy for LargeDeposits {
+    fn name(&self) -> &'static str {
+        "large_deposits"
+    }
+
+    fn after(
+        &self,
+        _client: &Client,
+        transaction: &Transaction,
+        _events: &[Event],
+    ) -> Option<String> {
+        match transaction {
+            Transaction::Deposit(data) if *data.amount() > self.threshold => {
+                Some(format!("deposit exceeds `{}`", self.threshold))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockedDeposits, LargeDeposits, NewClientWithdrawals};
+    use crate::{
+        errors::RepositoryError,
+        events::{Event, EventKind},
+        repo::Repository,
+        transaction::{CaptureData, ClientId, Transaction, TransactionDataAmount, TxId},
+    };
+    use std::rc::Rc;
+
+    fn deposit(client: ClientId, tx: TxId, amount: f64) -> Transaction {
+        Transaction::Deposit(TransactionDataAmount::new(client, tx, amount).unwrap())
+    }
+
+    fn withdrawal(client: ClientId, tx: TxId, amount: f64) -> Transaction {
+        Transaction::Withdrawal(TransactionDataAmount::new(client, tx, amount).unwrap())
+    }
+
+    #[test]
+    fn blocked_client_can_not_deposit() {
+        let mut repo = Repository::new();
+        repo.register_policy(Rc::new(BlockedDeposits::new([2].into())));
+
+        repo.register_transaction(deposit(1, 1, 1.0))
+            .expect("deposit failed");
+        let result = repo.register_transaction(deposit(2, 2, 1.0));
+
+        assert!(matches!(
+            result,
+            Err(RepositoryError::PolicyViolation(policy, _)) if policy == "blocked_deposits"
+        ));
+    }
+
+    #[test]
+    fn new_client_can_not_withdraw_above_limit() {
+        let mut repo = Repository::new();
+        repo.register_policy(Rc::new(NewClientWithdrawals::new(5.0, 2)));
+
+        repo.register_transaction(deposit(1, 1, 10.0))
+            .expect("deposit failed");
+        repo.register_transaction(withdrawal(1, 2, 10.0))
+            .expect_err("withdrawal did not fail");
+        repo.register_transaction(withdrawal(1, 3, 5.0))
+            .expect("withdrawal failed");
+        repo.register_transaction(withdrawal(1, 4, 5.0))
+            .expect("withdrawal failed");
+    }
+
+    #[test]
+    fn new_client_can_not_capture_above_limit() {
+        let mut repo = Repository::new();
+        repo.register_policy(Rc::new(NewClientWithdrawals::new(5.0, 2)));
+        let authorize =
+            |tx, amount| Transaction::Authorize(TransactionDataAmount::new(1, tx, amount).unwrap());
+        let capture = |tx, amount| Transaction::Capture(CaptureData::new(1, tx, amount).unwrap());
+
+        repo.register_transaction(deposit(1, 1, 20.0))
+            .expect("deposit failed");
+        repo.register_transaction(authorize(2, 10.0))
+            .expect("authorization failed");
+        repo.register_transaction(capture(2, None))
+            .expect_err("capture did not fail");
+        repo.register_transaction(capture(2, Some(5.0)))
+            .expect("capture failed");
+    }
+
+    #[test]
+    fn chained_policies_annotate_accepted_transactions() {
+        let mut repo = Repository::new();
+        repo.register_policy(Rc::new(BlockedDeposits::new([2].into())));
+        repo.register_policy(Rc::new(LargeDeposits::new(100.0)));
+
+        let events = repo
+            .register_transaction(deposit(1, 1, 200.0))
+            .expect("deposit failed");
+
+        assert_eq!(
+            events.last(),
+            Some(&Event::new(
+                1,
+                1,
+                None,
+                EventKind::Annotated {
+                    policy: "large_deposits",
+                    note: "deposit exceeds `100`".to_owned()
+                }
+            ))
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Progress of reading the input, shown on the standard error
+
+use std::{
+    cell::Cell,
+    io::{IsTerminal, Read, Write},
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// How often the progress is redrawn
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// Number of rows between the checks whether to redraw the progress
+const ROWS_PER_CHECK: u64 = 256;
+
+/// Whether a progress line is drawn on the standard error, unfinished
+static DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Clears the progress line, if drawn, so that the next write to the standard error starts on an empty line.
+/// The progress is drawn again when next due.
+pub fn clear() {
+    if DRAWN.swap(false, Ordering::Relaxed) {
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[K");
+        let _ = stderr.flush();
+    }
+}
+
+/// Reader counting the bytes read through it
+#[derive(Debug)]
+pub struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            count: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Returns the counter of the bytes read so far, shared with the reader
+    pub fn count(&self) -> Rc<Cell<u64>> {
+        self.count.clone()
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Progress indicator redrawn on a single line of the standard error. The final state is drawn when dropped.
+#[derive(Debug)]
+pub struct Progress {
+    /// Size of the input, zero if not known (e.g. a pipe)
+    size: u64,
+    read: Rc<Cell<u64>>,
+    rows: u64,
+    started: Instant,
+    drawn: Instant,
+}
+
+impl Progress {
+    /// Returns the progress of reading an input of `size` bytes, counted by `read`,
+    /// or none if the standard error is not a terminal
+    pub fn new(size: u64, read: Rc<Cell<u64>>) -> Option<Self> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+
+        let now = Instant::now();
+        Some(Self {
+            size,
+            read,
+            rows: 0,
+            started: now,
+            drawn: now,
+        })
+    }
+
+    /// Counts a row read, redrawing the progress if it is due
+    pub fn row(&mut self) {
+        self.rows += 1;
+        if self.rows % ROWS_PER_CHECK == 0 && self.drawn.elapsed() >= INTERVAL {
+            self.draw();
+        }
+    }
+
+    fn draw(&mut self) {
+        self.drawn = Instant::now();
+        let line = line(
+            self.read.get(),
+            self.size,
+            self.rows,
+            self.started.elapsed(),
+        );
+        // clears the rest of the previous line
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+        DRAWN.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.draw();
+        eprintln!();
+        DRAWN.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Returns the line describing the progress of reading `read` bytes out of `size`, in `rows` rows within `elapsed`
+fn line(read: u64, size: u64, rows: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    let rate = |n: u64| {
+        if seconds > 0.0 {
+            n as f64 / seconds
+        } else {
+            0.0
+        }
+    };
+    let mut line = format!("{} read", bytes(read));
+    if size > 0 {
+        // the reader runs ahead of the rows by its buffer, so the count can briefly exceed the size
+        let read = read.min(size);
+        line += &format!(
+            " of {} ({:.0}%)",
+            bytes(size),
+            read as f64 * 100.0 / size as f64
+        );
+    }
+    line += &format!(", {} rows, {:.0} rows/s", rows, rate(rows));
+    if size > 0 && read < size && rate(read) > 0.0 {
+        let eta = Duration::from_secs_f64((size - read) as f64 / rate(read));
+        line += &format!(", ETA {}", duration(eta));
+    }
+
+    line
+}
+
+/// Returns the number of bytes in the largest unit that keeps it at least 1
+fn bytes(n: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Returns the `duration` in hours, minutes and seconds
+fn duration(duration: Duration) -> String {
+    let s = duration.as_secs();
+    match (s / 3600, s / 60 % 60, s % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bytes, line, CountingReader};
+    use std::{io::Read, time::Duration};
+
+    #[test]
+    fn bytes_read_are_counted() {
+        let mut rdr = CountingReader::new("type,client,tx\n".as_bytes());
+        let count = rdr.count();
+        let mut out = String::new();
+        rdr.read_to_string(&mut out).unwrap();
+
+        assert_eq!(count.get(), 15);
+    }
+
+    #[test]
+    fn line_shows_rate_and_eta() {
+        let mib = 1024 * 1024;
+
+        assert_eq!(
+            line(mib, 4 * mib, 20000, Duration::from_secs(2)),
+            "1.0 MiB read of 4.0 MiB (25%), 20000 rows, 10000 rows/s, ETA 6s"
+        );
+        assert_eq!(
+            line(mib, 0, 20000, Duration::from_secs(2)),
+            "1.0 MiB read, 20000 rows, 10000 rows/s"
+        );
+        assert_eq!(bytes(512), "512 B");
+        assert_eq!(bytes(3 * 1024 * mib / 2), "1.5 GiB");
+    }
+}
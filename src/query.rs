@@ -0,0 +1,188 @@
+//! Point-in-time queries over the client state
+
+use crate::{
+    dto::InputRecord,
+    errors::RepositoryError,
+    repo::Repository,
+    transaction::{ClientId, Timestamp, Transaction, TxId},
+};
+use color_eyre::{eyre::bail, Result};
+
+/// Point of the input the state is queried at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Right after the transaction with the id was registered
+    Tx(TxId),
+
+    /// After all the transactions up to the time (inclusive)
+    Time(Timestamp),
+}
+
+/// Registers the `transactions` in `repo` up to the point `as_of`, or all of them if not given,
+/// calling `rejected` with every rejected one. The rest of the input is not read.
+/// As the transaction ids are unique per client only, `AsOf::Tx` is the transaction of the `client` if given,
+/// or else of whichever client creates the id first.
+pub fn replay(
+    repo: &mut Repository,
+    transactions: impl Iterator<Item = Result<(InputRecord, Transaction)>>,
+    as_of: Option<AsOf>,
+    client: Option<ClientId>,
+    mut rejected: impl FnMut(&InputRecord, &RepositoryError),
+) -> Result<()> {
+    for result in transactions {
+        let (record, transaction) = result?;
+        if let Some(AsOf::Time(time)) = as_of {
+            if transaction.timestamp().is_some_and(|ts| ts > time) {
+                break;
+            }
+        }
+
+        let result = repo.register_transaction(transaction);
+        if let Err(e) = &result {
+            rejected(&record, e);
+        }
+        // the state would be wrong from the failed expiry on
+        if let Some(e) = repo.take_expiry_errors().into_iter().next() {
+            return Err(e.into());
+        }
+
+        // only the record creating the transaction counts, not the ones referring to it or reusing its id
+        let creates = transaction.creates_tx()
+            && !matches!(result, Err(RepositoryError::DuplicateTransactionId(_)));
+        if creates
+            && as_of == Some(AsOf::Tx(transaction.tx()))
+            && client.map_or(true, |id| id == transaction.client())
+        {
+            return Ok(());
+        }
+    }
+
+    match as_of {
+        Some(AsOf::Tx(tx)) => match client {
+            Some(id) => bail!(
+                "Transaction `{}` of client `{}` not found in the input",
+                tx,
+                id
+            ),
+            None => bail!("Transaction `{}` not found in the input", tx),
+        },
+        // disputes and authorizations that would have expired by then
+        Some(AsOf::Time(time)) => repo.advance_clock(time)?,
+        None => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replay, AsOf};
+    use crate::{
+        config::Config,
+        currency::Currency,
+        dto::InputRecord,
+        repo::Repository,
+        transaction::{parse_timestamp, Transaction},
+    };
+    use std::convert::TryInto;
+
+    fn records() -> Vec<color_eyre::Result<(InputRecord, Transaction)>> {
+        vec![
+            InputRecord::new("deposit", 1, 1, Some(5.0)).with_timestamp("100"),
+            InputRecord::new("dispute", 1, 1, None).with_timestamp("200"),
+            InputRecord::new("deposit", 1, 2, Some(1.0)).with_timestamp("300"),
+        ]
+        .into_iter()
+        .map(|r| {
+            let transaction = (&r).try_into()?;
+            Ok((r, transaction))
+        })
+        .collect()
+    }
+
+    #[test]
+    fn stops_after_the_transaction() {
+        let mut repo = Repository::new();
+
+        replay(
+            &mut repo,
+            records().into_iter(),
+            Some(AsOf::Tx(1)),
+            None,
+            |_, _| {},
+        )
+        .unwrap();
+
+        let balance = repo.client(1).unwrap().balance(Currency::NONE);
+        assert_eq!(*balance.available(), 5.0);
+        assert_eq!(*balance.held(), 0.0);
+        assert!(replay(
+            &mut Repository::new(),
+            records().into_iter(),
+            Some(AsOf::Tx(3)),
+            None,
+            |_, _| {}
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn stops_after_the_transaction_of_the_client() {
+        let records = || {
+            vec![
+                InputRecord::new("deposit", 1, 1, Some(5.0)),
+                InputRecord::new("deposit", 1, 1, Some(7.0)),
+                InputRecord::new("dispute", 2, 1, None),
+                InputRecord::new("deposit", 2, 1, Some(3.0)),
+                InputRecord::new("deposit", 2, 2, Some(4.0)),
+            ]
+            .into_iter()
+            .map(|r| {
+                let transaction = (&r).try_into()?;
+                Ok((r, transaction))
+            })
+        };
+        let available = |repo: &Repository, client| {
+            repo.client(client)
+                .map(|c| *c.balance(Currency::NONE).available())
+        };
+
+        let mut repo = Repository::new();
+        replay(&mut repo, records(), Some(AsOf::Tx(1)), None, |_, _| {}).unwrap();
+        assert_eq!(available(&repo, 1), Some(5.0));
+        assert_eq!(available(&repo, 2), None);
+
+        // neither the duplicate of client 1 nor the dispute of client 2 stop the replay
+        let mut repo = Repository::new();
+        replay(&mut repo, records(), Some(AsOf::Tx(1)), Some(2), |_, _| {}).unwrap();
+        assert_eq!(available(&repo, 1), Some(5.0));
+        assert_eq!(available(&repo, 2), Some(3.0));
+
+        assert!(replay(
+            &mut Repository::new(),
+            records(),
+            Some(AsOf::Tx(2)),
+            Some(1),
+            |_, _| {}
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn stops_at_the_time_expiring_disputes() {
+        let config: Config = toml::from_str("disputes = { max_duration_seconds = 60 }").unwrap();
+        let at = |s| Some(AsOf::Time(parse_timestamp(s).unwrap()));
+
+        let mut repo = Repository::with_config(&config);
+        replay(&mut repo, records().into_iter(), at("250"), None, |_, _| {}).unwrap();
+        assert_eq!(repo.open_disputes(1).unwrap().len(), 1);
+
+        let mut repo = Repository::with_config(&config);
+        replay(&mut repo, records().into_iter(), at("299"), None, |_, _| {}).unwrap();
+        assert!(repo.open_disputes(1).unwrap().is_empty());
+        assert_eq!(
+            *repo.client(1).unwrap().balance(Currency::NONE).available(),
+            5.0
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Reconciliation of the client balances against the balances expected by another system
+
+use crate::snapshot::{BalanceKey, Snapshot, Tolerance};
+use getset::Getters;
+use std::collections::BTreeMap;
+
+/// What does not match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakKind {
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// Expected balance the engine does not have
+    Missing,
+    /// Balance of the engine that is not expected
+    Unexpected,
+}
+
+impl BreakKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BreakKind::Available => "available",
+            BreakKind::Held => "held",
+            BreakKind::Total => "total",
+            BreakKind::Locked => "locked",
+            BreakKind::Missing => "missing",
+            BreakKind::Unexpected => "unexpected",
+        }
+    }
+}
+
+/// Single mismatch between the expected and actual balance
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Break {
+    #[get = "pub"]
+    key: BalanceKey,
+
+    #[get = "pub"]
+    kind: BreakKind,
+
+    #[get = "pub"]
+    expected: Option<String>,
+
+    #[get = "pub"]
+    actual: Option<String>,
+}
+
+/// Compares the `actual` balances with the `expected` ones, returning the breaks ordered by client, account and currency
+pub fn reconcile(
+    expected: &BTreeMap<BalanceKey, Snapshot>,
+    actual: &BTreeMap<BalanceKey, Snapshot>,
+    tolerance: Tolerance,
+) -> Vec<Break> {
+    let amount = |a: f64| Some(format!("{:.4}", a));
+    let mut breaks = Vec::new();
+    for (key, e) in expected {
+        let a = match actual.get(key) {
+            Some(a) => a,
+            None => {
+                breaks.push(Break {
+                    key: *key,
+                    kind: BreakKind::Missing,
+                    expected: None,
+                    actual: None,
+                });
+                continue;
+            }
+        };
+
+        for (kind, expected, actual) in [
+            (BreakKind::Available, e.available, a.available),
+            (BreakKind::Held, e.held, a.held),
+            (BreakKind::Total, e.total, a.total),
+        ] {
+            if !tolerance.allows(expected, actual) {
+                breaks.push(Break {
+                    key: *key,
+                    kind,
+                    expected: amount(expected),
+                    actual: amount(actual),
+                });
+            }
+        }
+        if e.locked != a.locked {
+            breaks.push(Break {
+                key: *key,
+                kind: BreakKind::Locked,
+                expected: Some(e.locked.to_string()),
+                actual: Some(a.locked.to_string()),
+            });
+        }
+    }
+
+    for key in actual.keys().filter(|k| !expected.contains_key(k)) {
+        breaks.push(Break {
+            key: *key,
+            kind: BreakKind::Unexpected,
+            expected: None,
+            actual: None,
+        });
+    }
+    breaks.sort_by_key(|b| b.key);
+
+    breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconcile, BreakKind, Tolerance};
+    use crate::{account::Account, currency::Currency, snapshot::Snapshot};
+    use std::collections::BTreeMap;
+
+    fn balance(available: f64, held: f64, locked: bool) -> Snapshot {
+        Snapshot {
+            available,
+            held,
+            total: available + held,
+            locked,
+        }
+    }
+
+    #[test]
+    fn mismatches_are_reported_per_field() {
+        let key = (1, Account::MAIN, Currency::NONE);
+        let expected = BTreeMap::from([(key, balance(1.0, 0.0, false))]);
+        let actual = BTreeMap::from([(key, balance(0.5, 0.5, true))]);
+
+        let kinds: Vec<_> = reconcile(&expected, &actual, Tolerance::default())
+            .iter()
+            .map(|b| *b.kind())
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![BreakKind::Available, BreakKind::Held, BreakKind::Locked]
+        );
+    }
+
+    #[test]
+    fn missing_and_unexpected_balances_are_reported() {
+        let expected =
+            BTreeMap::from([((1, Account::MAIN, Currency::NONE), balance(1.0, 0.0, false))]);
+        let actual =
+            BTreeMap::from([((2, Account::MAIN, Currency::NONE), balance(1.0, 0.0, false))]);
+
+        let breaks = reconcile(&expected, &actual, Tolerance::default());
+
+        assert_eq!(breaks.len(), 2);
+        assert_eq!(*breaks[0].kind(), BreakKind::Missing);
+        assert_eq!(*breaks[1].kind(), BreakKind::Unexpected);
+    }
+
+    #[test]
+    fn differences_within_tolerance_are_not_breaks() {
+        let key = (1, Account::MAIN, Currency::NONE);
+        let expected = BTreeMap::from([(key, balance(100.0, 0.0, false))]);
+        let actual = BTreeMap::from([(key, balance(100.25, 0.0, false))]);
+
+        assert_eq!(reconcile(&expected, &actual, Tolerance::default()).len(), 2);
+        assert!(reconcile(&expected, &actual, Tolerance::new(0.5, 0.0)).is_empty());
+        assert!(reconcile(&expected, &actual, Tolerance::new(0.0, 0.01)).is_empty());
+        assert_eq!(
+            reconcile(&expected, &actual, Tolerance::new(0.1, 0.0)).len(),
+            2
+        );
+        assert_eq!(
+            reconcile(&expected, &actual, Tolerance::new(0.0, 0.001)).len(),
+            2
+        );
+    }
+}
@@ -2,16 +2,29 @@
 
 use getset::Getters;
 
-use crate::{errors::RepositoryError, transaction::Transaction};
-use std::collections::{HashMap, HashSet};
-
-/// Represents internal state of the client in the engine
-#[derive(Debug, Clone, Getters)]
-pub struct Client {
-    /// Client's unique id
-    #[get = "pub"]
-    id: u16,
+use crate::{
+    account::Account,
+    config::{AuthorizationsConfig, Config, DisputesConfig, ExpiryAction, LockScope},
+    currency::Currency,
+    errors::{ErrorCode, RepositoryError},
+    events::{Event, EventKind, Subscriber, Subscribers},
+    fx::{Fx, Pnl, Quote, RateTable},
+    history::{Entry, EntryKind, History},
+    limits::{Limit, Limits, LimitsConfig},
+    policy::{PolicyRegistry, TransactionPolicy},
+    transaction::{ClientId, Timestamp, Transaction, TransactionData, TxId},
+};
+use chrono::{Duration, NaiveDate};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+    rc::Rc,
+    time::Instant,
+};
 
+/// Funds of the client in a single currency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Getters)]
+pub struct Balance {
     /// Current available funds
     #[get = "pub"]
     available: f64,
@@ -20,194 +33,1445 @@ pub struct Client {
     #[get = "pub"]
     held: f64,
 
+    /// Part of the overdraft drawn by debits. Never more than the available funds are below zero,
+    /// so a shortfall caused by a dispute of already spent funds is not counted.
+    credit: f64,
+}
+
+impl Balance {
+    pub fn total(&self) -> f64 {
+        self.available + self.held
+    }
+
+    /// Returns the part of the overdraft in use
+    pub fn credit_used(&self) -> f64 {
+        self.credit
+    }
+
+    /// Adds `amount` to the available funds, repaying the overdraft as far as they get above its part
+    fn add_available(&mut self, amount: f64) {
+        self.available += amount;
+        self.credit = self.credit.min((-self.available).max(0.0));
+    }
+
+    /// Takes `amount` out of the available funds, drawing on the overdraft for the part they do not cover
+    fn take_available(&mut self, amount: f64) {
+        self.credit += amount - self.available.clamp(0.0, amount);
+        self.available -= amount;
+    }
+}
+
+/// Funds held by an authorization, until captured, voided or expired
+#[derive(Debug, Clone, Copy, PartialEq, Getters)]
+pub struct Hold {
+    #[get = "pub"]
+    account: Account,
+
+    #[get = "pub"]
+    currency: Currency,
+
+    /// Held amount
+    #[get = "pub"]
+    amount: f64,
+
+    /// Timestamp of the authorization, if known
+    #[get = "pub"]
+    timestamp: Option<Timestamp>,
+}
+
+/// Represents internal state of the client in the engine
+#[derive(Debug, Clone, Getters)]
+pub struct Client {
+    /// Client's unique id
+    #[get = "pub"]
+    id: ClientId,
+
+    /// Funds per account and currency
+    #[get = "pub"]
+    balances: BTreeMap<(Account, Currency), Balance>,
+
     /// Whether the client is locked (chargeback occured)
     #[get = "pub"]
     locked: bool,
 
-    /// Deposit and withdrawal log. On real system this should be backed by some kind of DB, as this will grow indefinitely.
+    /// Accounts locked after a chargeback, if only the account is locked
+    #[get = "pub"]
+    locked_accounts: BTreeSet<Account>,
+
+    /// What gets locked after a chargeback
+    #[get = "pub"]
+    lock_scope: LockScope,
+
+    /// Number of accepted deposits, withdrawals and captures. The transactions themselves are kept in the `History`.
+    #[get = "pub"]
+    transaction_count: usize,
+
+    /// Disputed transactions's IDs, with timestamps of the disputes
+    #[get = "pub"]
+    disputed: HashMap<TxId, Option<Timestamp>>,
+
+    /// Open authorization holds, by the authorization id
+    #[get = "pub"]
+    holds: HashMap<TxId, Hold>,
+
+    /// Reversed deposits' and withdrawals' IDs
+    #[get = "pub"]
+    reversed: HashSet<TxId>,
+
+    /// Risk limits applied to this client
+    #[get = "pub"]
+    limits: Limits,
+
+    /// Time after which a deposit can no longer be disputed
     #[get = "pub"]
-    transactions: HashMap<u32, Transaction>,
+    dispute_window: Option<Duration>,
 
-    /// Set of disputed transactions's IDs
+    /// Whether each of the most recent deposits and withdrawals was a withdrawal, oldest first
+    recent_withdrawals: VecDeque<bool>,
+
+    /// Timestamps of the most recent withdrawals, oldest first
+    recent_withdrawal_times: VecDeque<Timestamp>,
+
+    /// Day of the most recent timestamped deposit, and the totals deposited that day per currency
+    daily_deposits: Option<(NaiveDate, BTreeMap<Currency, f64>)>,
+
+    /// Timestamp of the most recent timestamped transaction
     #[get = "pub"]
-    disputed: HashSet<u32>,
+    last_timestamp: Option<Timestamp>,
 }
 
 impl Client {
     /// Creates new client with given `id`
-    pub fn new(id: u16) -> Self {
+    #[cfg(test)]
+    pub fn new(id: ClientId) -> Self {
+        Self::with_limits(id, Limits::default())
+    }
+
+    /// Creates new client with given `id` and risk `limits`
+    pub fn with_limits(id: ClientId, limits: Limits) -> Self {
         Self {
             id,
-            available: 0.0,
-            held: 0.0,
+            balances: BTreeMap::new(),
             locked: false,
-            transactions: HashMap::new(),
-            disputed: HashSet::new(),
+            locked_accounts: BTreeSet::new(),
+            lock_scope: LockScope::default(),
+            transaction_count: 0,
+            disputed: HashMap::new(),
+            holds: HashMap::new(),
+            reversed: HashSet::new(),
+            limits,
+            dispute_window: None,
+            recent_withdrawals: VecDeque::new(),
+            recent_withdrawal_times: VecDeque::new(),
+            daily_deposits: None,
+            last_timestamp: None,
+        }
+    }
+
+    /// Sets the time after which a deposit can no longer be disputed
+    pub fn with_dispute_window(mut self, dispute_window: Option<Duration>) -> Self {
+        self.dispute_window = dispute_window;
+        self
+    }
+
+    /// Sets what gets locked after a chargeback
+    pub fn with_lock_scope(mut self, lock_scope: LockScope) -> Self {
+        self.lock_scope = lock_scope;
+        self
+    }
+
+    /// Returns the funds of the main account in given `currency`
+    #[cfg(test)]
+    pub fn balance(&self, currency: Currency) -> Balance {
+        self.account_balance(Account::MAIN, currency)
+    }
+
+    /// Returns the funds of the `account` in given `currency`
+    pub fn account_balance(&self, account: Account, currency: Currency) -> Balance {
+        self.balances
+            .get(&(account, currency))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn balance_mut(&mut self, account: Account, currency: Currency) -> &mut Balance {
+        self.balances.entry((account, currency)).or_default()
+    }
+
+    /// Returns the overdraft in use in `currency`, across all the accounts
+    pub fn credit_used(&self, currency: Currency) -> f64 {
+        self.balances
+            .iter()
+            .filter(|((_, c), _)| *c == currency)
+            .map(|(_, b)| b.credit)
+            .sum()
+    }
+
+    /// Checks that `amount` can be taken out of the available funds of the `account` in `currency`.
+    /// Debits can use the overdraft the client has left in the currency, if it has one.
+    fn check_funds(
+        &self,
+        account: Account,
+        currency: Currency,
+        amount: f64,
+    ) -> Result<(), RepositoryError> {
+        let overdraft = self
+            .limits
+            .credit_limit()
+            .map_or(0.0, |limit| (limit - self.credit_used(currency)).max(0.0));
+        if self.account_balance(account, currency).available + overdraft < amount {
+            return Err(RepositoryError::InsufficientFunds(self.id));
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the `account` is locked, on its own or together with the whole client
+    pub fn is_locked(&self, account: Account) -> bool {
+        self.locked || self.locked_accounts.contains(&account)
+    }
+
+    /// Checks that the `account` accepts new transactions
+    fn check_locked(&self, account: Account) -> Result<(), RepositoryError> {
+        if self.locked {
+            return Err(RepositoryError::ClientLocked(self.id));
+        }
+        if self.locked_accounts.contains(&account) {
+            return Err(RepositoryError::AccountLocked(self.id, account.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the transaction is not older than the most recent transaction of this client
+    fn check_timestamp(&self, transaction: &Transaction) -> Result<(), RepositoryError> {
+        match (transaction.timestamp(), self.last_timestamp) {
+            (Some(ts), Some(last)) if ts < last => Err(RepositoryError::NonMonotonicTimestamp(
+                transaction.tx(),
+                self.id,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks whether deposit of `amount` in `currency` is within client's limits
+    fn check_deposit_limits(
+        &self,
+        amount: f64,
+        currency: Currency,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), RepositoryError> {
+        if let (Some(max), Some(ts)) = (self.limits.max_daily_deposit(), timestamp) {
+            let deposited = match &self.daily_deposits {
+                Some((day, totals)) if *day == ts.date_naive() => {
+                    totals.get(&currency).copied().unwrap_or_default()
+                }
+                _ => 0.0,
+            };
+            if deposited + amount > *max {
+                return Err(RepositoryError::LimitExceeded(
+                    self.id,
+                    Limit::MaxDailyDeposit,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether withdrawal of `amount` is within client's limits
+    fn check_withdrawal_limits(
+        &self,
+        amount: f64,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), RepositoryError> {
+        if let Some(max) = self.limits.max_withdrawal() {
+            if amount > *max {
+                return Err(RepositoryError::LimitExceeded(
+                    self.id,
+                    Limit::MaxWithdrawal,
+                ));
+            }
+        }
+
+        if let Some(velocity) = self.limits.withdrawal_velocity() {
+            let window = velocity.per_transactions().saturating_sub(1);
+            let withdrawals = self
+                .recent_withdrawals
+                .iter()
+                .rev()
+                .take(window)
+                .filter(|&&w| w)
+                .count();
+            if withdrawals + 1 > *velocity.max_withdrawals() {
+                return Err(RepositoryError::LimitExceeded(
+                    self.id,
+                    Limit::WithdrawalVelocity,
+                ));
+            }
+        }
+
+        if let (Some(rate), Some(ts)) = (self.limits.withdrawal_rate(), timestamp) {
+            let since = ts - Duration::seconds(*rate.window_seconds() as i64);
+            let withdrawals = self
+                .recent_withdrawal_times
+                .iter()
+                .filter(|&&t| t > since)
+                .count();
+            if withdrawals + 1 > *rate.max_withdrawals() {
+                return Err(RepositoryError::LimitExceeded(
+                    self.id,
+                    Limit::WithdrawalRate,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records accepted deposit or withdrawal for the purpose of velocity and volume limits
+    fn record_activity(
+        &mut self,
+        withdrawal: bool,
+        amount: f64,
+        currency: Currency,
+        timestamp: Option<Timestamp>,
+    ) {
+        if let Some(velocity) = self.limits.withdrawal_velocity() {
+            self.recent_withdrawals.push_back(withdrawal);
+            while self.recent_withdrawals.len() > *velocity.per_transactions() {
+                self.recent_withdrawals.pop_front();
+            }
+        }
+
+        let ts = match timestamp {
+            Some(ts) => ts,
+            None => return,
+        };
+
+        if withdrawal {
+            if let Some(rate) = self.limits.withdrawal_rate() {
+                let since = ts - Duration::seconds(*rate.window_seconds() as i64);
+                self.recent_withdrawal_times.push_back(ts);
+                while matches!(self.recent_withdrawal_times.front(), Some(&t) if t <= since) {
+                    self.recent_withdrawal_times.pop_front();
+                }
+            }
+        } else if self.limits.max_daily_deposit().is_some() {
+            let day = ts.date_naive();
+            if !matches!(&self.daily_deposits, Some((d, _)) if *d == day) {
+                self.daily_deposits = Some((day, BTreeMap::new()));
+            }
+            if let Some((_, totals)) = &mut self.daily_deposits {
+                *totals.entry(currency).or_default() += amount;
+            }
+        }
+    }
+
+    /// Sets the opening balance of the `account` in `currency`, returning the events it caused.
+    /// With `locked`, the account is locked as if after a chargeback.
+    pub fn open_balance(
+        &mut self,
+        account: Account,
+        currency: Currency,
+        available: f64,
+        held: f64,
+        locked: bool,
+    ) -> Result<Vec<Event>, RepositoryError> {
+        if self.balances.contains_key(&(account, currency)) {
+            return Err(RepositoryError::DuplicateOpeningBalance(
+                self.id,
+                account.to_string(),
+                currency.to_string(),
+            ));
+        }
+
+        // a negative opening balance is the overdraft in use, within the client's limit
+        let credit = (-available).max(0.0);
+        let credit_limit = self.limits.credit_limit().unwrap_or(0.0);
+        if credit > 0.0 && self.credit_used(currency) + credit > credit_limit {
+            return Err(RepositoryError::OpeningBalanceOverdrawn(
+                self.id,
+                account.to_string(),
+                currency.to_string(),
+            ));
+        }
+        self.balances.insert(
+            (account, currency),
+            Balance {
+                available,
+                held,
+                credit,
+            },
+        );
+        let mut events = vec![Event::new(
+            self.id,
+            0,
+            None,
+            EventKind::BalanceOpened {
+                available,
+                held,
+                currency,
+            },
+        )
+        .with_account(account)];
+        if locked {
+            let lock = Event::new(self.id, 0, None, EventKind::AccountLocked);
+            match self.lock_scope {
+                LockScope::Client if !self.locked => {
+                    self.locked = true;
+                    events.push(lock);
+                }
+                LockScope::Account if self.locked_accounts.insert(account) => {
+                    events.push(lock.with_account(account));
+                }
+                _ => {}
+            }
         }
+
+        Ok(events)
+    }
+
+    /// Releases the funds held by the authorization `tx`, returning the event it caused
+    fn release_hold(
+        &mut self,
+        tx: TxId,
+        timestamp: Option<Timestamp>,
+    ) -> Result<Event, RepositoryError> {
+        let hold = self
+            .holds
+            .remove(&tx)
+            .ok_or(RepositoryError::AuthorizationDoesNotExist(tx, self.id))?;
+
+        let balance = self.balance_mut(hold.account, hold.currency);
+        balance.held -= hold.amount;
+        balance.add_available(hold.amount);
+
+        Ok(Event::new(
+            self.id,
+            tx,
+            timestamp,
+            EventKind::AuthorizationReleased {
+                amount: hold.amount,
+                currency: hold.currency,
+            },
+        )
+        .with_account(hold.account))
+    }
+
+    /// Releases the hold of the authorization `tx`, expired at `timestamp`, returning the event it caused.
+    /// Unlike a void, the expiry releases the hold of a locked account as well.
+    pub fn expire_hold(
+        &mut self,
+        tx: TxId,
+        timestamp: Timestamp,
+    ) -> Result<Event, RepositoryError> {
+        let event = self.release_hold(tx, Some(timestamp))?;
+        self.last_timestamp = Some(timestamp);
+
+        Ok(event)
     }
 
-    /// Registers the transaction for this client
+    /// Registers the transaction for this client, returning the events it caused.
+    /// Deposits and withdrawals are recorded in, and referenced ones looked up from, the `history`.
+    /// Conversions have to be priced by the `Repository` first.
     pub fn register_transaction(
         &mut self,
         transaction: Transaction,
-    ) -> Result<(), RepositoryError> {
+        history: &mut History,
+    ) -> Result<Vec<Event>, RepositoryError> {
         let tx;
+        let timestamp = transaction.timestamp();
+        self.check_timestamp(&transaction)?;
+
+        let mut events = Vec::new();
         match transaction {
             Transaction::Deposit(data) => {
-                if self.locked {
-                    return Err(RepositoryError::ClientLocked(self.id));
-                }
+                self.check_locked(*data.account())?;
 
                 tx = data.tx().to_owned();
-                if self.transactions.keys().any(|&k| k == tx) {
+                if history.get(self.id, tx)?.is_some() {
                     return Err(RepositoryError::DuplicateTransactionId(tx));
                 }
 
-                self.available += data.amount();
-                self.transactions.insert(tx, transaction);
+                self.check_deposit_limits(*data.amount(), *data.currency(), timestamp)?;
+
+                history.insert(
+                    self.id,
+                    tx,
+                    Entry {
+                        kind: EntryKind::Deposit,
+                        amount: *data.amount(),
+                        currency: *data.currency(),
+                        account: *data.account(),
+                        timestamp,
+                    },
+                )?;
+                self.balance_mut(*data.account(), *data.currency())
+                    .add_available(*data.amount());
+                self.transaction_count += 1;
+                self.record_activity(false, *data.amount(), *data.currency(), timestamp);
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::FundsDeposited {
+                            amount: *data.amount(),
+                            currency: *data.currency(),
+                        },
+                    )
+                    .with_account(*data.account()),
+                );
             }
             Transaction::Withdrawal(data) => {
-                if self.locked {
-                    return Err(RepositoryError::ClientLocked(self.id));
-                }
+                self.check_locked(*data.account())?;
 
                 tx = data.tx().to_owned();
-                if self.transactions.keys().any(|&k| k == tx) {
+                if history.get(self.id, tx)?.is_some() {
                     return Err(RepositoryError::DuplicateTransactionId(tx));
                 }
-                if self.available < *data.amount() {
-                    return Err(RepositoryError::InsufficientFunds(*data.client()));
-                }
+                self.check_withdrawal_limits(*data.amount(), timestamp)?;
+                self.check_funds(*data.account(), *data.currency(), *data.amount())?;
 
-                self.available -= data.amount();
-                self.transactions.insert(tx, transaction);
+                history.insert(
+                    self.id,
+                    tx,
+                    Entry {
+                        kind: EntryKind::Withdrawal,
+                        amount: *data.amount(),
+                        currency: *data.currency(),
+                        account: *data.account(),
+                        timestamp,
+                    },
+                )?;
+                self.balance_mut(*data.account(), *data.currency())
+                    .take_available(*data.amount());
+                self.transaction_count += 1;
+                self.record_activity(true, *data.amount(), *data.currency(), timestamp);
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::FundsWithdrawn {
+                            amount: *data.amount(),
+                            currency: *data.currency(),
+                        },
+                    )
+                    .with_account(*data.account()),
+                );
             }
             Transaction::Dispute(data) => {
                 tx = data.tx().to_owned();
-                let org_tx = self
-                    .transactions
-                    .get(&tx)
+                let org_tx = history
+                    .get(self.id, tx)?
                     .ok_or(RepositoryError::TransactionDoesNotExist(tx, self.id))?;
 
-                if self.disputed.contains(&tx) {
+                if self.disputed.contains_key(&tx) {
                     return Err(RepositoryError::TransactionAlreadyDisputed(tx));
                 }
+                if self.reversed.contains(&tx) {
+                    return Err(RepositoryError::TransactionReversed(tx));
+                }
 
                 // I assume dispute can only be done on deposit
-                if let Transaction::Deposit(data) = org_tx {
-                    self.available -= data.amount();
-                    self.held += data.amount();
-                    self.disputed.insert(tx);
-                } else {
+                if org_tx.kind != EntryKind::Deposit {
                     return Err(RepositoryError::WrongReferenceTransactionType);
                 }
+
+                if let (Some(window), Some(deposited_at), Some(disputed_at)) =
+                    (self.dispute_window, org_tx.timestamp, timestamp)
+                {
+                    if deposited_at
+                        .checked_add_signed(window)
+                        .is_some_and(|closes_at| disputed_at > closes_at)
+                    {
+                        return Err(RepositoryError::DisputeWindowClosed(tx));
+                    }
+                }
+
+                let balance = self.balance_mut(org_tx.account, org_tx.currency);
+                balance.available -= org_tx.amount;
+                balance.held += org_tx.amount;
+                self.disputed.insert(tx, timestamp);
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::FundsHeld {
+                            amount: org_tx.amount,
+                            currency: org_tx.currency,
+                        },
+                    )
+                    .with_account(org_tx.account),
+                );
             }
             Transaction::Resolve(data) => {
                 tx = data.tx().to_owned();
-                let org_tx = self
-                    .transactions
-                    .get(&tx)
+                let org_tx = history
+                    .get(self.id, tx)?
                     .ok_or(RepositoryError::TransactionDoesNotExist(tx, self.id))?;
 
-                if !self.disputed.contains(&tx) {
+                if !self.disputed.contains_key(&tx) {
                     return Err(RepositoryError::TransactionNotDisputed(tx));
                 }
 
                 // I assume dispute can only be done on deposit
-                if let Transaction::Deposit(data) = org_tx {
-                    self.available += data.amount();
-                    self.held -= data.amount();
-                    self.disputed.remove(&tx); // not checking for result, b/c we have just checked that the set contains the id
-                } else {
+                if org_tx.kind != EntryKind::Deposit {
                     return Err(RepositoryError::WrongReferenceTransactionType);
                 }
+
+                let balance = self.balance_mut(org_tx.account, org_tx.currency);
+                balance.add_available(org_tx.amount);
+                balance.held -= org_tx.amount;
+                self.disputed.remove(&tx); // not checking for result, b/c we have just checked that the set contains the id
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::FundsReleased {
+                            amount: org_tx.amount,
+                            currency: org_tx.currency,
+                        },
+                    )
+                    .with_account(org_tx.account),
+                );
             }
             Transaction::Chargeback(data) => {
                 tx = data.tx().to_owned();
-                let org_tx = self
-                    .transactions
-                    .get(&tx)
+                let org_tx = history
+                    .get(self.id, tx)?
                     .ok_or(RepositoryError::TransactionDoesNotExist(tx, self.id))?;
 
-                if !self.disputed.contains(&tx) {
+                if !self.disputed.contains_key(&tx) {
                     return Err(RepositoryError::TransactionNotDisputed(tx));
                 }
 
                 // I assume dispute can only be done on deposit
-                if let Transaction::Deposit(data) = org_tx {
-                    self.held -= data.amount();
-                    self.locked = true;
-                    self.disputed.remove(&tx); // not checking for result, b/c we have just checked that the set contains the id
-                } else {
+                if org_tx.kind != EntryKind::Deposit {
                     return Err(RepositoryError::WrongReferenceTransactionType);
                 }
+
+                self.balance_mut(org_tx.account, org_tx.currency).held -= org_tx.amount;
+                self.disputed.remove(&tx); // not checking for result, b/c we have just checked that the set contains the id
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::ChargedBack {
+                            amount: org_tx.amount,
+                            currency: org_tx.currency,
+                        },
+                    )
+                    .with_account(org_tx.account),
+                );
+                let lock = Event::new(self.id, tx, timestamp, EventKind::AccountLocked);
+                match self.lock_scope {
+                    LockScope::Client if !self.locked => {
+                        self.locked = true;
+                        events.push(lock);
+                    }
+                    LockScope::Account if self.locked_accounts.insert(org_tx.account) => {
+                        events.push(lock.with_account(org_tx.account));
+                    }
+                    _ => {}
+                }
+            }
+            Transaction::Convert(data) => {
+                self.check_locked(*data.account())?;
+
+                tx = data.tx().to_owned();
+                let quote = data.quote().ok_or_else(|| {
+                    RepositoryError::RateNotAvailable(
+                        data.from().to_string(),
+                        data.to().to_string(),
+                    )
+                })?;
+                if history.get(self.id, tx)?.is_some() {
+                    return Err(RepositoryError::DuplicateTransactionId(tx));
+                }
+                self.check_funds(*data.account(), *data.from(), *data.amount())?;
+
+                self.balance_mut(*data.account(), *data.from())
+                    .take_available(*data.amount());
+                self.balance_mut(*data.account(), *data.to())
+                    .add_available(*quote.amount());
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::ConvertedFrom {
+                            amount: *data.amount(),
+                            currency: *data.from(),
+                        },
+                    )
+                    .with_account(*data.account()),
+                );
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::ConvertedTo {
+                            amount: *quote.amount(),
+                            currency: *data.to(),
+                        },
+                    )
+                    .with_account(*data.account()),
+                );
+            }
+            Transaction::Transfer(data) => {
+                self.check_locked(*data.from())?;
+                self.check_locked(*data.to())?;
+
+                tx = data.tx().to_owned();
+                if history.get(self.id, tx)?.is_some() {
+                    return Err(RepositoryError::DuplicateTransactionId(tx));
+                }
+                self.check_funds(*data.from(), *data.currency(), *data.amount())?;
+
+                self.balance_mut(*data.from(), *data.currency())
+                    .take_available(*data.amount());
+                self.balance_mut(*data.to(), *data.currency())
+                    .add_available(*data.amount());
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::TransferredOut {
+                            amount: *data.amount(),
+                            currency: *data.currency(),
+                        },
+                    )
+                    .with_account(*data.from()),
+                );
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::TransferredIn {
+                            amount: *data.amount(),
+                            currency: *data.currency(),
+                        },
+                    )
+                    .with_account(*data.to()),
+                );
+            }
+            Transaction::Authorize(data) => {
+                self.check_locked(*data.account())?;
+
+                tx = data.tx().to_owned();
+                if self.holds.contains_key(&tx) || history.get(self.id, tx)?.is_some() {
+                    return Err(RepositoryError::DuplicateTransactionId(tx));
+                }
+                self.check_funds(*data.account(), *data.currency(), *data.amount())?;
+
+                let balance = self.balance_mut(*data.account(), *data.currency());
+                balance.take_available(*data.amount());
+                balance.held += data.amount();
+                self.holds.insert(
+                    tx,
+                    Hold {
+                        account: *data.account(),
+                        currency: *data.currency(),
+                        amount: *data.amount(),
+                        timestamp,
+                    },
+                );
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::FundsAuthorized {
+                            amount: *data.amount(),
+                            currency: *data.currency(),
+                        },
+                    )
+                    .with_account(*data.account()),
+                );
+            }
+            Transaction::Capture(data) => {
+                tx = data.tx().to_owned();
+                let hold = self
+                    .holds
+                    .get(&tx)
+                    .copied()
+                    .ok_or(RepositoryError::AuthorizationDoesNotExist(tx, self.id))?;
+                self.check_locked(hold.account)?;
+                let captured = data.amount().unwrap_or(hold.amount);
+                if captured > hold.amount {
+                    return Err(RepositoryError::CaptureExceedsAuthorization(tx));
+                }
+                // captured funds leave the engine, so the capture is limited like a withdrawal
+                self.check_withdrawal_limits(captured, timestamp)?;
+
+                // the part of the hold which is not captured gets released
+                let released = hold.amount - captured;
+                let balance = self.balance_mut(hold.account, hold.currency);
+                balance.held -= hold.amount;
+                balance.add_available(released);
+                self.holds.remove(&tx);
+                self.transaction_count += 1;
+                self.record_activity(true, captured, hold.currency, timestamp);
+                events.push(
+                    Event::new(
+                        self.id,
+                        tx,
+                        timestamp,
+                        EventKind::FundsCaptured {
+                            amount: captured,
+                            currency: hold.currency,
+                        },
+                    )
+                    .with_account(hold.account),
+                );
+                if released > 0.0 {
+                    events.push(
+                        Event::new(
+                            self.id,
+                            tx,
+                            timestamp,
+                            EventKind::AuthorizationReleased {
+                                amount: released,
+                                currency: hold.currency,
+                            },
+                        )
+                        .with_account(hold.account),
+                    );
+                }
+            }
+            Transaction::Void(data) => {
+                tx = data.tx().to_owned();
+                let hold = self
+                    .holds
+                    .get(&tx)
+                    .ok_or(RepositoryError::AuthorizationDoesNotExist(tx, self.id))?;
+                self.check_locked(hold.account)?;
+
+                events.push(self.release_hold(tx, timestamp)?);
+            }
+            Transaction::Reversal(data) => {
+                tx = data.tx().to_owned();
+                let org_tx = history
+                    .get(self.id, tx)?
+                    .ok_or(RepositoryError::TransactionDoesNotExist(tx, self.id))?;
+                self.check_locked(org_tx.account)?;
+
+                if self.reversed.contains(&tx) {
+                    return Err(RepositoryError::TransactionReversed(tx));
+                }
+                if self.disputed.contains_key(&tx) {
+                    return Err(RepositoryError::TransactionDisputed(tx));
+                }
+
+                let kind = match org_tx.kind {
+                    EntryKind::Deposit => {
+                        // the deposited funds may have been spent in the meantime
+                        self.check_funds(org_tx.account, org_tx.currency, org_tx.amount)?;
+
+                        self.balance_mut(org_tx.account, org_tx.currency)
+                            .take_available(org_tx.amount);
+                        EventKind::DepositReversed {
+                            amount: org_tx.amount,
+                            currency: org_tx.currency,
+                        }
+                    }
+                    EntryKind::Withdrawal => {
+                        self.balance_mut(org_tx.account, org_tx.currency)
+                            .add_available(org_tx.amount);
+                        EventKind::WithdrawalReversed {
+                            amount: org_tx.amount,
+                            currency: org_tx.currency,
+                        }
+                    }
+                };
+                self.reversed.insert(tx);
+                events.push(Event::new(self.id, tx, timestamp, kind).with_account(org_tx.account));
             }
         }
 
-        Ok(())
+        if timestamp.is_some() {
+            self.last_timestamp = timestamp;
+        }
+
+        Ok(events)
     }
 }
 
 /// Repository of all clients handled by this engine.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Repository {
     // even though `Client` struct holds its id, we use HashMap here
     // instead of Vector for performance reasons
-    clients: HashMap<u16, Client>,
+    clients: HashMap<ClientId, Client>,
+
+    /// Deposits, withdrawals, conversions, transfers and authorizations of all the clients
+    history: History,
+
+    /// Prices the conversions
+    fx: Fx,
+
+    /// Callbacks notified about every event caused by registered transactions
+    subscribers: Subscribers,
+
+    /// Business rules consulted before and after every transaction
+    policies: PolicyRegistry,
+
+    /// Risk limits applied to newly created clients
+    limits: LimitsConfig,
+
+    /// Dispute windows and expiry
+    disputes: DisputesConfig,
+
+    /// Authorization expiry
+    authorizations: AuthorizationsConfig,
+
+    /// What gets locked after a chargeback
+    lock_scope: LockScope,
+
+    /// Timestamp of the most recent timestamped transaction, across all clients
+    clock: Option<Timestamp>,
+
+    /// Deadlines of open disputes and authorizations, earliest first
+    deadlines: BinaryHeap<Reverse<Due>>,
+
+    /// Failed expiries, not yet taken by `take_expiry_errors`
+    expiry_errors: Vec<RepositoryError>,
+
+    /// Changes of the running simulation, if any
+    journal: Option<Journal>,
+}
+
+/// Deadline of an open dispute or authorization, as (deadline, client, tx, what expires)
+type Due = (Timestamp, ClientId, TxId, Deadline);
+
+/// What expires at a deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Deadline {
+    Dispute,
+    Authorization,
+}
+
+/// Changes made by a simulation, kept so that they can be committed or undone
+#[derive(Debug, Default)]
+struct Journal {
+    /// Clients as they were before the simulation first changed them, `None` for the ones it created
+    clients: HashMap<ClientId, Option<Client>>,
+
+    /// Clock as it was before the simulation
+    clock: Option<Timestamp>,
+
+    /// Deadlines added by the simulation
+    pushed: Vec<Due>,
+
+    /// Deadlines expired by the simulation
+    popped: Vec<Due>,
+
+    /// Quotes of the accepted conversions, recorded in the FX P&L on commit
+    quotes: Vec<(Currency, Quote)>,
+
+    /// Events, notified to the subscribers on commit
+    events: Vec<Event>,
 }
 
 impl Repository {
     /// Returns new empty `Repository`
+    #[cfg(test)]
     pub fn new() -> Self {
+        Self::with_config(&Config::default())
+    }
+
+    /// Returns new empty `Repository` configured with `config`
+    pub fn with_config(config: &Config) -> Self {
         Self {
             clients: HashMap::new(),
+            history: History::new(
+                *config.history().memory_budget(),
+                config.history().spill_dir().clone(),
+            ),
+            fx: Fx::new(config.fx().clone()),
+            subscribers: Subscribers::default(),
+            policies: PolicyRegistry::default(),
+            limits: config.limits().clone(),
+            disputes: config.disputes().clone(),
+            authorizations: config.authorizations().clone(),
+            lock_scope: config.accounts().chargeback_lock(),
+            clock: None,
+            deadlines: BinaryHeap::new(),
+            expiry_errors: Vec::new(),
+            journal: None,
         }
     }
 
-    /// Registers the transaction and modifies internal state
-    pub fn register_transaction(
-        &mut self,
-        transaction: Transaction,
-    ) -> Result<(), RepositoryError> {
-        let client_id = match transaction {
-            Transaction::Deposit(data) => data.client().to_owned(),
-            Transaction::Withdrawal(data) => data.client().to_owned(),
-            Transaction::Dispute(data) => data.client().to_owned(),
-            Transaction::Resolve(data) => data.client().to_owned(),
-            Transaction::Chargeback(data) => data.client().to_owned(),
-        };
-
-        let client = self
-            .clients
-            .entry(client_id)
-            .or_insert_with(|| Client::new(client_id));
+    /// Registers a callback that will be notified about every emitted `Event`
+    pub fn subscribe(&mut self, subscriber: Subscriber) {
+        self.subscribers.push(subscriber);
+    }
 
-        client.register_transaction(transaction)?;
+    /// Replaces the exchange rates used to price the conversions
+    pub fn set_rates(&mut self, rates: RateTable) {
+        self.fx.set_rates(rates);
+    }
 
-        Ok(())
+    /// Returns the FX P&L of the accepted conversions, per target currency
+    pub fn fx_pnl(&self) -> &BTreeMap<Currency, Pnl> {
+        self.fx.pnl()
     }
 
-    /// Returns an iterator over clients existing in the system
-    pub fn iter_clients(&self) -> impl Iterator<Item = &Client> {
-        self.clients.values()
+    /// Appends the `policy` to the chain of policies consulted on every transaction
+    pub fn register_policy(&mut self, policy: Rc<dyn TransactionPolicy>) {
+        self.policies.register(policy);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::Repository;
-    use crate::{
-        repo::Client,
-        transaction::{Transaction, TransactionData, TransactionDataAmount},
-    };
-    use quickcheck::TestResult;
-    use quickcheck_macros::quickcheck;
-    use std::collections::{HashMap, HashSet};
+    /// Registers the transaction and modifies internal state, returning the events it caused
+    pub fn register_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Vec<Event>, RepositoryError> {
+        let _span = tracing::info_span!(
+            "register_transaction",
+            client = transaction.client(),
+            tx = transaction.tx(),
+            kind = transaction.name(),
+        )
+        .entered();
+        let start = Instant::now();
+        let result = self.apply(transaction);
+        let latency_us = start.elapsed().as_micros() as u64;
+        match &result {
+            Ok(events) => tracing::debug!(
+                outcome = "accepted",
+                events = events.len(),
+                latency_us,
+                "transaction accepted"
+            ),
+            Err(e) => tracing::info!(
+                outcome = "rejected",
+                code = e.code(),
+                error = %e,
+                latency_us,
+                "transaction rejected"
+            ),
+        }
 
-    macro_rules! valid_amount {
-        ($amount:expr) => {
-            $amount.is_normal() && $amount.is_sign_positive()
-        };
+        result
+    }
+
+    /// Returns the failures of the expiries run by the registered transactions, since the last call.
+    /// A failed expiry does not reject the transaction that ran it, and is retried with the next one.
+    pub fn take_expiry_errors(&mut self) -> Vec<RepositoryError> {
+        std::mem::take(&mut self.expiry_errors)
+    }
+
+    /// Applies the transaction to the state of its client. The clock is advanced only once the transaction
+    /// is priced and passed the policies; the business rules of the client are checked after the expiries.
+    fn apply(&mut self, transaction: Transaction) -> Result<Vec<Event>, RepositoryError> {
+        let transaction = match transaction {
+            Transaction::Convert(data) => {
+                // conversions without timestamp are priced at the current time of the engine
+                let at = data.timestamp().or(self.clock);
+                let quote = self
+                    .fx
+                    .quote(*data.from(), *data.to(), *data.amount(), at)?;
+                Transaction::Convert(data.with_quote(quote))
+            }
+            t => t,
+        };
+
+        let client_id = transaction.client();
+        self.checkpoint(client_id);
+        self.client_mut(client_id);
+        self.policies
+            .before(&self.clients[&client_id], &transaction)
+            .map_err(|(policy, reason)| {
+                RepositoryError::PolicyViolation(policy.to_owned(), reason)
+            })?;
+
+        if let Some(ts) = transaction.timestamp() {
+            if let Err(e) = self.advance_clock(ts) {
+                self.expiry_errors.push(e);
+            }
+        }
+
+        // expiries only change the existing clients
+        let client = match self.clients.get_mut(&client_id) {
+            Some(client) => client,
+            None => unreachable!("client `{}` was created above", client_id),
+        };
+        let mut events = client.register_transaction(transaction, &mut self.history)?;
+        if let Transaction::Convert(data) = transaction {
+            if let Some(quote) = data.quote() {
+                match &mut self.journal {
+                    Some(journal) => journal.quotes.push((*data.to(), *quote)),
+                    None => self.fx.record(*data.to(), quote),
+                }
+            }
+        }
+        let deadline = match transaction {
+            Transaction::Dispute(_) => self
+                .disputes
+                .deadline(transaction.timestamp())
+                .map(|d| (d, Deadline::Dispute)),
+            Transaction::Authorize(_) => self
+                .authorizations
+                .deadline(transaction.timestamp())
+                .map(|d| (d, Deadline::Authorization)),
+            _ => None,
+        };
+        let annotations = self.policies.after(client, &transaction, &events);
+        events.extend(annotations);
+        if let Some((deadline, what)) = deadline {
+            self.schedule((deadline, client_id, transaction.tx(), what));
+        }
+
+        self.publish(&events);
+
+        Ok(events)
+    }
+
+    /// Moves the clock forward to `ts`, expiring disputes and authorizations whose deadline has passed.
+    /// If an expiry fails, its deadline is kept and the clock stays where it was, so that it and the remaining
+    /// expiries are retried.
+    pub fn advance_clock(&mut self, ts: Timestamp) -> Result<(), RepositoryError> {
+        if self.clock.is_some_and(|clock| clock >= ts) {
+            return Ok(());
+        }
+
+        while let Some(&Reverse(due)) = self.deadlines.peek() {
+            let (deadline, client_id, tx, what) = due;
+            if deadline > ts {
+                break;
+            }
+            self.deadlines.pop();
+            if let Some(journal) = &mut self.journal {
+                journal.popped.push(due);
+            }
+
+            if !self.clients.contains_key(&client_id) {
+                continue;
+            }
+            self.checkpoint(client_id);
+            let client = match self.clients.get_mut(&client_id) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            // the client's own later transactions can be past the deadline already, if the dispute
+            // or authorization was made after the clock had passed it
+            let at = client
+                .last_timestamp()
+                .map_or(deadline, |last| last.max(deadline));
+            let (expired, result) = match what {
+                Deadline::Dispute => {
+                    // the dispute could have been resolved, or resolved and disputed again, in the meantime
+                    let disputed_at = client.disputed().get(&tx).copied().flatten();
+                    if self.disputes.deadline(disputed_at) != Some(deadline) {
+                        continue;
+                    }
+
+                    let data = TransactionData::new(client_id, tx).with_timestamp(Some(at));
+                    let synthetic = match self.disputes.expiry_action() {
+                        ExpiryAction::Resolve => Transaction::Resolve(data),
+                        ExpiryAction::Chargeback => Transaction::Chargeback(data),
+                    };
+                    (
+                        EventKind::DisputeExpired,
+                        client.register_transaction(synthetic, &mut self.history),
+                    )
+                }
+                Deadline::Authorization => {
+                    // the authorization could have been captured or voided in the meantime
+                    if !client.holds().contains_key(&tx) {
+                        continue;
+                    }
+
+                    (
+                        EventKind::AuthorizationExpired,
+                        client.expire_hold(tx, at).map(|e| vec![e]),
+                    )
+                }
+            };
+
+            let mut events = vec![Event::new(client_id, tx, Some(deadline), expired)];
+            match result {
+                Ok(caused) => events.extend(caused),
+                Err(e) => {
+                    self.deadlines.push(Reverse(due));
+                    if let Some(journal) = &mut self.journal {
+                        journal.popped.pop();
+                    }
+                    return Err(RepositoryError::ExpiryFailed(tx, client_id, Box::new(e)));
+                }
+            }
+
+            self.publish(&events);
+        }
+        self.clock = Some(ts);
+
+        Ok(())
+    }
+
+    /// Adds the deadline of an open dispute or authorization
+    fn schedule(&mut self, due: Due) {
+        self.deadlines.push(Reverse(due));
+        if let Some(journal) = &mut self.journal {
+            journal.pushed.push(due);
+        }
+    }
+
+    /// Notifies the subscribers about the `events`, or keeps them until the running simulation is committed
+    fn publish(&mut self, events: &[Event]) {
+        match &mut self.journal {
+            Some(journal) => journal.events.extend_from_slice(events),
+            None => {
+                for event in events {
+                    self.subscribers.notify(event);
+                }
+            }
+        }
+    }
+
+    /// Keeps the copy of the client with `id` as it was before the running simulation first changed it
+    fn checkpoint(&mut self, id: ClientId) {
+        if let Some(journal) = &mut self.journal {
+            journal
+                .clients
+                .entry(id)
+                .or_insert_with(|| self.clients.get(&id).cloned());
+        }
+    }
+
+    /// Starts a simulation - transactions registered through it can be committed or discarded
+    pub fn simulate(&mut self) -> Simulation<'_> {
+        self.journal = Some(Journal {
+            clock: self.clock,
+            ..Journal::default()
+        });
+        self.history.stage();
+
+        Simulation {
+            repo: self,
+            committed: false,
+        }
+    }
+
+    /// Keeps the changes of the running simulation, notifying the subscribers about its events
+    fn commit_simulation(&mut self) -> Result<(), RepositoryError> {
+        let journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return Ok(()),
+        };
+
+        self.history.commit_staged()?;
+        for (currency, quote) in &journal.quotes {
+            self.fx.record(*currency, quote);
+        }
+        self.publish(&journal.events);
+
+        Ok(())
+    }
+
+    /// Undoes the changes of the running simulation
+    fn discard_simulation(&mut self) {
+        let journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return,
+        };
+
+        self.history.discard_staged();
+        for (id, client) in journal.clients {
+            match client {
+                Some(client) => self.clients.insert(id, client),
+                None => self.clients.remove(&id),
+            };
+        }
+        self.clock = journal.clock;
+
+        let mut deadlines = std::mem::take(&mut self.deadlines).into_vec();
+        for due in journal.pushed {
+            if let Some(i) = deadlines.iter().position(|Reverse(d)| *d == due) {
+                deadlines.swap_remove(i);
+            }
+        }
+        deadlines.extend(journal.popped.into_iter().map(Reverse));
+        self.deadlines = deadlines.into();
+    }
+
+    /// Returns the client with `id`, creating it if it does not exist yet
+    fn client_mut(&mut self, id: ClientId) -> &mut Client {
+        let limits = &self.limits;
+        let dispute_window = self.disputes.window();
+        let lock_scope = self.lock_scope;
+        self.clients.entry(id).or_insert_with(|| {
+            Client::with_limits(id, limits.for_client(id))
+                .with_dispute_window(dispute_window)
+                .with_lock_scope(lock_scope)
+        })
+    }
+
+    /// Loads the opening balance of the client's `account` in `currency`, before any transaction is registered
+    pub fn open_balance(
+        &mut self,
+        client: ClientId,
+        account: Account,
+        currency: Currency,
+        available: f64,
+        held: f64,
+        locked: bool,
+    ) -> Result<(), RepositoryError> {
+        let events = self
+            .client_mut(client)
+            .open_balance(account, currency, available, held, locked)?;
+        for event in &events {
+            self.subscribers.notify(event);
+        }
+
+        Ok(())
+    }
+
+    /// Loads the dispute of the client's deposit `tx`, open before this run.
+    /// The disputed funds have to be held in the opening balance already.
+    /// It can be resolved, charged back or expire as any other dispute.
+    pub fn open_dispute(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        deposit: Entry,
+        disputed_at: Option<Timestamp>,
+    ) -> Result<(), RepositoryError> {
+        if self.history.get(client, tx)?.is_some() {
+            return Err(RepositoryError::DuplicateTransactionId(tx));
+        }
+
+        self.history.insert(client, tx, deposit)?;
+        self.client_mut(client).disputed.insert(tx, disputed_at);
+        if let Some(deadline) = self.disputes.deadline(disputed_at) {
+            self.schedule((deadline, client, tx, Deadline::Dispute));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the client with `id`, if it exists in the system
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    /// Returns the open disputes of the client with `id`, as (tx, disputed deposit, disputed at), ordered by tx
+    pub fn open_disputes(
+        &self,
+        id: ClientId,
+    ) -> Result<Vec<(TxId, Entry, Option<Timestamp>)>, RepositoryError> {
+        let client = match self.clients.get(&id) {
+            Some(c) => c,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut disputes = Vec::new();
+        for (tx, disputed_at) in &client.disputed {
+            let deposit = self
+                .history
+                .get(id, *tx)?
+                .ok_or(RepositoryError::TransactionDoesNotExist(*tx, id))?;
+            disputes.push((*tx, deposit, *disputed_at));
+        }
+        disputes.sort_by_key(|(tx, _, _)| *tx);
+
+        Ok(disputes)
+    }
+
+    /// Returns an iterator over clients existing in the system
+    pub fn iter_clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+}
+
+/// What-if view of the `Repository`. Every client is copied once the simulation first changes it,
+/// and the events are kept from the subscribers, so the changes can be undone.
+/// Dropping the simulation without committing it discards the changes.
+#[derive(Debug)]
+pub struct Simulation<'a> {
+    repo: &'a mut Repository,
+    committed: bool,
+}
+
+impl Simulation<'_> {
+    /// Registers the transaction in the simulation, returning the events it caused
+    pub fn register_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Vec<Event>, RepositoryError> {
+        self.repo.register_transaction(transaction)
+    }
+
+    /// Returns the failures of the expiries run by the simulated transactions, since the last call
+    pub fn take_expiry_errors(&mut self) -> Vec<RepositoryError> {
+        self.repo.take_expiry_errors()
+    }
+
+    /// Returns the clients changed by the simulation, as they were before it and as they are now
+    pub fn changes(&self) -> impl Iterator<Item = (Option<&Client>, &Client)> {
+        let journal = self.repo.journal.as_ref();
+        journal
+            .into_iter()
+            .flat_map(|j| j.clients.iter())
+            .filter_map(|(id, before)| Some((before.as_ref(), self.repo.clients.get(id)?)))
+    }
+
+    /// Keeps the changes, notifying the subscribers about the events of the simulation
+    pub fn commit(mut self) -> Result<(), RepositoryError> {
+        self.committed = true;
+        self.repo.commit_simulation()
+    }
+}
+
+impl Drop for Simulation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.repo.discard_simulation();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Balance, Repository};
+    use crate::{
+        account::Account,
+        config::{Config, LockScope},
+        currency::Currency,
+        errors::RepositoryError,
+        events::{Event, EventKind},
+        fx::{Quote, RateTable},
+        history::{Entry, EntryKind, History},
+        limits::{Limit, Limits},
+        repo::Client,
+        transaction::{
+            CaptureData, ConversionData, Transaction, TransactionData, TransactionDataAmount,
+            TransferData,
+        },
+    };
+    use chrono::{Duration, TimeZone, Utc};
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+    use std::{cell::RefCell, env, fs, process, rc::Rc};
+
+    fn history() -> History {
+        History::new(usize::MAX, env::temp_dir())
+    }
+
+    macro_rules! valid_amount {
+        ($amount:expr) => {
+            $amount.is_normal() && $amount.is_sign_positive()
+        };
     }
 
     #[test]
@@ -248,17 +1512,12 @@ mod tests {
     fn locked_client_rejects_deposit_and_withdrawal() {
         macro_rules! test {
             ($tr:path) => {
-                let mut c = Client {
-                    id: 1,
-                    available: 0.0,
-                    held: 0.0,
-                    locked: true,
-                    transactions: HashMap::new(),
-                    disputed: HashSet::new(),
-                };
+                let mut c = Client::new(1);
+                let mut history = history();
+                c.locked = true;
                 let tr = $tr(TransactionDataAmount::new(1, 1, 1.0).unwrap());
 
-                let result = c.register_transaction(tr);
+                let result = c.register_transaction(tr, &mut history);
 
                 assert!(match result {
                     Ok(_) => false,
@@ -275,22 +1534,25 @@ mod tests {
 
     #[test]
     fn locked_client_accepts_dispute() {
-        let mut log = HashMap::new();
-        log.insert(
-            1u32,
-            Transaction::Deposit(TransactionDataAmount::new(1, 1, 1.0).unwrap()),
-        );
-        let mut c = Client {
-            id: 1,
-            available: 0.0,
-            held: 0.0,
-            locked: true,
-            transactions: log,
-            disputed: HashSet::new(),
-        };
+        let mut history = history();
+        history
+            .insert(
+                1,
+                1,
+                Entry {
+                    kind: EntryKind::Deposit,
+                    amount: 1.0,
+                    currency: Currency::NONE,
+                    account: Account::MAIN,
+                    timestamp: None,
+                },
+            )
+            .unwrap();
+        let mut c = Client::new(1);
+        c.locked = true;
         let tr = Transaction::Dispute(TransactionData::new(1, 1));
 
-        let result = c.register_transaction(tr);
+        let result = c.register_transaction(tr, &mut history);
 
         assert!(result.is_ok());
     }
@@ -302,13 +1564,19 @@ mod tests {
         }
 
         let mut client = Client::new(1);
+
+        let mut history = history();
         let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, x).unwrap());
         let wit = Transaction::Withdrawal(TransactionDataAmount::new(1, 2, x).unwrap());
 
-        client.register_transaction(dep).expect("Deposit failed");
-        client.register_transaction(wit).expect("Withdrawal failed");
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(wit, &mut history)
+            .expect("Withdrawal failed");
 
-        TestResult::from_bool(client.available == 0.0 && client.held == 0.0)
+        TestResult::from_bool(client.balance(Currency::NONE) == Balance::default())
     }
 
     #[quickcheck]
@@ -318,13 +1586,20 @@ mod tests {
         }
 
         let mut client = Client::new(1);
+
+        let mut history = history();
         let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, x).unwrap());
         let dis = Transaction::Dispute(TransactionData::new(1, 1));
 
-        client.register_transaction(dep).expect("Deposit failed");
-        client.register_transaction(dis).expect("Dispute failed");
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(dis, &mut history)
+            .expect("Dispute failed");
 
-        TestResult::from_bool(client.available == 0.0 && client.held == x)
+        let balance = client.balance(Currency::NONE);
+        TestResult::from_bool(balance.available == 0.0 && balance.held == x)
     }
 
     #[quickcheck]
@@ -334,15 +1609,24 @@ mod tests {
         }
 
         let mut client = Client::new(1);
+
+        let mut history = history();
         let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, x).unwrap());
         let dis = Transaction::Dispute(TransactionData::new(1, 1));
         let res = Transaction::Resolve(TransactionData::new(1, 1));
 
-        client.register_transaction(dep).expect("Deposit failed");
-        client.register_transaction(dis).expect("Dispute failed");
-        client.register_transaction(res).expect("Resolve failed");
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(dis, &mut history)
+            .expect("Dispute failed");
+        client
+            .register_transaction(res, &mut history)
+            .expect("Resolve failed");
 
-        TestResult::from_bool(client.available == x && client.held == 0.0)
+        let balance = client.balance(Currency::NONE);
+        TestResult::from_bool(balance.available == x && balance.held == 0.0)
     }
 
     #[quickcheck]
@@ -352,14 +1636,1149 @@ mod tests {
         }
 
         let mut client = Client::new(1);
+
+        let mut history = history();
         let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, x).unwrap());
         let dis = Transaction::Dispute(TransactionData::new(1, 1));
         let cha = Transaction::Chargeback(TransactionData::new(1, 1));
 
-        client.register_transaction(dep).expect("Deposit failed");
-        client.register_transaction(dis).expect("Dispute failed");
-        client.register_transaction(cha).expect("Chargeback failed");
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(dis, &mut history)
+            .expect("Dispute failed");
+        client
+            .register_transaction(cha, &mut history)
+            .expect("Chargeback failed");
+
+        TestResult::from_bool(client.balance(Currency::NONE) == Balance::default() && client.locked)
+    }
+
+    #[test]
+    fn chargeback_emits_events_in_order() {
+        let mut client = Client::new(1);
+        let mut history = history();
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 2.0).unwrap());
+        let dis = Transaction::Dispute(TransactionData::new(1, 1));
+        let cha = Transaction::Chargeback(TransactionData::new(1, 1));
+
+        let mut events = client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        events.extend(
+            client
+                .register_transaction(dis, &mut history)
+                .expect("Dispute failed"),
+        );
+        events.extend(
+            client
+                .register_transaction(cha, &mut history)
+                .expect("Chargeback failed"),
+        );
+
+        assert_eq!(
+            events,
+            vec![
+                Event::new(
+                    1,
+                    1,
+                    None,
+                    EventKind::FundsDeposited {
+                        amount: 2.0,
+                        currency: Currency::NONE
+                    }
+                ),
+                Event::new(
+                    1,
+                    1,
+                    None,
+                    EventKind::FundsHeld {
+                        amount: 2.0,
+                        currency: Currency::NONE
+                    }
+                ),
+                Event::new(
+                    1,
+                    1,
+                    None,
+                    EventKind::ChargedBack {
+                        amount: 2.0,
+                        currency: Currency::NONE
+                    }
+                ),
+                Event::new(1, 1, None, EventKind::AccountLocked),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_transaction_does_not_notify_subscribers() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let mut repo = Repository::new();
+        repo.subscribe(Rc::new(move |e: &Event| sink.borrow_mut().push(e.clone())));
+
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 1.0).unwrap());
+        let wit = Transaction::Withdrawal(TransactionDataAmount::new(1, 2, 2.0).unwrap());
+        repo.register_transaction(dep).expect("deposit failed");
+        repo.register_transaction(wit)
+            .expect_err("withdrawal did not fail");
+
+        assert_eq!(
+            *received.borrow(),
+            vec![Event::new(
+                1,
+                1,
+                None,
+                EventKind::FundsDeposited {
+                    amount: 1.0,
+                    currency: Currency::NONE
+                }
+            )]
+        );
+    }
 
-        TestResult::from_bool(client.available == 0.0 && client.held == 0.0 && client.locked)
+    #[test]
+    fn reversal_restores_the_balance_and_blocks_disputes() {
+        let mut repo = Repository::new();
+        for tr in [
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 5.0).unwrap()),
+            Transaction::Withdrawal(TransactionDataAmount::new(1, 2, 2.0).unwrap()),
+            Transaction::Reversal(TransactionData::new(1, 2)),
+            Transaction::Reversal(TransactionData::new(1, 1)),
+        ] {
+            repo.register_transaction(tr).expect("Transaction failed");
+        }
+
+        assert_eq!(
+            repo.clients[&1].balance(Currency::NONE),
+            Balance {
+                available: 0.0,
+                held: 0.0,
+                credit: 0.0
+            }
+        );
+        assert_eq!(
+            repo.register_transaction(Transaction::Dispute(TransactionData::new(1, 1))),
+            Err(RepositoryError::TransactionReversed(1))
+        );
+        assert_eq!(
+            repo.register_transaction(Transaction::Reversal(TransactionData::new(1, 1))),
+            Err(RepositoryError::TransactionReversed(1))
+        );
+    }
+
+    #[test]
+    fn reversal_of_disputed_or_spent_deposit_results_in_error() {
+        let mut repo = Repository::new();
+        let dep = |tx| Transaction::Deposit(TransactionDataAmount::new(1, tx, 5.0).unwrap());
+        repo.register_transaction(dep(1)).expect("Deposit failed");
+        repo.register_transaction(dep(2)).expect("Deposit failed");
+        repo.register_transaction(Transaction::Dispute(TransactionData::new(1, 1)))
+            .expect("Dispute failed");
+        repo.register_transaction(Transaction::Withdrawal(
+            TransactionDataAmount::new(1, 3, 4.0).unwrap(),
+        ))
+        .expect("Withdrawal failed");
+
+        assert_eq!(
+            repo.register_transaction(Transaction::Reversal(TransactionData::new(1, 1))),
+            Err(RepositoryError::TransactionDisputed(1))
+        );
+        assert_eq!(
+            repo.register_transaction(Transaction::Reversal(TransactionData::new(1, 2))),
+            Err(RepositoryError::InsufficientFunds(1))
+        );
+    }
+
+    #[test]
+    fn withdrawal_above_max_withdrawal_results_in_error() {
+        let limits: Limits = toml::from_str("max_withdrawal = 5.0").unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap());
+        let wit = Transaction::Withdrawal(TransactionDataAmount::new(1, 2, 6.0).unwrap());
+
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        let result = client.register_transaction(wit, &mut history);
+
+        assert_eq!(
+            result,
+            Err(RepositoryError::LimitExceeded(1, Limit::MaxWithdrawal))
+        );
+        assert_eq!(client.balance(Currency::NONE).available, 10.0);
+    }
+
+    #[test]
+    fn withdrawal_can_use_the_overdraft() {
+        let limits: Limits = toml::from_str("credit_limit = 5.0").unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap());
+        let wit = |tx, amount| {
+            Transaction::Withdrawal(TransactionDataAmount::new(1, tx, amount).unwrap())
+        };
+
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(wit(2, 14.0), &mut history)
+            .expect("Withdrawal within the overdraft failed");
+
+        assert_eq!(
+            client.register_transaction(wit(3, 2.0), &mut history),
+            Err(RepositoryError::InsufficientFunds(1))
+        );
+        assert_eq!(client.balance(Currency::NONE).available, -4.0);
+        assert_eq!(client.balance(Currency::NONE).credit_used(), 4.0);
+    }
+
+    #[test]
+    fn overdraft_is_shared_by_accounts_and_used_by_every_debit() {
+        let limits: Limits = toml::from_str("credit_limit = 5.0").unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+        let savings: Account = "savings".parse().unwrap();
+        let transfer =
+            Transaction::Transfer(TransferData::new(1, 1, 3.0, Account::MAIN, savings).unwrap());
+        let authorize = |tx, amount| {
+            Transaction::Authorize(
+                TransactionDataAmount::new(1, tx, amount)
+                    .unwrap()
+                    .with_account(savings),
+            )
+        };
+
+        client
+            .register_transaction(transfer, &mut history)
+            .expect("Transfer within the overdraft failed");
+        assert_eq!(
+            client.register_transaction(authorize(2, 6.0), &mut history),
+            Err(RepositoryError::InsufficientFunds(1))
+        );
+        client
+            .register_transaction(authorize(3, 5.0), &mut history)
+            .expect("Authorization within the overdraft failed");
+
+        assert_eq!(client.balance(Currency::NONE).credit_used(), 3.0);
+        assert_eq!(
+            client
+                .account_balance(savings, Currency::NONE)
+                .credit_used(),
+            2.0
+        );
+        assert_eq!(client.credit_used(Currency::NONE), 5.0);
+    }
+
+    #[test]
+    fn dispute_of_spent_deposit_does_not_use_the_overdraft() {
+        let limits: Limits = toml::from_str("credit_limit = 5.0").unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+
+        for tr in [
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap()),
+            Transaction::Withdrawal(TransactionDataAmount::new(1, 2, 12.0).unwrap()),
+            Transaction::Dispute(TransactionData::new(1, 1)),
+        ] {
+            client
+                .register_transaction(tr, &mut history)
+                .expect("Transaction failed");
+        }
+        assert_eq!(client.balance(Currency::NONE).available, -12.0);
+        assert_eq!(client.credit_used(Currency::NONE), 2.0);
+
+        client
+            .register_transaction(
+                Transaction::Deposit(TransactionDataAmount::new(1, 3, 11.0).unwrap()),
+                &mut history,
+            )
+            .expect("Deposit failed");
+        assert_eq!(client.credit_used(Currency::NONE), 1.0);
+    }
+
+    #[test]
+    fn withdrawals_above_velocity_result_in_error() {
+        let limits: Limits =
+            toml::from_str("withdrawal_velocity = { max_withdrawals = 2, per_transactions = 3 }")
+                .unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+        let dep = |tx| Transaction::Deposit(TransactionDataAmount::new(1, tx, 10.0).unwrap());
+        let wit = |tx| Transaction::Withdrawal(TransactionDataAmount::new(1, tx, 1.0).unwrap());
+
+        client
+            .register_transaction(dep(1), &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(wit(2), &mut history)
+            .expect("Withdrawal failed");
+        client
+            .register_transaction(wit(3), &mut history)
+            .expect("Withdrawal failed");
+        assert_eq!(
+            client.register_transaction(wit(4), &mut history),
+            Err(RepositoryError::LimitExceeded(1, Limit::WithdrawalVelocity))
+        );
+        client
+            .register_transaction(dep(5), &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(wit(6), &mut history)
+            .expect("Withdrawal failed");
+    }
+
+    #[test]
+    fn transaction_older_than_last_one_results_in_error() {
+        let ts = |secs| Utc.timestamp_opt(secs, 0).single();
+        let mut client = Client::new(1);
+        let mut history = history();
+        let dep = |tx, secs| {
+            Transaction::Deposit(
+                TransactionDataAmount::new(1, tx, 1.0)
+                    .unwrap()
+                    .with_timestamp(ts(secs)),
+            )
+        };
+
+        client
+            .register_transaction(dep(1, 100), &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(dep(2, 100), &mut history)
+            .expect("Deposit failed");
+        assert_eq!(
+            client.register_transaction(dep(3, 99), &mut history),
+            Err(RepositoryError::NonMonotonicTimestamp(3, 1))
+        );
+        client
+            .register_transaction(
+                Transaction::Dispute(TransactionData::new(1, 1)),
+                &mut history,
+            )
+            .expect("Dispute without timestamp failed");
+        assert_eq!(*client.last_timestamp(), ts(100));
+    }
+
+    #[test]
+    fn deposits_above_daily_volume_result_in_error() {
+        let limits: Limits = toml::from_str("max_daily_deposit = 10.0").unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+        let dep = |tx, time: &str| {
+            Transaction::Deposit(
+                TransactionDataAmount::new(1, tx, 6.0)
+                    .unwrap()
+                    .with_timestamp(Some(time.parse().unwrap())),
+            )
+        };
+
+        client
+            .register_transaction(dep(1, "2022-09-01T10:00:00Z"), &mut history)
+            .expect("Deposit failed");
+        assert_eq!(
+            client.register_transaction(dep(2, "2022-09-01T23:59:59Z"), &mut history),
+            Err(RepositoryError::LimitExceeded(1, Limit::MaxDailyDeposit))
+        );
+        client
+            .register_transaction(dep(3, "2022-09-02T00:00:00Z"), &mut history)
+            .expect("Deposit failed");
+
+        // the same day, but another currency
+        let eur = Transaction::Deposit(
+            TransactionDataAmount::new(1, 4, 6.0)
+                .unwrap()
+                .with_currency("EUR".parse().unwrap())
+                .with_timestamp(Some("2022-09-02T01:00:00Z".parse().unwrap())),
+        );
+        client
+            .register_transaction(eur, &mut history)
+            .expect("Deposit in another currency failed");
+    }
+
+    #[test]
+    fn withdrawals_above_rate_result_in_error() {
+        let limits: Limits =
+            toml::from_str("withdrawal_rate = { max_withdrawals = 1, window_seconds = 60 }")
+                .unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+        client
+            .register_transaction(
+                Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap()),
+                &mut history,
+            )
+            .expect("Deposit failed");
+        let wit = |tx, secs| {
+            Transaction::Withdrawal(
+                TransactionDataAmount::new(1, tx, 1.0)
+                    .unwrap()
+                    .with_timestamp(Utc.timestamp_opt(secs, 0).single()),
+            )
+        };
+
+        client
+            .register_transaction(wit(2, 0), &mut history)
+            .expect("Withdrawal failed");
+        assert_eq!(
+            client.register_transaction(wit(3, 59), &mut history),
+            Err(RepositoryError::LimitExceeded(1, Limit::WithdrawalRate))
+        );
+        client
+            .register_transaction(wit(4, 60), &mut history)
+            .expect("Withdrawal failed");
+    }
+
+    fn timestamped(tr: Transaction, time: &str) -> Transaction {
+        let ts = Some(time.parse().unwrap());
+        match tr {
+            Transaction::Deposit(d) => Transaction::Deposit(d.with_timestamp(ts)),
+            Transaction::Withdrawal(d) => Transaction::Withdrawal(d.with_timestamp(ts)),
+            Transaction::Dispute(d) => Transaction::Dispute(d.with_timestamp(ts)),
+            Transaction::Resolve(d) => Transaction::Resolve(d.with_timestamp(ts)),
+            Transaction::Chargeback(d) => Transaction::Chargeback(d.with_timestamp(ts)),
+            Transaction::Convert(d) => Transaction::Convert(d.with_timestamp(ts)),
+            Transaction::Transfer(d) => Transaction::Transfer(d.with_timestamp(ts)),
+            Transaction::Authorize(d) => Transaction::Authorize(d.with_timestamp(ts)),
+            Transaction::Capture(d) => Transaction::Capture(d.with_timestamp(ts)),
+            Transaction::Void(d) => Transaction::Void(d.with_timestamp(ts)),
+            Transaction::Reversal(d) => Transaction::Reversal(d.with_timestamp(ts)),
+        }
+    }
+
+    #[test]
+    fn dispute_after_window_results_in_error() {
+        let mut client = Client::new(1).with_dispute_window(Some(Duration::days(1)));
+        let mut history = history();
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 1.0).unwrap());
+        let dis = Transaction::Dispute(TransactionData::new(1, 1));
+
+        client
+            .register_transaction(timestamped(dep, "2022-09-01T00:00:00Z"), &mut history)
+            .expect("Deposit failed");
+
+        assert_eq!(
+            client.register_transaction(timestamped(dis, "2022-09-02T00:00:01Z"), &mut history),
+            Err(RepositoryError::DisputeWindowClosed(1))
+        );
+        client
+            .register_transaction(timestamped(dis, "2022-09-02T00:00:00Z"), &mut history)
+            .expect("Dispute failed");
+    }
+
+    #[test]
+    fn expired_dispute_is_resolved_by_default() {
+        let config: Config = toml::from_str("disputes = { max_duration_seconds = 60 }").unwrap();
+        let mut repo = Repository::with_config(&config);
+        let dep =
+            |client, tx| Transaction::Deposit(TransactionDataAmount::new(client, tx, 1.0).unwrap());
+
+        repo.register_transaction(timestamped(dep(1, 1), "2022-09-01T00:00:00Z"))
+            .expect("Deposit failed");
+        repo.register_transaction(timestamped(
+            Transaction::Dispute(TransactionData::new(1, 1)),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Dispute failed");
+        repo.register_transaction(timestamped(dep(2, 2), "2022-09-01T00:00:59Z"))
+            .expect("Deposit failed");
+        assert_eq!(repo.clients[&1].balance(Currency::NONE).held, 1.0);
+
+        repo.register_transaction(timestamped(dep(2, 3), "2022-09-01T00:01:00Z"))
+            .expect("Deposit failed");
+
+        let client = &repo.clients[&1];
+        assert_eq!(client.balance(Currency::NONE).held, 0.0);
+        assert_eq!(client.balance(Currency::NONE).available, 1.0);
+        assert!(client.disputed().is_empty());
+    }
+
+    #[test]
+    fn rejected_transaction_does_not_expire_disputes() {
+        let config: Config = toml::from_str("disputes = { max_duration_seconds = 60 }").unwrap();
+        let mut repo = Repository::with_config(&config);
+        let (eur, usd) = ("EUR".parse().unwrap(), "USD".parse().unwrap());
+
+        repo.register_transaction(timestamped(
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 1.0).unwrap()),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Deposit failed");
+        repo.register_transaction(timestamped(
+            Transaction::Dispute(TransactionData::new(1, 1)),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Dispute failed");
+        // there are no rates to price the conversion with
+        let conv = ConversionData::new(2, 2, 1.0, eur, usd).unwrap();
+        repo.register_transaction(timestamped(
+            Transaction::Convert(conv),
+            "2022-09-01T00:05:00Z",
+        ))
+        .expect_err("Conversion without rates did not fail");
+
+        assert_eq!(repo.clients[&1].balance(Currency::NONE).held, 1.0);
+        assert!(!repo.clients[&1].disputed().is_empty());
+    }
+
+    #[test]
+    fn failed_expiry_is_reported_apart_and_retried() {
+        let dir = env::temp_dir().join(format!("toy-payments-engine-expiry-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config: Config = toml::from_str(&format!(
+            "disputes = {{ max_duration_seconds = 60 }}\nhistory = {{ memory_budget = 1, spill_dir = {:?} }}",
+            dir
+        ))
+        .unwrap();
+        let mut repo = Repository::with_config(&config);
+        let dep =
+            |client, tx| Transaction::Deposit(TransactionDataAmount::new(client, tx, 1.0).unwrap());
+
+        // every entry is spilled once the next one is inserted
+        repo.register_transaction(timestamped(dep(1, 1), "2022-09-01T00:00:00Z"))
+            .expect("Deposit failed");
+        repo.register_transaction(timestamped(
+            Transaction::Dispute(TransactionData::new(1, 1)),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Dispute failed");
+        repo.register_transaction(timestamped(dep(3, 9), "2022-09-01T00:00:00Z"))
+            .expect("Deposit failed");
+        let run = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.extension().is_some_and(|e| e == "run"))
+            .unwrap();
+        let content = fs::read(&run).unwrap();
+        fs::write(&run, []).unwrap();
+
+        repo.register_transaction(timestamped(dep(2, 2), "2022-09-01T00:05:00Z"))
+            .expect("Deposit failed");
+        let errors = repo.take_expiry_errors();
+        assert!(matches!(
+            errors[..],
+            [RepositoryError::ExpiryFailed(1, 1, _)]
+        ));
+        assert_eq!(repo.clients[&1].balance(Currency::NONE).held, 1.0);
+
+        fs::write(&run, content).unwrap();
+        repo.register_transaction(timestamped(dep(2, 3), "2022-09-01T00:06:00Z"))
+            .expect("Deposit failed");
+        assert!(repo.take_expiry_errors().is_empty());
+        assert!(repo.clients[&1].disputed().is_empty());
+
+        drop(repo);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dispute_expires_after_later_transaction_of_its_client() {
+        let config: Config = toml::from_str("disputes = { max_duration_seconds = 60 }").unwrap();
+        let mut repo = Repository::with_config(&config);
+        let dep =
+            |client, tx| Transaction::Deposit(TransactionDataAmount::new(client, tx, 1.0).unwrap());
+
+        repo.register_transaction(timestamped(dep(2, 1), "2022-09-01T00:10:00Z"))
+            .expect("Deposit failed");
+        // the deadline of the dispute has passed already, but the clock does not move
+        repo.register_transaction(timestamped(dep(1, 1), "2022-09-01T00:00:00Z"))
+            .expect("Deposit failed");
+        repo.register_transaction(timestamped(
+            Transaction::Dispute(TransactionData::new(1, 1)),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Dispute failed");
+        repo.register_transaction(timestamped(dep(1, 2), "2022-09-01T00:05:00Z"))
+            .expect("Deposit failed");
+        assert_eq!(repo.clients[&1].balance(Currency::NONE).held, 1.0);
+
+        repo.register_transaction(timestamped(dep(2, 3), "2022-09-01T00:11:00Z"))
+            .expect("Deposit failed");
+
+        let client = &repo.clients[&1];
+        assert_eq!(client.balance(Currency::NONE).held, 0.0);
+        assert_eq!(client.balance(Currency::NONE).available, 2.0);
+        assert!(client.disputed().is_empty());
+    }
+
+    #[test]
+    fn expired_dispute_can_be_charged_back() {
+        let config: Config = toml::from_str(
+            "disputes = { max_duration_seconds = 60, expiry_action = \"chargeback\" }",
+        )
+        .unwrap();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let mut repo = Repository::with_config(&config);
+        repo.subscribe(Rc::new(move |e: &Event| sink.borrow_mut().push(e.clone())));
+
+        repo.register_transaction(timestamped(
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 1.0).unwrap()),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Deposit failed");
+        repo.register_transaction(timestamped(
+            Transaction::Dispute(TransactionData::new(1, 1)),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Dispute failed");
+        repo.register_transaction(timestamped(
+            Transaction::Withdrawal(TransactionDataAmount::new(1, 2, 1.0).unwrap()),
+            "2022-09-02T00:00:00Z",
+        ))
+        .expect_err("Withdrawal on locked client did not fail");
+
+        let deadline = Some("2022-09-01T00:01:00Z".parse().unwrap());
+        assert_eq!(
+            received.borrow()[2..],
+            [
+                Event::new(1, 1, deadline, EventKind::DisputeExpired),
+                Event::new(
+                    1,
+                    1,
+                    deadline,
+                    EventKind::ChargedBack {
+                        amount: 1.0,
+                        currency: Currency::NONE
+                    }
+                ),
+                Event::new(1, 1, deadline, EventKind::AccountLocked),
+            ]
+        );
+    }
+
+    #[test]
+    fn conversion_moves_funds_between_currencies() {
+        let mut client = Client::new(1);
+        let mut history = history();
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let dep = Transaction::Deposit(
+            TransactionDataAmount::new(1, 1, 10.0)
+                .unwrap()
+                .with_currency(eur),
+        );
+        let conv = ConversionData::new(1, 2, 4.0, eur, usd).unwrap();
+
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        assert_eq!(
+            client.register_transaction(Transaction::Convert(conv), &mut history),
+            Err(RepositoryError::RateNotAvailable(
+                "EUR".to_owned(),
+                "USD".to_owned()
+            ))
+        );
+        let events = client
+            .register_transaction(
+                Transaction::Convert(conv.with_quote(Quote::new(1.5, 5.9, 0.1))),
+                &mut history,
+            )
+            .expect("Conversion failed");
+
+        assert_eq!(*client.balance(eur).available(), 6.0);
+        assert_eq!(*client.balance(usd).available(), 5.9);
+        assert_eq!(
+            events[1],
+            Event::new(
+                1,
+                2,
+                None,
+                EventKind::ConvertedTo {
+                    amount: 5.9,
+                    currency: usd
+                }
+            )
+        );
+        // conversions are not kept in the history, as nothing can refer to them
+        assert_eq!(
+            client.register_transaction(
+                Transaction::Dispute(TransactionData::new(1, 2)),
+                &mut history
+            ),
+            Err(RepositoryError::TransactionDoesNotExist(2, 1))
+        );
+    }
+
+    #[test]
+    fn repository_prices_conversions_and_records_pnl() {
+        let mut repo = Repository::new();
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let mut rates = RateTable::default();
+        rates.insert(eur, usd, 1.25, None);
+        repo.set_rates(rates);
+
+        repo.register_transaction(Transaction::Deposit(
+            TransactionDataAmount::new(1, 1, 10.0)
+                .unwrap()
+                .with_currency(usd),
+        ))
+        .expect("Deposit failed");
+        repo.register_transaction(Transaction::Convert(
+            ConversionData::new(1, 2, 5.0, usd, eur).unwrap(),
+        ))
+        .expect("Conversion failed");
+        repo.register_transaction(Transaction::Convert(
+            ConversionData::new(1, 3, 10.0, usd, eur).unwrap(),
+        ))
+        .expect_err("Conversion on insufficient funds did not fail");
+
+        let client = repo.iter_clients().next().unwrap();
+        assert_eq!(*client.balance(usd).available(), 5.0);
+        assert_eq!(*client.balance(eur).available(), 4.0);
+        assert_eq!(*repo.fx_pnl()[&eur].conversions(), 1);
+    }
+
+    #[test]
+    fn transfer_moves_funds_between_accounts() {
+        let mut client = Client::new(1);
+        let mut history = history();
+        let savings: Account = "savings".parse().unwrap();
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap());
+        let transfer = |tx, amount| {
+            Transaction::Transfer(TransferData::new(1, tx, amount, Account::MAIN, savings).unwrap())
+        };
+
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(transfer(2, 4.0), &mut history)
+            .expect("Transfer failed");
+
+        assert_eq!(
+            client.register_transaction(transfer(3, 7.0), &mut history),
+            Err(RepositoryError::InsufficientFunds(1))
+        );
+        assert_eq!(*client.balance(Currency::NONE).available(), 6.0);
+        assert_eq!(
+            *client.account_balance(savings, Currency::NONE).available(),
+            4.0
+        );
+    }
+
+    #[test]
+    fn chargeback_locks_only_the_account_if_configured() {
+        let mut client = Client::new(1).with_lock_scope(LockScope::Account);
+        let mut history = history();
+        let savings: Account = "savings".parse().unwrap();
+        let dep = |tx, account| {
+            Transaction::Deposit(
+                TransactionDataAmount::new(1, tx, 1.0)
+                    .unwrap()
+                    .with_account(account),
+            )
+        };
+
+        client
+            .register_transaction(dep(1, savings), &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(
+                Transaction::Dispute(TransactionData::new(1, 1)),
+                &mut history,
+            )
+            .expect("Dispute failed");
+        let events = client
+            .register_transaction(
+                Transaction::Chargeback(TransactionData::new(1, 1)),
+                &mut history,
+            )
+            .expect("Chargeback failed");
+
+        assert_eq!(
+            events[1],
+            Event::new(1, 1, None, EventKind::AccountLocked).with_account(savings)
+        );
+        assert!(!client.locked());
+        assert!(client.is_locked(savings));
+        assert_eq!(
+            client.register_transaction(dep(2, savings), &mut history),
+            Err(RepositoryError::AccountLocked(1, "savings".to_owned()))
+        );
+        client
+            .register_transaction(dep(3, Account::MAIN), &mut history)
+            .expect("Deposit to other account failed");
+    }
+
+    #[test]
+    fn partial_capture_releases_the_rest_of_the_hold() {
+        let mut client = Client::new(1);
+        let mut history = history();
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap());
+        let auth = Transaction::Authorize(TransactionDataAmount::new(1, 2, 4.0).unwrap());
+        let capture = |amount| Transaction::Capture(CaptureData::new(1, 2, amount).unwrap());
+
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(auth, &mut history)
+            .expect("Authorization failed");
+        assert_eq!(
+            client.balance(Currency::NONE),
+            Balance {
+                available: 6.0,
+                held: 4.0,
+                credit: 0.0
+            }
+        );
+        assert_eq!(
+            client.register_transaction(capture(Some(5.0)), &mut history),
+            Err(RepositoryError::CaptureExceedsAuthorization(2))
+        );
+
+        let events = client
+            .register_transaction(capture(Some(3.0)), &mut history)
+            .expect("Capture failed");
+
+        assert_eq!(
+            client.balance(Currency::NONE),
+            Balance {
+                available: 7.0,
+                held: 0.0,
+                credit: 0.0
+            }
+        );
+        assert_eq!(
+            events,
+            vec![
+                Event::new(
+                    1,
+                    2,
+                    None,
+                    EventKind::FundsCaptured {
+                        amount: 3.0,
+                        currency: Currency::NONE
+                    }
+                ),
+                Event::new(
+                    1,
+                    2,
+                    None,
+                    EventKind::AuthorizationReleased {
+                        amount: 1.0,
+                        currency: Currency::NONE
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            client.register_transaction(capture(None), &mut history),
+            Err(RepositoryError::AuthorizationDoesNotExist(2, 1))
+        );
+    }
+
+    #[test]
+    fn authorizations_and_disputes_do_not_interfere() {
+        let mut client = Client::new(1);
+        let mut history = history();
+        let dep = Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap());
+        let auth = Transaction::Authorize(TransactionDataAmount::new(1, 2, 4.0).unwrap());
+
+        client
+            .register_transaction(dep, &mut history)
+            .expect("Deposit failed");
+        client
+            .register_transaction(auth, &mut history)
+            .expect("Authorization failed");
+
+        assert_eq!(
+            client.register_transaction(
+                Transaction::Dispute(TransactionData::new(1, 2)),
+                &mut history
+            ),
+            Err(RepositoryError::TransactionDoesNotExist(2, 1))
+        );
+        assert_eq!(
+            client.register_transaction(auth, &mut history),
+            Err(RepositoryError::DuplicateTransactionId(2))
+        );
+        assert_eq!(
+            client
+                .register_transaction(Transaction::Void(TransactionData::new(1, 1)), &mut history),
+            Err(RepositoryError::AuthorizationDoesNotExist(1, 1))
+        );
+        client
+            .register_transaction(
+                Transaction::Dispute(TransactionData::new(1, 1)),
+                &mut history,
+            )
+            .expect("Dispute failed");
+        client
+            .register_transaction(Transaction::Void(TransactionData::new(1, 2)), &mut history)
+            .expect("Void failed");
+
+        assert_eq!(
+            client.balance(Currency::NONE),
+            Balance {
+                available: 0.0,
+                held: 10.0,
+                credit: 0.0
+            }
+        );
+        assert!(client.holds().is_empty());
+        assert!(client.disputed().contains_key(&1));
+    }
+
+    #[test]
+    fn authorization_expires() {
+        let config: Config = toml::from_str("authorizations = { expiry_seconds = 60 }").unwrap();
+        let mut repo = Repository::with_config(&config);
+
+        repo.register_transaction(timestamped(
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap()),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Deposit failed");
+        repo.register_transaction(timestamped(
+            Transaction::Authorize(TransactionDataAmount::new(1, 2, 4.0).unwrap()),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Authorization failed");
+        repo.register_transaction(timestamped(
+            Transaction::Deposit(TransactionDataAmount::new(2, 3, 1.0).unwrap()),
+            "2022-09-01T00:01:00Z",
+        ))
+        .expect("Deposit failed");
+
+        let client = repo.clients.get(&1).unwrap();
+        assert!(client.holds().is_empty());
+        assert_eq!(*client.balance(Currency::NONE).available(), 10.0);
+    }
+
+    #[test]
+    fn hold_of_locked_client_can_only_expire() {
+        let config: Config = toml::from_str("authorizations = { expiry_seconds = 60 }").unwrap();
+        let mut repo = Repository::with_config(&config);
+        let at = "2022-09-01T00:00:00Z";
+
+        for tr in [
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap()),
+            Transaction::Authorize(TransactionDataAmount::new(1, 2, 4.0).unwrap()),
+            Transaction::Deposit(TransactionDataAmount::new(1, 3, 5.0).unwrap()),
+            Transaction::Dispute(TransactionData::new(1, 3)),
+            Transaction::Chargeback(TransactionData::new(1, 3)),
+        ] {
+            repo.register_transaction(timestamped(tr, at))
+                .expect("Transaction failed");
+        }
+
+        assert_eq!(
+            repo.register_transaction(timestamped(
+                Transaction::Capture(CaptureData::new(1, 2, None).unwrap()),
+                at
+            )),
+            Err(RepositoryError::ClientLocked(1))
+        );
+        assert_eq!(
+            repo.register_transaction(timestamped(
+                Transaction::Void(TransactionData::new(1, 2)),
+                at
+            )),
+            Err(RepositoryError::ClientLocked(1))
+        );
+
+        repo.register_transaction(timestamped(
+            Transaction::Deposit(TransactionDataAmount::new(2, 4, 1.0).unwrap()),
+            "2022-09-01T00:01:00Z",
+        ))
+        .expect("Deposit failed");
+
+        let client = repo.clients.get(&1).unwrap();
+        assert!(client.holds().is_empty());
+        assert_eq!(*client.balance(Currency::NONE).available(), 10.0);
+    }
+
+    #[test]
+    fn capture_above_max_withdrawal_results_in_error() {
+        let limits: Limits = toml::from_str("max_withdrawal = 5.0").unwrap();
+        let mut client = Client::with_limits(1, limits);
+        let mut history = history();
+        let capture = |amount| Transaction::Capture(CaptureData::new(1, 2, amount).unwrap());
+
+        for tr in [
+            Transaction::Deposit(TransactionDataAmount::new(1, 1, 10.0).unwrap()),
+            Transaction::Authorize(TransactionDataAmount::new(1, 2, 8.0).unwrap()),
+        ] {
+            client
+                .register_transaction(tr, &mut history)
+                .expect("Transaction failed");
+        }
+
+        assert_eq!(
+            client.register_transaction(capture(None), &mut history),
+            Err(RepositoryError::LimitExceeded(1, Limit::MaxWithdrawal))
+        );
+        client
+            .register_transaction(capture(Some(5.0)), &mut history)
+            .expect("Capture within the limit failed");
+        assert_eq!(*client.transaction_count(), 2);
+    }
+
+    #[test]
+    fn opening_balance_and_dispute_continue_previous_run() {
+        let mut repo = Repository::new();
+        let deposit = Entry {
+            kind: EntryKind::Deposit,
+            amount: 3.0,
+            currency: Currency::NONE,
+            account: Account::MAIN,
+            timestamp: None,
+        };
+
+        repo.open_balance(1, Account::MAIN, Currency::NONE, 5.0, 3.0, false)
+            .expect("Opening balance failed");
+        repo.open_dispute(1, 7, deposit, None)
+            .expect("Opening dispute failed");
+        repo.register_transaction(Transaction::Chargeback(TransactionData::new(1, 7)))
+            .expect("Chargeback failed");
+
+        let client = repo.clients.get(&1).unwrap();
+        assert_eq!(
+            client.balance(Currency::NONE),
+            Balance {
+                available: 5.0,
+                held: 0.0,
+                credit: 0.0
+            }
+        );
+        assert!(client.is_locked(Account::MAIN));
+    }
+
+    #[test]
+    fn negative_opening_balance_has_to_be_within_the_overdraft() {
+        let mut client = Client::new(1);
+        let limits: Limits = toml::from_str("credit_limit = 5.0").unwrap();
+        let mut overdraft_client = Client::with_limits(2, limits);
+
+        assert_eq!(
+            client.open_balance(Account::MAIN, Currency::NONE, -1.0, 0.0, false),
+            Err(RepositoryError::OpeningBalanceOverdrawn(
+                1,
+                "main".to_owned(),
+                "".to_owned()
+            ))
+        );
+        overdraft_client
+            .open_balance(Account::MAIN, Currency::NONE, -3.0, 0.0, false)
+            .expect("Opening balance failed");
+        assert_eq!(overdraft_client.credit_used(Currency::NONE), 3.0);
+        assert!(overdraft_client
+            .open_balance("savings".parse().unwrap(), Currency::NONE, -3.0, 0.0, false)
+            .is_err());
+    }
+
+    #[test]
+    fn opening_balance_can_be_locked_and_loaded_once() {
+        let mut repo = Repository::new();
+
+        repo.open_balance(1, Account::MAIN, Currency::NONE, 5.0, 0.0, true)
+            .expect("Opening balance failed");
+        let result = repo.open_balance(1, Account::MAIN, Currency::NONE, 1.0, 0.0, false);
+
+        assert_eq!(
+            result,
+            Err(RepositoryError::DuplicateOpeningBalance(
+                1,
+                "main".to_owned(),
+                "".to_owned()
+            ))
+        );
+        assert_eq!(
+            repo.register_transaction(Transaction::Deposit(
+                TransactionDataAmount::new(1, 1, 1.0).unwrap()
+            )),
+            Err(RepositoryError::ClientLocked(1))
+        );
+    }
+
+    #[test]
+    fn discarded_simulation_leaves_no_trace() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let config: Config = toml::from_str("disputes = { max_duration_seconds = 60 }").unwrap();
+        let mut repo = Repository::with_config(&config);
+        repo.subscribe(Rc::new(move |e: &Event| sink.borrow_mut().push(e.clone())));
+        let dep =
+            |client, tx| Transaction::Deposit(TransactionDataAmount::new(client, tx, 1.0).unwrap());
+        repo.register_transaction(timestamped(dep(1, 1), "2022-09-01T00:00:00Z"))
+            .expect("Deposit failed");
+        repo.register_transaction(timestamped(
+            Transaction::Dispute(TransactionData::new(1, 1)),
+            "2022-09-01T00:00:00Z",
+        ))
+        .expect("Dispute failed");
+
+        {
+            let mut simulation = repo.simulate();
+            // expires the dispute of client 1
+            simulation
+                .register_transaction(timestamped(dep(2, 2), "2022-09-01T00:02:00Z"))
+                .expect("Deposit failed");
+            simulation
+                .register_transaction(Transaction::Withdrawal(
+                    TransactionDataAmount::new(2, 3, 5.0).unwrap(),
+                ))
+                .expect_err("Withdrawal did not fail");
+            assert_eq!(simulation.changes().count(), 2);
+        }
+
+        assert_eq!(received.borrow().len(), 2);
+        assert!(!repo.clients.contains_key(&2));
+        assert_eq!(repo.clients[&1].balance(Currency::NONE).held, 1.0);
+        assert_eq!(repo.history.get(2, 2).unwrap(), None);
+
+        // the dispute still expires once the time passes for real
+        repo.register_transaction(timestamped(dep(3, 4), "2022-09-01T00:01:00Z"))
+            .expect("Deposit failed");
+        assert_eq!(repo.clients[&1].balance(Currency::NONE).held, 0.0);
+    }
+
+    #[test]
+    fn committed_simulation_notifies_subscribers() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let mut repo = Repository::new();
+        repo.subscribe(Rc::new(move |e: &Event| sink.borrow_mut().push(e.clone())));
+
+        let mut simulation = repo.simulate();
+        simulation
+            .register_transaction(Transaction::Deposit(
+                TransactionDataAmount::new(1, 1, 2.0).unwrap(),
+            ))
+            .expect("Deposit failed");
+        let changes: Vec<_> = simulation
+            .changes()
+            .map(|(before, after)| (before.is_some(), after.balance(Currency::NONE)))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![(
+                false,
+                Balance {
+                    available: 2.0,
+                    held: 0.0,
+                    credit: 0.0
+                }
+            )]
+        );
+        assert!(received.borrow().is_empty());
+        simulation.commit().expect("Commit failed");
+
+        assert_eq!(received.borrow().len(), 1);
+        assert_eq!(repo.history.get(1, 1).unwrap().map(|e| e.amount), Some(2.0));
+        assert_eq!(
+            repo.register_transaction(Transaction::Deposit(
+                TransactionDataAmount::new(1, 1, 2.0).unwrap()
+            )),
+            Err(RepositoryError::DuplicateTransactionId(1))
+        );
     }
 }
@@ -0,0 +1,142 @@
+//! Snapshots of the client balances, as in the output, and of the open disputes, as in the open disputes file
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    dto::{BalanceRecord, OpenDisputeRecord, OutputColumns, OutputRecord},
+    repo::Client,
+    transaction::{ClientId, Timestamp, TxId},
+};
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use csv::Trim;
+use getset::Getters;
+use std::{collections::BTreeMap, fs::File};
+
+/// Client, account and currency of a single balance
+pub type BalanceKey = (ClientId, Account, Currency);
+
+/// Balance of a single client account in a single currency, as in the output
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Snapshot {
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// Client and id of the disputed deposit of a single open dispute
+pub type DisputeKey = (ClientId, TxId);
+
+/// Open dispute of a single deposit, as in the open disputes file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisputeSnapshot {
+    pub amount: f64,
+    pub account: Account,
+    pub currency: Currency,
+    pub disputed_at: Option<Timestamp>,
+}
+
+/// Differences of the amounts of two snapshots that are not reported (e.g. as reconciliation breaks)
+#[derive(Debug, Clone, Copy, PartialEq, Getters)]
+pub struct Tolerance {
+    #[get = "pub"]
+    absolute: f64,
+
+    /// Fraction of the expected amount
+    #[get = "pub"]
+    relative: f64,
+}
+
+impl Default for Tolerance {
+    /// Tolerates the differences hidden by the four decimal places of the output
+    fn default() -> Self {
+        Self {
+            absolute: 0.00005,
+            relative: 0.0,
+        }
+    }
+}
+
+impl Tolerance {
+    pub fn new(absolute: f64, relative: f64) -> Self {
+        Self { absolute, relative }
+    }
+
+    /// Returns whether the `actual` amount is close enough to the `expected` one
+    pub fn allows(&self, expected: f64, actual: f64) -> bool {
+        (expected - actual).abs() <= self.absolute.max(self.relative * expected.abs())
+    }
+}
+
+/// Loads the balances from CSV file at `path`, in the shape of the output
+pub fn load(path: &str) -> Result<BTreeMap<BalanceKey, Snapshot>> {
+    let file =
+        File::open(path).wrap_err_with(|| format!("Can not open balances file `{}`", path))?;
+    let mut rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(file);
+
+    let mut balances = BTreeMap::new();
+    for result in rdr.deserialize() {
+        let record: BalanceRecord =
+            result.wrap_err_with(|| format!("Invalid balances file `{}`", path))?;
+        let (client, account, currency, snapshot) = record
+            .parse()
+            .wrap_err_with(|| format!("Invalid balances file `{}`", path))?;
+        if balances
+            .insert((client, account, currency), snapshot)
+            .is_some()
+        {
+            bail!(
+                "Balances file `{}` has more than one balance of account `{}` of client `{}` in currency `{}`",
+                path,
+                account,
+                client,
+                currency
+            );
+        }
+    }
+
+    Ok(balances)
+}
+
+/// Loads the open disputes from CSV file at `path`, in the shape of the open disputes file
+pub fn load_disputes(path: &str) -> Result<BTreeMap<DisputeKey, DisputeSnapshot>> {
+    let file =
+        File::open(path).wrap_err_with(|| format!("Can not open open disputes file `{}`", path))?;
+    let mut rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(file);
+
+    let mut disputes = BTreeMap::new();
+    for result in rdr.deserialize() {
+        let record: OpenDisputeRecord =
+            result.wrap_err_with(|| format!("Invalid open disputes file `{}`", path))?;
+        let (client, tx, deposit, disputed_at) = record
+            .parse()
+            .wrap_err_with(|| format!("Invalid open disputes file `{}`", path))?;
+        let snapshot = DisputeSnapshot {
+            amount: deposit.amount,
+            account: deposit.account,
+            currency: deposit.currency,
+            disputed_at,
+        };
+        if disputes.insert((client, tx), snapshot).is_some() {
+            bail!(
+                "Open disputes file `{}` has more than one dispute of transaction `{}` of client `{}`",
+                path,
+                tx,
+                client
+            );
+        }
+    }
+
+    Ok(disputes)
+}
+
+/// Returns the balances of the `clients`, with the same rows as the output
+pub fn of<'a>(clients: impl Iterator<Item = &'a Client>) -> BTreeMap<BalanceKey, Snapshot> {
+    clients
+        .flat_map(|c| OutputRecord::rows(c, OutputColumns::default()))
+        .map(|r| r.snapshot())
+        .collect()
+}
@@ -0,0 +1,396 @@
+//! Per-client account statements
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    dto::{InputRecord, StatementRecord},
+    errors::RepositoryError,
+    events::{Event, EventKind, Subscriber},
+    repo::Repository,
+    table,
+    transaction::{ClientId, Timestamp, Transaction, TxId},
+};
+use color_eyre::Result;
+use getset::Getters;
+use std::{cell::RefCell, collections::BTreeMap, io::Write, rc::Rc};
+
+/// Selects the transactions shown in the statement. All the bounds are inclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Getters)]
+pub struct StatementFilter {
+    #[get = "pub"]
+    from: Option<Timestamp>,
+
+    #[get = "pub"]
+    to: Option<Timestamp>,
+
+    #[get = "pub"]
+    from_tx: Option<TxId>,
+
+    #[get = "pub"]
+    to_tx: Option<TxId>,
+}
+
+impl StatementFilter {
+    pub fn new(
+        from: Option<Timestamp>,
+        to: Option<Timestamp>,
+        from_tx: Option<TxId>,
+        to_tx: Option<TxId>,
+    ) -> Self {
+        Self {
+            from,
+            to,
+            from_tx,
+            to_tx,
+        }
+    }
+
+    /// Returns whether the `transaction` falls within the time and transaction id range
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        self.matches_at(transaction.tx(), transaction.timestamp())
+    }
+
+    /// Returns whether the transaction `tx` made at `timestamp` falls within the time and transaction id range
+    fn matches_at(&self, tx: TxId, timestamp: Option<Timestamp>) -> bool {
+        if self.from_tx.is_some_and(|f| tx < f) || self.to_tx.is_some_and(|t| tx > t) {
+            return false;
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+
+        // transactions without timestamp can not be placed within the time range
+        timestamp.is_some_and(|ts| {
+            self.from.map_or(true, |f| ts >= f) && self.to.map_or(true, |t| ts <= t)
+        })
+    }
+}
+
+/// Statement of a single client, built while the input is processed
+#[derive(Debug, Clone)]
+pub struct Statement {
+    client: ClientId,
+    filter: StatementFilter,
+    records: Vec<StatementRecord>,
+
+    /// Account and currency of the latest record, shown again for transactions that moved no funds
+    last: (Account, Currency),
+
+    /// Events of the client published since the latest transaction, to find the expiries among them
+    published: Rc<RefCell<Vec<Event>>>,
+}
+
+impl Statement {
+    pub fn new(client: ClientId, filter: StatementFilter) -> Self {
+        Self {
+            client,
+            filter,
+            records: Vec::new(),
+            last: (Account::MAIN, Currency::NONE),
+            published: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Returns the subscriber collecting the events of the client, so that the expiries can be recorded
+    pub fn subscriber(&self) -> Subscriber {
+        let client = self.client;
+        let published = self.published.clone();
+        Rc::new(move |e: &Event| {
+            if *e.client() == client {
+                published.borrow_mut().push(e.clone());
+            }
+        })
+    }
+
+    /// Records the disputes and authorizations of the client expired since the latest transaction, with the balances after each of them.
+    /// Has to be called once the clock of `repo` has been advanced, before the next transaction is registered.
+    pub fn record_expiries(&mut self, repo: &Repository) {
+        // every expiry is published followed by the events of the funds it moved
+        let mut expiries: Vec<(Event, Vec<Event>)> = Vec::new();
+        for e in self.published.borrow_mut().drain(..) {
+            match e.kind() {
+                EventKind::DisputeExpired | EventKind::AuthorizationExpired => {
+                    expiries.push((e, Vec::new()))
+                }
+                _ => {
+                    if let Some((_, moved)) = expiries.last_mut() {
+                        moved.push(e);
+                    }
+                }
+            }
+        }
+
+        // `repo` has the balances after the last expiry, the earlier ones are found by undoing the later ones
+        let client = repo.client(self.client);
+        let mut undone: BTreeMap<(Account, Currency), (f64, f64)> = BTreeMap::new();
+        let mut records = Vec::new();
+        for (expiry, moved) in expiries.iter().rev() {
+            let mut balances = Vec::new();
+            for balance in moved
+                .iter()
+                .filter_map(|e| e.kind().currency().map(|c| (*e.account(), c)))
+            {
+                if !balances.contains(&balance) {
+                    balances.push(balance);
+                }
+            }
+
+            if self.filter.matches_at(*expiry.tx(), *expiry.timestamp()) {
+                for &(account, currency) in balances.iter().rev() {
+                    let balance = client
+                        .map(|c| c.account_balance(account, currency))
+                        .unwrap_or_default();
+                    let (available, held) = undone
+                        .get(&(account, currency))
+                        .copied()
+                        .unwrap_or_default();
+                    let record = StatementRecord::expiry(
+                        expiry,
+                        account,
+                        currency,
+                        balance.available() - available,
+                        balance.held() - held,
+                    );
+                    records.push(((account, currency), record));
+                }
+            }
+
+            for e in moved {
+                let (available, held) = match e.kind() {
+                    EventKind::FundsReleased { amount, .. }
+                    | EventKind::AuthorizationReleased { amount, .. } => (*amount, -amount),
+                    EventKind::ChargedBack { amount, .. } => (0.0, -amount),
+                    _ => continue,
+                };
+                if let Some(currency) = e.kind().currency() {
+                    let undone = undone.entry((*e.account(), currency)).or_default();
+                    undone.0 += available;
+                    undone.1 += held;
+                }
+            }
+        }
+
+        for (balance, record) in records.into_iter().rev() {
+            self.last = balance;
+            self.records.push(record);
+        }
+    }
+
+    /// Records the `result` of the `transaction` read from `record`, once registered in `repo`.
+    /// One record is added per balance the transaction moved.
+    pub fn record(
+        &mut self,
+        record: &InputRecord,
+        transaction: &Transaction,
+        result: &Result<Vec<Event>, RepositoryError>,
+        repo: &Repository,
+    ) {
+        // the events of the transaction itself are in the `result`
+        self.published.borrow_mut().clear();
+        if transaction.client() != self.client || !self.filter.matches(transaction) {
+            return;
+        }
+
+        let mut balances = Vec::new();
+        match result {
+            Ok(events) => {
+                let moved = events
+                    .iter()
+                    .filter(|e| *e.client() == self.client)
+                    .filter_map(|e| e.kind().currency().map(|c| (*e.account(), c)));
+                for balance in moved {
+                    if !balances.contains(&balance) {
+                        balances.push(balance);
+                    }
+                }
+            }
+            Err(_) => balances.extend(funds_of(transaction)),
+        }
+        if balances.is_empty() {
+            balances.push(self.last);
+        }
+
+        let client = repo.client(self.client);
+        for (account, currency) in balances {
+            let balance = client
+                .map(|c| c.account_balance(account, currency))
+                .unwrap_or_default();
+            self.records.push(StatementRecord::new(
+                record,
+                transaction.timestamp(),
+                result.as_ref().err(),
+                account,
+                currency,
+                &balance,
+            ));
+            self.last = (account, currency);
+        }
+    }
+
+    /// Writes the statement as CSV
+    pub fn write_csv<W: Write>(&self, w: W) -> Result<()> {
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(w);
+        // the header is written explicitly, so that an empty statement has one too
+        wtr.write_record(StatementRecord::COLUMNS)?;
+        for record in &self.records {
+            wtr.serialize(record)?;
+        }
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the statement as a table with aligned columns
+    pub fn write_table<W: Write>(&self, w: W) -> Result<()> {
+        let rows: Vec<_> = self.records.iter().map(|r| r.cells().to_vec()).collect();
+        table::write(w, &StatementRecord::COLUMNS, &rows)
+    }
+}
+
+/// Returns the account and currency of the funds the `transaction` would move, if given by the transaction itself
+fn funds_of(transaction: &Transaction) -> Option<(Account, Currency)> {
+    match transaction {
+        Transaction::Deposit(data)
+        | Transaction::Withdrawal(data)
+        | Transaction::Authorize(data) => Some((*data.account(), *data.currency())),
+        Transaction::Convert(data) => Some((*data.account(), *data.from())),
+        Transaction::Transfer(data) => Some((*data.from(), *data.currency())),
+        Transaction::Dispute(_)
+        | Transaction::Resolve(_)
+        | Transaction::Chargeback(_)
+        | Transaction::Capture(_)
+        | Transaction::Void(_)
+        | Transaction::Reversal(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Statement, StatementFilter};
+    use crate::{
+        config::Config,
+        dto::InputRecord,
+        repo::Repository,
+        transaction::{Timestamp, Transaction},
+    };
+    use chrono::{TimeZone, Utc};
+    use std::convert::TryInto;
+
+    fn statement(filter: StatementFilter, records: Vec<InputRecord>) -> String {
+        let mut repo = Repository::new();
+        let mut statement = Statement::new(1, filter);
+        for record in records {
+            let transaction: Transaction = (&record).try_into().unwrap();
+            let result = repo.register_transaction(transaction);
+            statement.record(&record, &transaction, &result, &repo);
+        }
+
+        let mut out = Vec::new();
+        statement.write_csv(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn shows_running_balances_and_rejections() {
+        let out = statement(
+            StatementFilter::default(),
+            vec![
+                InputRecord::new("deposit", 1, 1, Some(5.0)),
+                InputRecord::new("deposit", 2, 2, Some(7.0)),
+                InputRecord::new("withdrawal", 1, 3, Some(9.0)),
+                InputRecord::new("dispute", 1, 1, None),
+            ],
+        );
+
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            vec![
+                "type,tx,timestamp,amount,status,account,currency,available,held,total,error_code,error_name,error_category,error",
+                "deposit,1,,5.0000,applied,main,,5.0000,0.0000,5.0000,,,,",
+                "withdrawal,3,,9.0000,rejected,main,,5.0000,0.0000,5.0000,2001,insufficient_funds,business_rule,Withdrawal operation on client `1` would result in a negative amount",
+                "dispute,1,,,applied,main,,0.0000,5.0000,5.0000,,,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_expiries_with_balances_after_each() {
+        let config: Config = toml::from_str(
+            "disputes = { max_duration_seconds = 60 }\nauthorizations = { expiry_seconds = 60 }",
+        )
+        .unwrap();
+        let mut repo = Repository::with_config(&config);
+        let mut statement = Statement::new(1, StatementFilter::default());
+        repo.subscribe(statement.subscriber());
+
+        for record in [
+            InputRecord::new("deposit", 1, 1, Some(10.0)).with_timestamp("0"),
+            InputRecord::new("authorize", 1, 2, Some(2.0)).with_timestamp("0"),
+            InputRecord::new("dispute", 1, 1, None).with_timestamp("0"),
+            InputRecord::new("deposit", 1, 3, Some(1.0)).with_timestamp("120"),
+        ] {
+            let transaction: Transaction = (&record).try_into().unwrap();
+            if let Some(ts) = transaction.timestamp() {
+                repo.advance_clock(ts).unwrap();
+                statement.record_expiries(&repo);
+            }
+            let result = repo.register_transaction(transaction);
+            statement.record(&record, &transaction, &result, &repo);
+        }
+
+        let mut out = Vec::new();
+        statement.write_csv(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().skip(3).collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "dispute,1,1970-01-01T00:00:00+00:00,,applied,main,,-2.0000,12.0000,10.0000,,,,",
+                "dispute_expired,1,1970-01-01T00:01:00+00:00,,applied,main,,8.0000,2.0000,10.0000,,,,",
+                "authorization_expired,2,1970-01-01T00:01:00+00:00,,applied,main,,10.0000,0.0000,10.0000,,,,",
+                "deposit,3,1970-01-01T00:02:00+00:00,1.0000,applied,main,,11.0000,0.0000,11.0000,,,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_time_and_tx_range() {
+        let t = |s| -> Timestamp { Utc.timestamp_opt(s, 0).unwrap() };
+        let records = vec![
+            InputRecord::new("deposit", 1, 1, Some(1.0)).with_timestamp("100"),
+            InputRecord::new("deposit", 1, 2, Some(1.0)).with_timestamp("200"),
+            InputRecord::new("deposit", 1, 3, Some(1.0)).with_timestamp("300"),
+        ];
+
+        let by_time = statement(
+            StatementFilter::new(Some(t(150)), Some(t(300)), None, None),
+            records.clone(),
+        );
+        let by_tx = statement(StatementFilter::new(None, None, None, Some(1)), records);
+
+        assert_eq!(by_time.lines().count(), 3);
+        assert!(by_time.contains("deposit,2,"));
+        assert_eq!(by_tx.lines().count(), 2);
+        assert!(by_tx.contains("deposit,1,"));
+    }
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let mut repo = Repository::new();
+        let mut statement = Statement::new(1, StatementFilter::default());
+        let record = InputRecord::new("deposit", 1, 1, Some(5.0));
+        let transaction: Transaction = (&record).try_into().unwrap();
+        let result = repo.register_transaction(transaction);
+        statement.record(&record, &transaction, &result, &repo);
+
+        let mut out = Vec::new();
+        statement.write_table(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("type     tx  timestamp  amount  status "));
+        assert!(lines[2].starts_with("deposit  1              5.0000  applied"));
+    }
+}
@@ -0,0 +1,71 @@
+//! Output formats of the reports, and tables aligned for reading
+
+use color_eyre::{eyre::bail, Result};
+use std::{io::Write, str::FromStr};
+
+/// Output format of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// Columns aligned for reading
+    Table,
+}
+
+impl FromStr for Format {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "table" => Ok(Format::Table),
+            _ => bail!("`{}` is not a known format. Expected `csv` or `table`", s),
+        }
+    }
+}
+
+/// Writes the `rows` as a table with the `columns` header, each column as wide as its widest cell
+pub fn write<W: Write>(mut w: W, columns: &[&str], rows: &[Vec<String>]) -> Result<()> {
+    let mut widths: Vec<_> = columns.iter().map(|c| c.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut write_row = |cells: &mut dyn Iterator<Item = &str>| -> std::io::Result<()> {
+        let line: Vec<_> = cells
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(w, "{}", line.join("  ").trim_end())
+    };
+    write_row(&mut columns.iter().copied())?;
+    let rules: Vec<_> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    write_row(&mut rules.iter().map(String::as_str))?;
+    for row in rows {
+        write_row(&mut row.iter().map(String::as_str))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write;
+
+    #[test]
+    fn columns_are_aligned() {
+        let mut out = Vec::new();
+        let rows = vec![
+            vec!["1".to_owned(), "long value".to_owned()],
+            vec!["22".to_owned(), "".to_owned()],
+        ];
+
+        write(&mut out, &["a", "b"], &rows).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a   b\n--  ----------\n1   long value\n22\n"
+        );
+    }
+}
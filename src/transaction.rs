@@ -1,46 +1,295 @@
 //! Transaction definitions
 
-use crate::{dto::InputRecord, errors::*};
+use crate::{account::Account, currency::Currency, dto::InputRecord, errors::*, fx::Quote};
+use chrono::{DateTime, TimeZone, Utc};
 use getset::Getters;
 use std::convert::TryFrom;
 
+/// Point in time at which the transaction occured
+pub type Timestamp = DateTime<Utc>;
+
+/// Unique id of the client
+pub type ClientId = u64;
+
+/// Unique id of the transaction
+pub type TxId = u64;
+
 #[derive(Debug, Clone, Copy, Getters)]
 pub struct TransactionDataAmount {
     #[get = "pub"]
-    client: u16,
+    client: ClientId,
 
     #[get = "pub"]
-    tx: u32,
+    tx: TxId,
 
     #[get = "pub"]
     amount: f64,
+
+    #[get = "pub"]
+    currency: Currency,
+
+    #[get = "pub"]
+    account: Account,
+
+    #[get = "pub"]
+    timestamp: Option<Timestamp>,
 }
 
 impl TransactionDataAmount {
-    pub fn new(client: u16, tx: u32, amount: f64) -> Result<Self, DeserializationError> {
+    pub fn new(client: ClientId, tx: TxId, amount: f64) -> Result<Self, DeserializationError> {
         if !amount.is_normal() || amount.is_sign_negative() {
             return Err(DeserializationError::InvalidAmount(amount));
         }
 
-        Ok(Self { client, tx, amount })
+        Ok(Self {
+            client,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            account: Account::MAIN,
+            timestamp: None,
+        })
+    }
+
+    /// Sets the currency of the amount
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    /// Sets the account of the client the transaction concerns
+    pub fn with_account(mut self, account: Account) -> Self {
+        self.account = account;
+        self
+    }
+
+    /// Sets the timestamp of the transaction
+    pub fn with_timestamp(mut self, timestamp: Option<Timestamp>) -> Self {
+        self.timestamp = timestamp;
+        self
     }
 }
 
 #[derive(Debug, Clone, Copy, Getters)]
 pub struct TransactionData {
     #[get = "pub"]
-    client: u16,
+    client: ClientId,
+
+    #[get = "pub"]
+    tx: TxId,
 
     #[get = "pub"]
-    tx: u32,
+    timestamp: Option<Timestamp>,
 }
 
 impl TransactionData {
-    pub fn new(client: u16, tx: u32) -> Self {
-        Self { client, tx }
+    pub fn new(client: ClientId, tx: TxId) -> Self {
+        Self {
+            client,
+            tx,
+            timestamp: None,
+        }
+    }
+
+    /// Sets the timestamp of the transaction
+    pub fn with_timestamp(mut self, timestamp: Option<Timestamp>) -> Self {
+        self.timestamp = timestamp;
+        self
     }
 }
 
+#[derive(Debug, Clone, Copy, Getters)]
+pub struct ConversionData {
+    #[get = "pub"]
+    client: ClientId,
+
+    #[get = "pub"]
+    tx: TxId,
+
+    /// Amount taken from the `from` balance
+    #[get = "pub"]
+    amount: f64,
+
+    #[get = "pub"]
+    from: Currency,
+
+    #[get = "pub"]
+    to: Currency,
+
+    /// Account in which the funds are converted
+    #[get = "pub"]
+    account: Account,
+
+    /// Price of the conversion, set by the `Repository`
+    #[get = "pub"]
+    quote: Option<Quote>,
+
+    #[get = "pub"]
+    timestamp: Option<Timestamp>,
+}
+
+impl ConversionData {
+    pub fn new(
+        client: ClientId,
+        tx: TxId,
+        amount: f64,
+        from: Currency,
+        to: Currency,
+    ) -> Result<Self, DeserializationError> {
+        let data = TransactionDataAmount::new(client, tx, amount)?;
+        if from == to {
+            return Err(DeserializationError::SameCurrency(from.to_string()));
+        }
+
+        Ok(Self {
+            client,
+            tx,
+            amount: data.amount,
+            from,
+            to,
+            account: Account::MAIN,
+            quote: None,
+            timestamp: None,
+        })
+    }
+
+    /// Sets the account in which the funds are converted
+    pub fn with_account(mut self, account: Account) -> Self {
+        self.account = account;
+        self
+    }
+
+    /// Sets the price of the conversion
+    pub fn with_quote(mut self, quote: Quote) -> Self {
+        self.quote = Some(quote);
+        self
+    }
+
+    /// Sets the timestamp of the transaction
+    pub fn with_timestamp(mut self, timestamp: Option<Timestamp>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Getters)]
+pub struct TransferData {
+    #[get = "pub"]
+    client: ClientId,
+
+    #[get = "pub"]
+    tx: TxId,
+
+    #[get = "pub"]
+    amount: f64,
+
+    #[get = "pub"]
+    currency: Currency,
+
+    /// Account the funds are taken from
+    #[get = "pub"]
+    from: Account,
+
+    /// Account the funds are moved to
+    #[get = "pub"]
+    to: Account,
+
+    #[get = "pub"]
+    timestamp: Option<Timestamp>,
+}
+
+impl TransferData {
+    pub fn new(
+        client: ClientId,
+        tx: TxId,
+        amount: f64,
+        from: Account,
+        to: Account,
+    ) -> Result<Self, DeserializationError> {
+        let data = TransactionDataAmount::new(client, tx, amount)?;
+        if from == to {
+            return Err(DeserializationError::SameAccount(from.to_string()));
+        }
+
+        Ok(Self {
+            client,
+            tx,
+            amount: data.amount,
+            currency: Currency::NONE,
+            from,
+            to,
+            timestamp: None,
+        })
+    }
+
+    /// Sets the currency of the amount
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    /// Sets the timestamp of the transaction
+    pub fn with_timestamp(mut self, timestamp: Option<Timestamp>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Getters)]
+pub struct CaptureData {
+    #[get = "pub"]
+    client: ClientId,
+
+    /// Id of the authorization
+    #[get = "pub"]
+    tx: TxId,
+
+    /// Amount to capture; the whole held amount if not set
+    #[get = "pub"]
+    amount: Option<f64>,
+
+    #[get = "pub"]
+    timestamp: Option<Timestamp>,
+}
+
+impl CaptureData {
+    pub fn new(
+        client: ClientId,
+        tx: TxId,
+        amount: Option<f64>,
+    ) -> Result<Self, DeserializationError> {
+        if let Some(amount) = amount {
+            TransactionDataAmount::new(client, tx, amount)?;
+        }
+
+        Ok(Self {
+            client,
+            tx,
+            amount,
+            timestamp: None,
+        })
+    }
+
+    /// Sets the timestamp of the transaction
+    pub fn with_timestamp(mut self, timestamp: Option<Timestamp>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+}
+
+/// Parses RFC 3339 date and time, or seconds since the Unix epoch
+pub fn parse_timestamp(value: &str) -> Result<Timestamp, DeserializationError> {
+    let invalid = || DeserializationError::InvalidTimestamp(value.to_owned());
+
+    if let Ok(secs) = value.parse::<i64>() {
+        return Utc.timestamp_opt(secs, 0).single().ok_or_else(invalid);
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| invalid())
+}
+
 /// Represents the transaction in the system
 #[derive(Debug, Clone, Copy)]
 pub enum Transaction {
@@ -49,12 +298,121 @@ pub enum Transaction {
     Dispute(TransactionData),
     Resolve(TransactionData),
     Chargeback(TransactionData),
+    Convert(ConversionData),
+    Transfer(TransferData),
+    Authorize(TransactionDataAmount),
+    Capture(CaptureData),
+    Void(TransactionData),
+    /// Cancels an earlier deposit or withdrawal
+    Reversal(TransactionData),
+}
+
+impl Transaction {
+    /// Returns id of the client this transaction concerns
+    pub fn client(&self) -> ClientId {
+        match self {
+            Transaction::Deposit(data)
+            | Transaction::Withdrawal(data)
+            | Transaction::Authorize(data) => *data.client(),
+            Transaction::Dispute(data)
+            | Transaction::Resolve(data)
+            | Transaction::Chargeback(data)
+            | Transaction::Void(data)
+            | Transaction::Reversal(data) => *data.client(),
+            Transaction::Convert(data) => *data.client(),
+            Transaction::Transfer(data) => *data.client(),
+            Transaction::Capture(data) => *data.client(),
+        }
+    }
+
+    /// Returns id of this transaction, or of the transaction it references
+    pub fn tx(&self) -> TxId {
+        match self {
+            Transaction::Deposit(data)
+            | Transaction::Withdrawal(data)
+            | Transaction::Authorize(data) => *data.tx(),
+            Transaction::Dispute(data)
+            | Transaction::Resolve(data)
+            | Transaction::Chargeback(data)
+            | Transaction::Void(data)
+            | Transaction::Reversal(data) => *data.tx(),
+            Transaction::Convert(data) => *data.tx(),
+            Transaction::Transfer(data) => *data.tx(),
+            Transaction::Capture(data) => *data.tx(),
+        }
+    }
+
+    /// Returns whether this transaction has an id of its own, rather than referencing an earlier transaction
+    pub fn creates_tx(&self) -> bool {
+        matches!(
+            self,
+            Transaction::Deposit(_)
+                | Transaction::Withdrawal(_)
+                | Transaction::Convert(_)
+                | Transaction::Transfer(_)
+                | Transaction::Authorize(_)
+        )
+    }
+
+    /// Returns the type of this transaction, as given in the input
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transaction::Deposit(_) => "deposit",
+            Transaction::Withdrawal(_) => "withdrawal",
+            Transaction::Dispute(_) => "dispute",
+            Transaction::Resolve(_) => "resolve",
+            Transaction::Chargeback(_) => "chargeback",
+            Transaction::Convert(_) => "convert",
+            Transaction::Transfer(_) => "transfer",
+            Transaction::Authorize(_) => "authorize",
+            Transaction::Capture(_) => "capture",
+            Transaction::Void(_) => "void",
+            Transaction::Reversal(_) => "reversal",
+        }
+    }
+
+    /// Returns the timestamp of this transaction, if known
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        match self {
+            Transaction::Deposit(data)
+            | Transaction::Withdrawal(data)
+            | Transaction::Authorize(data) => *data.timestamp(),
+            Transaction::Dispute(data)
+            | Transaction::Resolve(data)
+            | Transaction::Chargeback(data)
+            | Transaction::Void(data)
+            | Transaction::Reversal(data) => *data.timestamp(),
+            Transaction::Convert(data) => *data.timestamp(),
+            Transaction::Transfer(data) => *data.timestamp(),
+            Transaction::Capture(data) => *data.timestamp(),
+        }
+    }
 }
 
 impl TryFrom<&InputRecord> for Transaction {
     type Error = DeserializationError;
 
+    #[tracing::instrument(
+        name = "convert_record",
+        level = "trace",
+        skip_all,
+        fields(client = value.client(), tx = value.tx(), kind = %value.r#type())
+    )]
     fn try_from(value: &InputRecord) -> Result<Self, Self::Error> {
+        let timestamp = value
+            .timestamp()
+            .as_deref()
+            .map(parse_timestamp)
+            .transpose()?;
+        let currency = match value.currency().as_deref() {
+            Some(c) if !c.is_empty() => c.parse()?,
+            _ => Currency::NONE,
+        };
+        let account = match value.account().as_deref() {
+            Some(a) if !a.is_empty() => a.parse()?,
+            _ => Account::MAIN,
+        };
+
         match value.r#type().as_str() {
             "deposit" => {
                 let data = TransactionDataAmount::new(
@@ -63,7 +421,10 @@ impl TryFrom<&InputRecord> for Transaction {
                     value.amount().ok_or_else(|| {
                         DeserializationError::AmountMissing(value.r#type().to_owned())
                     })?,
-                )?;
+                )?
+                .with_currency(currency)
+                .with_account(account)
+                .with_timestamp(timestamp);
 
                 Ok(Transaction::Deposit(data))
             }
@@ -74,25 +435,115 @@ impl TryFrom<&InputRecord> for Transaction {
                     value.amount().ok_or_else(|| {
                         DeserializationError::AmountMissing(value.r#type().to_owned())
                     })?,
-                )?;
+                )?
+                .with_currency(currency)
+                .with_account(account)
+                .with_timestamp(timestamp);
 
                 Ok(Transaction::Withdrawal(data))
             }
+            "authorize" => {
+                let data = TransactionDataAmount::new(
+                    value.client().to_owned(),
+                    value.tx().to_owned(),
+                    value.amount().ok_or_else(|| {
+                        DeserializationError::AmountMissing(value.r#type().to_owned())
+                    })?,
+                )?
+                .with_currency(currency)
+                .with_account(account)
+                .with_timestamp(timestamp);
+
+                Ok(Transaction::Authorize(data))
+            }
+            "capture" => {
+                let data = CaptureData::new(
+                    value.client().to_owned(),
+                    value.tx().to_owned(),
+                    value.amount().to_owned(),
+                )?
+                .with_timestamp(timestamp);
+
+                Ok(Transaction::Capture(data))
+            }
+            "void" => {
+                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned())
+                    .with_timestamp(timestamp);
+
+                Ok(Transaction::Void(data))
+            }
+            "reversal" => {
+                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned())
+                    .with_timestamp(timestamp);
+
+                Ok(Transaction::Reversal(data))
+            }
             "dispute" => {
-                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned());
+                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned())
+                    .with_timestamp(timestamp);
 
                 Ok(Transaction::Dispute(data))
             }
             "resolve" => {
-                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned());
+                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned())
+                    .with_timestamp(timestamp);
 
                 Ok(Transaction::Resolve(data))
             }
             "chargeback" => {
-                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned());
+                let data = TransactionData::new(value.client().to_owned(), value.tx().to_owned())
+                    .with_timestamp(timestamp);
 
                 Ok(Transaction::Chargeback(data))
             }
+            "convert" => {
+                let amount = value.amount().ok_or_else(|| {
+                    DeserializationError::AmountMissing(value.r#type().to_owned())
+                })?;
+                let to = match value.to_currency().as_deref() {
+                    Some(c) if !c.is_empty() => c.parse()?,
+                    _ => {
+                        return Err(DeserializationError::TargetCurrencyMissing(
+                            value.r#type().to_owned(),
+                        ))
+                    }
+                };
+                let data = ConversionData::new(
+                    value.client().to_owned(),
+                    value.tx().to_owned(),
+                    amount,
+                    currency,
+                    to,
+                )?
+                .with_account(account)
+                .with_timestamp(timestamp);
+
+                Ok(Transaction::Convert(data))
+            }
+            "transfer" => {
+                let amount = value.amount().ok_or_else(|| {
+                    DeserializationError::AmountMissing(value.r#type().to_owned())
+                })?;
+                let to = match value.to_account().as_deref() {
+                    Some(a) if !a.is_empty() => a.parse()?,
+                    _ => {
+                        return Err(DeserializationError::TargetAccountMissing(
+                            value.r#type().to_owned(),
+                        ))
+                    }
+                };
+                let data = TransferData::new(
+                    value.client().to_owned(),
+                    value.tx().to_owned(),
+                    amount,
+                    account,
+                    to,
+                )?
+                .with_currency(currency)
+                .with_timestamp(timestamp);
+
+                Ok(Transaction::Transfer(data))
+            }
             _ => Err(DeserializationError::UnknownTransactionType(
                 value.r#type().to_owned(),
             )),
@@ -110,7 +561,7 @@ impl TryFrom<InputRecord> for Transaction {
 
 #[cfg(test)]
 mod tests {
-    use super::Transaction;
+    use super::{ClientId, Transaction, TxId};
     use crate::{dto::InputRecord, errors::DeserializationError};
     use paste::paste;
     use quickcheck::Arbitrary;
@@ -135,8 +586,8 @@ mod tests {
 
             InputRecord::new(
                 chosen_type.to_owned(),
-                u16::arbitrary(g),
-                u32::arbitrary(g),
+                ClientId::arbitrary(g),
+                TxId::arbitrary(g),
                 Some(amount),
             )
         }
@@ -159,6 +610,12 @@ mod tests {
             Transaction::Dispute(data) => record_equals_data!(r, data),
             Transaction::Resolve(data) => record_equals_data!(r, data),
             Transaction::Chargeback(data) => record_equals_data!(r, data),
+            Transaction::Convert(data) => record_equals_data!(r, data),
+            Transaction::Transfer(data) => record_equals_data!(r, data),
+            Transaction::Authorize(data) => record_equals_data!(r, data),
+            Transaction::Capture(data) => record_equals_data!(r, data),
+            Transaction::Void(data) => record_equals_data!(r, data),
+            Transaction::Reversal(data) => record_equals_data!(r, data),
         }
     }
 
@@ -181,6 +638,9 @@ mod tests {
 
     test_needs_amount!("deposit");
     test_needs_amount!("withdrawal");
+    test_needs_amount!("convert");
+    test_needs_amount!("transfer");
+    test_needs_amount!("authorize");
 
     macro_rules! test_amount_validation {
         ($name:ident, $value:expr) => {
@@ -199,4 +659,157 @@ mod tests {
     test_amount_validation!(NaN, f64::NAN);
     test_amount_validation!(minusOne, -1f64);
     test_amount_validation!(infinity, f64::INFINITY);
+
+    #[test]
+    fn timestamp_is_parsed_from_rfc3339_and_epoch() {
+        let rfc = InputRecord::new("deposit", 1, 1, Some(1.0))
+            .with_timestamp("2022-09-01T12:00:00+02:00");
+        let epoch = InputRecord::new("deposit", 1, 2, Some(1.0)).with_timestamp("1662026400");
+
+        let rfc: Transaction = rfc.try_into().unwrap();
+        let epoch: Transaction = epoch.try_into().unwrap();
+
+        assert!(rfc.timestamp().is_some());
+        assert_eq!(rfc.timestamp(), epoch.timestamp());
+    }
+
+    #[test]
+    fn invalid_timestamp_results_in_error() {
+        let record = InputRecord::new("dispute", 1, 1, None).with_timestamp("yesterday");
+
+        let result: Result<Transaction, _> = record.try_into();
+
+        assert_eq!(
+            result.unwrap_err(),
+            DeserializationError::InvalidTimestamp("yesterday".to_owned())
+        );
+    }
+
+    #[test]
+    fn currency_is_parsed_case_insensitively() {
+        let record = InputRecord::new("withdrawal", 1, 1, Some(1.0)).with_currency("eur");
+
+        let tr: Transaction = record.try_into().unwrap();
+
+        match tr {
+            Transaction::Withdrawal(data) => assert_eq!(data.currency().to_string(), "EUR"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn invalid_currency_results_in_error() {
+        let record = InputRecord::new("deposit", 1, 1, Some(1.0)).with_currency("EURO");
+
+        let result: Result<Transaction, _> = record.try_into();
+
+        assert_eq!(
+            result.unwrap_err(),
+            DeserializationError::InvalidCurrency("EURO".to_owned())
+        );
+    }
+
+    #[test]
+    fn convert_is_parsed() {
+        let record = InputRecord::new("convert", 1, 1, Some(2.0))
+            .with_currency("EUR")
+            .with_to_currency("usd");
+
+        let tr: Transaction = record.try_into().unwrap();
+
+        match tr {
+            Transaction::Convert(data) => {
+                assert_eq!(data.from().to_string(), "EUR");
+                assert_eq!(data.to().to_string(), "USD");
+                assert_eq!(*data.amount(), 2.0);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn convert_needs_target_currency() {
+        let record = InputRecord::new("convert", 1, 1, Some(1.0)).with_currency("EUR");
+
+        let result: Result<Transaction, _> = record.try_into();
+
+        assert_eq!(
+            result.unwrap_err(),
+            DeserializationError::TargetCurrencyMissing("convert".to_owned())
+        );
+    }
+
+    #[test]
+    fn convert_to_same_currency_results_in_error() {
+        let record = InputRecord::new("convert", 1, 1, Some(1.0))
+            .with_currency("EUR")
+            .with_to_currency("eur");
+
+        let result: Result<Transaction, _> = record.try_into();
+
+        assert_eq!(
+            result.unwrap_err(),
+            DeserializationError::SameCurrency("EUR".to_owned())
+        );
+    }
+
+    #[test]
+    fn transfer_is_parsed() {
+        let record = InputRecord::new("transfer", 1, 1, Some(2.0))
+            .with_account("savings")
+            .with_to_account("escrow");
+
+        let tr: Transaction = record.try_into().unwrap();
+
+        match tr {
+            Transaction::Transfer(data) => {
+                assert_eq!(data.from().to_string(), "savings");
+                assert_eq!(data.to().to_string(), "escrow");
+                assert!(data.currency().is_none());
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn transfer_needs_target_account() {
+        let record = InputRecord::new("transfer", 1, 1, Some(1.0));
+
+        let result: Result<Transaction, _> = record.try_into();
+
+        assert_eq!(
+            result.unwrap_err(),
+            DeserializationError::TargetAccountMissing("transfer".to_owned())
+        );
+    }
+
+    #[test]
+    fn transfer_to_same_account_results_in_error() {
+        let record = InputRecord::new("transfer", 1, 1, Some(1.0))
+            .with_account("savings")
+            .with_to_account("SAVINGS");
+
+        let result: Result<Transaction, _> = record.try_into();
+
+        assert_eq!(
+            result.unwrap_err(),
+            DeserializationError::SameAccount("savings".to_owned())
+        );
+    }
+
+    #[test]
+    fn capture_amount_is_optional() {
+        let full: Transaction = InputRecord::new("capture", 1, 1, None).try_into().unwrap();
+        let partial: Transaction = InputRecord::new("capture", 1, 1, Some(0.5))
+            .try_into()
+            .unwrap();
+
+        match (full, partial) {
+            (Transaction::Capture(full), Transaction::Capture(partial)) => {
+                assert_eq!(*full.amount(), None);
+                assert_eq!(*partial.amount(), Some(0.5));
+            }
+            _ => unreachable!(),
+        }
+    }
 }
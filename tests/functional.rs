@@ -13,6 +13,15 @@ macro_rules! count_tts {
     ($($tts:tt)*) => {0usize $(+ replace_expr!($tts 1usize))*};
 }
 
+/// Returns a path in the temporary directory unique to this test process
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "toy-payments-engine-{}-{}",
+        std::process::id(),
+        name
+    ))
+}
+
 macro_rules! test_output {
     ($name:ident, $($line:expr),*) => {
         #[test]
@@ -24,6 +33,10 @@ macro_rules! test_output {
                 .run()?;
             let mut cmd = bin.command();
             let name = stringify!($name);
+            let config = format!("tests/data/{}.toml", name);
+            if std::path::Path::new(&config).exists() {
+                cmd.arg("--config").arg(config);
+            }
             cmd.arg(format!("tests/data/{}.csv", name));
             let n = count_tts!($($line)*);
             cmd.assert().success().stdout(
@@ -74,3 +87,505 @@ test_output!(
     "1,2.3702,0.0000,2.3702,false",
     "2,2.2345,0.0000,2.2345,false"
 );
+
+test_output!(
+    timestamps,
+    "1,1.5000,0.0000,1.5000,false",
+    "2,2.0000,0.0000,2.0000,false"
+);
+
+test_output!(
+    dispute_expiry,
+    "1,2.0000,0.0000,2.0000,false",
+    "2,0.0000,0.0000,0.0000,true",
+    "3,0.0000,2.0000,2.0000,false"
+);
+
+test_output!(
+    currencies,
+    "1,1.0000,0.0000,1.0000,false,",
+    "1,1.5000,2.0000,3.5000,false,EUR",
+    "1,0.5000,0.0000,0.5000,false,USD",
+    "2,0.0000,0.0000,0.0000,true,EUR"
+);
+
+test_output!(
+    convert,
+    "1,49.0800,0.0000,49.0800,false,EUR",
+    "1,55.3300,0.0000,55.3300,false,USD"
+);
+
+test_output!(
+    accounts,
+    "1,1.0000,0.0000,1.0000,true,escrow",
+    "1,6.0000,0.0000,6.0000,false,main",
+    "1,2.0000,0.0000,2.0000,false,savings",
+    "2,1.0000,0.0000,1.0000,false,main"
+);
+
+test_output!(
+    wide_ids,
+    "70000,0.0000,2.0000,2.0000,false",
+    "18446744073709551615,1.0000,0.0000,1.0000,false"
+);
+
+test_output!(
+    overdraft,
+    "1,10.0000,0.0000,10.0000,false,0.0000,0.0000",
+    "2,-40.0000,0.0000,-40.0000,false,40.0000,60.0000"
+);
+
+test_output!(
+    authorizations,
+    "1,8.5000,0.0000,8.5000,false",
+    "2,0.0000,1.0000,1.0000,false"
+);
+
+test_output!(
+    reversal,
+    "1,5.0000,0.0000,5.0000,false",
+    "2,0.0000,2.0000,2.0000,false"
+);
+
+#[test]
+fn events() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let events_path = temp_path("events.csv");
+    let mut cmd = bin.command();
+    cmd.arg("--events")
+        .arg(&events_path)
+        .arg("tests/data/chargeback.csv");
+    cmd.assert().success();
+
+    let events = std::fs::read_to_string(&events_path)?;
+    std::fs::remove_file(&events_path)?;
+    assert_eq!(
+        events.lines().collect::<Vec<_>>(),
+        vec![
+            "event,client,account,tx,timestamp,currency,amount,note",
+            "funds_deposited,1,main,1,,,1.0000,",
+            "funds_deposited,2,main,2,,,2.0000,",
+            "funds_deposited,1,main,3,,,2.0000,",
+            "funds_withdrawn,1,main,4,,,1.5000,",
+            "funds_held,1,main,1,,,1.0000,",
+            "charged_back,1,main,1,,,1.0000,",
+            "account_locked,1,main,1,,,,",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn fx_report() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let report_path = temp_path("fx-report.csv");
+    let mut cmd = bin.command();
+    cmd.arg("--config")
+        .arg("tests/data/convert.toml")
+        .arg("--fx-report")
+        .arg(&report_path)
+        .arg("tests/data/convert.csv");
+    cmd.assert().success();
+
+    let report = std::fs::read_to_string(&report_path)?;
+    std::fs::remove_file(&report_path)?;
+    assert_eq!(
+        report.lines().collect::<Vec<_>>(),
+        vec!["currency,conversions,pnl", "EUR,1,0.0867", "USD,2,0.6700"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ledger() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let ledger_path = temp_path("ledger.csv");
+    let mut cmd = bin.command();
+    cmd.arg("--ledger")
+        .arg(&ledger_path)
+        .arg("tests/data/chargeback.csv");
+    cmd.assert().success();
+
+    let ledger = std::fs::read_to_string(&ledger_path)?;
+    std::fs::remove_file(&ledger_path)?;
+    assert_eq!(
+        ledger.lines().collect::<Vec<_>>(),
+        vec![
+            "entry,client,tx,timestamp,debit,credit,currency,amount",
+            "1,1,1,,cash,client:1:main:available,,1.0000",
+            "2,2,2,,cash,client:2:main:available,,2.0000",
+            "3,1,3,,cash,client:1:main:available,,2.0000",
+            "4,1,4,,client:1:main:available,cash,,1.5000",
+            "5,1,1,,client:1:main:available,client:1:main:held,,1.0000",
+            "6,1,1,,client:1:main:held,cash,,1.0000",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn metrics() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let metrics_path = temp_path("metrics.prom");
+    let mut cmd = bin.command();
+    cmd.arg("--summary")
+        .arg("--metrics")
+        .arg(&metrics_path)
+        .arg("tests/data/chargeback.csv");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("Processed 7 transactions in "))
+        .stderr(predicate::str::contains("withdrawal  1         1"))
+        .stderr(predicate::str::contains("Locked clients: 1"));
+
+    let metrics = std::fs::read_to_string(&metrics_path)?;
+    std::fs::remove_file(&metrics_path)?;
+    assert!(
+        metrics.contains("payments_transactions_total{type=\"deposit\",outcome=\"accepted\"} 3\n")
+    );
+    assert!(metrics
+        .contains("payments_rejections_total{error=\"insufficient_funds\",code=\"2001\"} 1\n"));
+    assert!(metrics
+        .contains("payments_funds_total{movement=\"charged_back\",currency=\"default\"} 1\n"));
+    assert!(metrics.contains("payments_locked_clients 1\n"));
+
+    Ok(())
+}
+
+#[test]
+fn statement() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("statement")
+        .arg("--client")
+        .arg("2")
+        .arg("tests/data/chargeback.csv");
+    let output = cmd.assert().success().get_output().stdout.clone();
+
+    assert_eq!(
+        String::from_utf8(output)?.lines().collect::<Vec<_>>(),
+        vec![
+            "type,tx,timestamp,amount,status,account,currency,available,held,total,error_code,error_name,error_category,error",
+            "deposit,2,,2.0000,applied,main,,2.0000,0.0000,2.0000,,,,",
+            "withdrawal,5,,3.0000,rejected,main,,2.0000,0.0000,2.0000,2001,insufficient_funds,business_rule,Withdrawal operation on client `2` would result in a negative amount",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn audit_log() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let audit_path = temp_path("audit.csv");
+    // the second run continues the chain of the first one
+    for _ in 0..2 {
+        let mut cmd = bin.command();
+        cmd.arg("--audit")
+            .arg(&audit_path)
+            .arg("tests/data/chargeback.csv");
+        cmd.assert().success();
+    }
+
+    let audit = std::fs::read_to_string(&audit_path)?;
+    let lines: Vec<_> = audit.lines().collect();
+    assert_eq!(lines.len(), 15);
+    assert_eq!(
+        lines[0],
+        "seq,action,client,tx,timestamp,amount,detail,prev_hash,hash"
+    );
+    assert!(lines[1].starts_with("1,run_started,,,,,input=tests/data/chargeback.csv config=,"));
+    assert!(lines[2].starts_with("2,deposit,1,1,,1.0000,,"));
+    assert!(lines[8].starts_with("8,run_started,"));
+
+    let mut cmd = bin.command();
+    cmd.arg("verify-audit").arg(&audit_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("14 entries, head `14:"));
+
+    std::fs::write(&audit_path, audit.replacen(",1.0000,", ",10.0000,", 1))?;
+    let mut cmd = bin.command();
+    cmd.arg("verify-audit").arg(&audit_path);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Audit log entry `2` was tampered with",
+    ));
+
+    // the recorded head reveals entries removed from the end, and the next run refuses to continue
+    std::fs::write(&audit_path, lines[..14].join("\n"))?;
+    let mut cmd = bin.command();
+    cmd.arg("verify-audit").arg(&audit_path);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Audit log ends at entry `13`, before its head `14`",
+    ));
+    let mut cmd = bin.command();
+    cmd.arg("--audit")
+        .arg(&audit_path)
+        .arg("tests/data/chargeback.csv");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("can not be continued"));
+
+    std::fs::remove_file(&audit_path)?;
+    std::fs::remove_file(format!("{}.head", audit_path.display()))?;
+
+    Ok(())
+}
+
+#[test]
+fn reconcile() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("reconcile")
+        .arg("--expected")
+        .arg("tests/data/reconcile_breaks.csv")
+        .arg("tests/data/chargeback.csv");
+    let output = cmd
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Reconciliation found 4 breaks"))
+        .get_output()
+        .stdout
+        .clone();
+
+    assert_eq!(
+        String::from_utf8(output)?.lines().collect::<Vec<_>>(),
+        vec![
+            "client,account,currency,break,expected,actual",
+            "1,main,,locked,false,true",
+            "2,main,,available,2.0500,2.0000",
+            "2,main,,total,2.0500,2.0000",
+            "3,main,,missing,,",
+        ]
+    );
+
+    let mut cmd = bin.command();
+    cmd.arg("reconcile")
+        .arg("--expected")
+        .arg("tests/data/reconcile_tolerated.csv")
+        .arg("--tolerance")
+        .arg("0.01")
+        .arg("tests/data/chargeback.csv");
+    cmd.assert().success().stdout(predicate::str::is_empty());
+
+    let mut cmd = bin.command();
+    cmd.arg("reconcile")
+        .arg("--expected")
+        .arg("tests/data/reconcile_breaks.csv")
+        .arg("--tolerance")
+        .arg("0.01")
+        .arg("tests/data/chargeback.csv");
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("2,main,,available,2.0500,2.0000"));
+
+    Ok(())
+}
+
+#[test]
+fn diff() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("diff")
+        .arg("tests/data/diff_before.csv")
+        .arg("tests/data/diff_after.csv");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output)?;
+    let lines: Vec<_> = output.lines().collect();
+
+    assert_eq!(lines.len(), 12);
+    assert_eq!(
+        &lines[..4],
+        &[
+            "client,account,currency,change,field,before,after,delta",
+            "1,main,,changed,available,1.5000,0.5000,-1.0000",
+            "1,main,,changed,held,0.0000,1.0000,1.0000",
+            "1,main,,changed,locked,false,true,",
+        ]
+    );
+    assert!(output.contains("3,main,,removed,total,1.0000,,-1.0000"));
+    assert!(output.contains("4,main,,added,total,,3.0000,3.0000"));
+
+    let mut cmd = bin.command();
+    cmd.arg("diff")
+        .arg("--format")
+        .arg("table")
+        .arg("tests/data/diff_before.csv")
+        .arg("tests/data/diff_after.csv");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("1 added, 1 removed, 1 changed"));
+
+    let mut cmd = bin.command();
+    cmd.arg("diff")
+        .arg("--disputes")
+        .arg("tests/data/diff_disputes_before.csv")
+        .arg("tests/data/diff_disputes_after.csv");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output)?;
+    let lines: Vec<_> = output.lines().collect();
+
+    assert_eq!(lines.len(), 10);
+    assert_eq!(
+        &lines[..3],
+        &[
+            "client,tx,change,field,before,after,delta",
+            "1,2,changed,disputed_at,2022-09-01T00:00:00+00:00,2022-09-02T00:00:00+00:00,",
+            "2,3,removed,amount,3.0000,,-3.0000",
+        ]
+    );
+    assert!(output.contains("3,4,added,account,,savings,"));
+
+    Ok(())
+}
+
+#[test]
+fn simulate() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("simulate")
+        .arg("--batch")
+        .arg("tests/data/simulate_batch.csv")
+        .arg("tests/data/simple.csv");
+    let output = cmd
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "REJECTED [2001 insufficient_funds] withdrawal 7:",
+        ))
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output)?;
+
+    assert_eq!(output.lines().count(), 7);
+    assert!(output.contains("2,main,,changed,available,2.0000,3.0000,1.0000"));
+    assert!(output.contains("3,main,,added,total,,4.0000,4.0000"));
+    assert!(!output.contains("1,main,"));
+
+    let mut cmd = bin.command();
+    cmd.arg("simulate")
+        .arg("--batch")
+        .arg("tests/data/simulate_batch.csv")
+        .arg("--commit")
+        .arg("tests/data/simple.csv");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("1,1.5000,0.0000,1.5000,false"))
+        .stdout(predicate::str::contains("2,3.0000,0.0000,3.0000,false"))
+        .stdout(predicate::str::contains("3,4.0000,0.0000,4.0000,false"));
+
+    Ok(())
+}
+
+#[test]
+fn query() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("query")
+        .arg("--client")
+        .arg("1")
+        .arg("--at-tx")
+        .arg("3")
+        .arg("tests/data/dispute.csv");
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked\n\
+         1,3.0000,0.0000,3.0000,false\n",
+    );
+
+    let mut cmd = bin.command();
+    cmd.arg("query")
+        .arg("--disputes")
+        .arg("tests/data/dispute.csv");
+    cmd.assert().success().stdout(
+        "client,tx,amount,account,currency,disputed_at\n\
+         1,1,1.0,main,,\n",
+    );
+
+    let mut cmd = bin.command();
+    cmd.arg("query")
+        .arg("--at-tx")
+        .arg("99")
+        .arg("tests/data/dispute.csv");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "ERROR [9001 other]: Transaction `99` not found",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn opening_state() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let audit_path = temp_path("opening-audit.csv");
+    let mut cmd = bin.command();
+    cmd.arg("--opening-balances")
+        .arg("tests/data/opening_balances.csv")
+        .arg("--open-disputes")
+        .arg("tests/data/opening_disputes.csv")
+        .arg("--audit")
+        .arg(&audit_path)
+        .arg("tests/data/opening.csv");
+    cmd.assert()
+        .success()
+        .stdout(
+            predicate::str::contains("1,9.0000,0.0000,9.0000,false")
+                .and(predicate::str::contains("2,1.0000,0.0000,1.0000,true")),
+        )
+        .stderr(predicate::str::contains("Client ID `2` is locked"));
+
+    let audit = std::fs::read_to_string(&audit_path)?;
+    assert_eq!(audit.matches(",balance_opened,").count(), 2);
+    assert_eq!(audit.matches(",dispute_opened,").count(), 1);
+    std::fs::remove_file(&audit_path)?;
+    std::fs::remove_file(format!("{}.head", audit_path.display()))?;
+
+    Ok(())
+}
@@ -33,3 +33,93 @@ fn fails_on_two_arguments() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn statement_fails_without_client() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("statement").arg("tests/data/simple.csv");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("needs option `--client`"));
+
+    Ok(())
+}
+
+#[test]
+fn reports_errors_as_json() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("--error-format")
+        .arg("json")
+        .arg("tests/data/simple.csv");
+    cmd.assert().success().stderr(predicate::str::contains(
+        r#"{"code":2001,"name":"insufficient_funds","category":"business_rule","severity":"warning","#,
+    ));
+
+    let mut cmd = bin.command();
+    cmd.arg("--error-format")
+        .arg("json")
+        .arg("tests/data/missing.csv");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains(r#"{"code":9001,"name":"other","#));
+
+    let mut cmd = bin.command();
+    cmd.arg("tests/data/malformed.csv");
+    cmd.assert().failure().stderr(predicate::str::ends_with(
+        "ERROR [1014 malformed_record]: CSV deserialize error: record 1 (line: 2, byte: 22): field 1: invalid digit found in string\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn logs_rejections_as_json() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("--log-level")
+        .arg("info")
+        .arg("--log-format")
+        .arg("json")
+        .arg("tests/data/simple.csv");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains(
+            r#""message":"transaction rejected","outcome":"rejected","code":2001"#,
+        ))
+        .stderr(predicate::str::contains(
+            r#""span":{"client":2,"kind":"withdrawal","tx":5,"name":"register_transaction"}"#,
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn prints_usage_on_help() -> Result<(), Box<dyn std::error::Error>> {
+    let bin = escargot::CargoBuild::new()
+        .bin("toy-payments-engine")
+        .current_release()
+        .current_target()
+        .run()?;
+    let mut cmd = bin.command();
+    cmd.arg("--help");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("USAGE:"))
+        .stderr(predicate::str::contains("`query` replays the input"));
+
+    Ok(())
+}